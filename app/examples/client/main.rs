@@ -1,7 +1,16 @@
-use std::{env, error::Error, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    env,
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use chrono::Local;
-use flip_flop_app::{CommandRequest, EventReply};
+use flip_flop_app::{
+    classify_epoch_offset, CommandRequest, Datagram, DiscriminantSet, OffsetTransition, Reply,
+};
+use flip_flop_data::{DataFrame, DataSource, Header, ProtocolVersion, ServerAddress, ServerPort};
 use tokio::{
     net::UdpSocket,
     time::{self, Instant},
@@ -31,12 +40,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let s = r.clone();
 
     // This size should never exceed what can be sent in one packet. If you
-    // have needs that exceed this constraint then you will need to consider
-    // framing.
-    const MAX_DATAGRAM_SIZE: usize = 32;
+    // have needs that exceed this constraint then you will need to split
+    // payloads with `fragments` and reassemble them with a `Reassembler`.
+    const MAX_DATAGRAM_SIZE: usize = 64;
+    type DatagramBuf = Datagram<MAX_DATAGRAM_SIZE>;
+
+    // Any request fits within a datagram, as checked at compile time, and so
+    // encoding one cannot fail.
+    const _: () = assert!(DatagramBuf::fits::<CommandRequest<Command>>());
 
+    let mut last_epoch = 0;
     let mut last_event_offset = 0;
     let mut event_count = 0;
+    // Frame counters must keep advancing should the client restart, or the
+    // server will drop its frames as replays. As frames are sent every second,
+    // starting from the current time in seconds achieves this.
+    let mut frame_counter = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs() as u16;
 
     println!("CLIENT: listening on {:?}", local_addr);
 
@@ -53,7 +74,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         // Tell the server to do something and let it know what we know
         // of its state by communicating the last event offset we received
         // for it.
-        let mut send_buf = [0; MAX_DATAGRAM_SIZE];
+        let mut send_buf = DatagramBuf::new();
         let command = if init_mode {
             None
         } else {
@@ -61,22 +82,48 @@ async fn main() -> Result<(), Box<dyn Error>> {
         };
         let request = CommandRequest {
             last_event_offset,
+            subscriptions: DiscriminantSet::ALL,
+            acked_offset: 0,
+            acked_epoch: 0,
             command,
         };
-        if let Ok(encoded_buf) = postcard::to_slice(&request, &mut send_buf) {
-            let _ = s.send_to(encoded_buf, remote_addr).await;
-            println!("CLIENT: {:?} command sent to {:?}", request, remote_addr);
-        }
+        // The request is conveyed in a data frame so that the server can
+        // reject it should it be replayed. For brevity, this example does not
+        // seal its payloads; see `ServerEndpoint` for a server that does.
+        let header = Header {
+            version: ProtocolVersion::LATEST.get(),
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(0),
+            server_port: ServerPort::new_unchecked(0),
+            frame_counter,
+        };
+        let mut payload_buf = [0; DatagramBuf::MAX_PLAINTEXT_LEN];
+        let payload = request
+            .encode(ProtocolVersion::LATEST, &mut payload_buf)
+            .expect("request fits");
+        let len = DataFrame::new(&header, payload)
+            .to_bytes(&mut send_buf)
+            .expect("frame fits");
+        let _ = s.send_to(&send_buf[..len], remote_addr).await;
+        println!("CLIENT: {:?} command sent to {:?}", request, remote_addr);
+        frame_counter = frame_counter.wrapping_add(1);
 
         // Receive an event from the server. If we don't get anything within
         // a short timeout then we move on.
-        let mut recv_buf = [0; MAX_DATAGRAM_SIZE];
+        let mut recv_buf = DatagramBuf::new();
         if let Ok(Ok((len, remote_addr))) =
             time::timeout(Duration::from_millis(100), r.recv_from(&mut recv_buf)).await
         {
-            if let Ok(reply) = postcard::from_bytes::<EventReply<Event>>(&recv_buf[..len]) {
+            let reply = Reply::<Event>::decode(ProtocolVersion::LATEST, &recv_buf[..len]);
+            if let Ok(Reply::Alive { frame_counter }) = reply {
+                println!(
+                    "CLIENT: no new events, {:?} alive in reply to {}",
+                    remote_addr, frame_counter
+                );
+                init_mode = false;
+            } else if let Ok(Reply::Event(reply)) = reply {
                 if let Some(local_time) = Local::now().checked_sub_signed(
-                    chrono::Duration::from_std(Duration::from_secs(reply.delta_ticks))
+                    chrono::Duration::from_std(Duration::from_secs(reply.age.get().into()))
                         .unwrap_or(chrono::Duration::seconds(0)),
                 ) {
                     println!(
@@ -84,14 +131,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         local_time, reply, event_count, remote_addr
                     );
                 }
-                match reply.event {
+                // The server's epoch changes whenever it resets, which tells
+                // a reset apart from progress whatever the offset conveyed.
+                let transition = match reply.event {
+                    None if reply.epoch == last_epoch => None,
+                    None => Some((OffsetTransition::Reset, 0)),
+                    Some(_) => Some((
+                        classify_epoch_offset(
+                            (last_epoch, last_event_offset),
+                            (reply.epoch, reply.offset),
+                        ),
+                        reply.offset,
+                    )),
+                };
+                match transition {
                     None => init_mode = false,
-                    Some((_, offset)) if offset <= last_event_offset => {
+                    Some((OffsetTransition::Reset, offset)) => {
                         init_mode = true;
                         event_count = 0;
+                        last_epoch = reply.epoch;
                         last_event_offset = offset;
-                        println!("CLIENT: Previous events for this server are now forgotten given an offset <= what we know");
+                        println!("CLIENT: Previous events for this server are now forgotten given a new epoch");
                     }
+                    Some((OffsetTransition::Duplicate, _)) => (),
                     Some((_, offset)) => {
                         event_count += 1;
                         last_event_offset = offset;
@@ -1,3 +1,4 @@
+use flip_flop_app::{Discriminant, WireMessage};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -5,7 +6,27 @@ pub enum Command {
     SomeCommand,
 }
 
+impl Discriminant for Command {
+    fn discriminant(&self) -> u8 {
+        0
+    }
+}
+
+impl WireMessage for Command {
+    const MAX_ENCODED_LEN: usize = 1;
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Event {
     SomeEvent,
 }
+
+impl Discriminant for Event {
+    fn discriminant(&self) -> u8 {
+        0
+    }
+}
+
+impl WireMessage for Event {
+    const MAX_ENCODED_LEN: usize = 1;
+}
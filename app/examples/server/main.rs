@@ -1,13 +1,11 @@
-use circular_queue::CircularQueue;
 use rand::prelude::*;
 use std::{env, error::Error, net::SocketAddr, time::Duration};
 
-use flip_flop_app::CommandRequest;
-use tokio::{
-    net::UdpSocket,
-    sync::mpsc,
-    time::{self, Instant},
+use flip_flop_app::{
+    CommandRequest, Datagram, Outcome, ReplayGuard, Reply, RoleFilter, Server, TokioClock,
 };
+use flip_flop_data::DataFrame;
+use tokio::{net::UdpSocket, sync::mpsc, time};
 
 #[path = "../common/lib.rs"]
 mod common;
@@ -24,72 +22,117 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("SERVER: listening on {:?}", local_addr);
 
-    // Generate events in the background. We simple generate timestamp
-    // and send them to our main loop.
+    // Generate events in the background. We simply signal our main loop
+    // to record an event at random intervals.
 
-    let (event_s, mut event_r) = mpsc::channel::<Instant>(100);
+    let (event_s, mut event_r) = mpsc::channel::<()>(100);
 
     tokio::spawn(async move {
         loop {
-            let event_time = Instant::now();
-            let delay = { Duration::from_secs(rand::thread_rng().gen_range(0..3)) };
-            if let Some(instant) = event_time.checked_add(delay) {
-                time::sleep_until(instant).await;
-                let _ = event_s.send(event_time).await;
-            }
+            let delay = Duration::from_secs(rand::thread_rng().gen_range(0..3));
+            time::sleep(delay).await;
+            let _ = event_s.send(()).await;
         }
     });
 
     // This size should never exceed what can be sent in one packet. If you
-    // have needs that exceed this constraint then you will need to consider
-    // framing.
-    const MAX_DATAGRAM_SIZE: usize = 32;
+    // have needs that exceed this constraint then you will need to split
+    // payloads with `fragments` and reassemble them with a `Reassembler`.
+    const MAX_DATAGRAM_SIZE: usize = 64;
     const MAX_EVENTS: usize = 10;
+    type DatagramBuf = Datagram<MAX_DATAGRAM_SIZE>;
+
+    // Any reply fits within a datagram, as checked at compile time, and so
+    // encoding one cannot fail.
+    const _: () = assert!(DatagramBuf::fits::<Reply<Event>>());
 
-    let mut recv_buf = [0; MAX_DATAGRAM_SIZE];
-    let mut events = CircularQueue::<(Event, u32, Instant)>::with_capacity(MAX_EVENTS);
-    let mut event_offset = 0;
+    let mut recv_buf = DatagramBuf::new();
+    // Ticks are the seconds since the server started.
+    let mut server = Server::<Event, _, MAX_EVENTS>::new(TokioClock::new());
+    let mut replay_guard = ReplayGuard::new();
+    let role_filter = RoleFilter::server();
 
     loop {
         tokio::select! {
             Ok((len, remote_addr)) = socket.recv_from(&mut recv_buf) => {
-                if let Ok(request) = postcard::from_bytes::<CommandRequest<Command>>(&recv_buf[..len]) {
+                // Frames sourced by servers, including our own should they be
+                // echoed back, are ignored.
+                if !role_filter.should_process(&recv_buf[..len]) {
+                    continue;
+                }
+
+                // Outcomes are counted for observing the server, and printed
+                // with each reply.
+                server.stats_mut().record(Outcome::Received);
+                let (header, payload) = match DataFrame::from_bytes(&recv_buf[..len]).and_then(|f| f.parse()) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        server.stats_mut().record(Outcome::ParseFailed);
+                        println!("SERVER: dropping frame from {:?}: {}", remote_addr, e);
+                        continue;
+                    }
+                };
+                // WARNING: do not copy this replay check. For brevity, this
+                // example's frames are not sealed, and so the guard advances on
+                // frames that have not been authenticated. A single forged
+                // frame with a counter just under half of the counter's range
+                // ahead of the client's would then cause the server to reject
+                // the client's frames until its counter catches up. A server
+                // must only advance its guard once a frame has been opened, as
+                // `ServerEndpoint::process` does with the `endpoint` feature.
+                if !replay_guard.check_and_update(header.source, header.server_address, header.frame_counter) {
+                    server.stats_mut().record(Outcome::Replayed);
                     println!(
-                        "SERVER: {:?} command received from {:?}. Replying.",
-                        request, remote_addr
+                        "SERVER: dropping replayed frame {} from {:?}",
+                        header.frame_counter, remote_addr
                     );
-
-                    // We optimise searching for events by going backward in our
-                    // circular buffer until we find the latest event where its
-                    // offset exceeds the last one observed by the client. In the
-                    // case where we have no last offset expressed by the client
-                    // then we provide the oldest one we have.
-                    let maybe_reply = events
-                            .iter()
-                            .take_while(|(_, offset, _)| *offset > request.last_event_offset)
-                            .last().or_else(|| events.iter().next());
-
-                    let reply = flip_flop_app::event_reply(maybe_reply, |t|Instant::now().duration_since(t).as_secs());
-
-                    let mut send_buf = [0; MAX_DATAGRAM_SIZE];
-                    if let Ok(encoded_buf) = postcard::to_slice(&reply, &mut send_buf) {
+                    continue;
+                }
+                // Payloads are laid out as the version of their frame does, as
+                // is the reply.
+                let version = match header.protocol_version() {
+                    Ok(version) => version,
+                    Err(e) => {
+                        server.stats_mut().record(Outcome::DecodeFailed);
+                        println!("SERVER: dropping request from {:?}: {}", remote_addr, e);
+                        continue;
+                    }
+                };
+                match CommandRequest::<Command>::decode(version, payload) {
+                    Ok(request) => {
+                        println!(
+                            "SERVER: {:?} command received from {:?}. Replying.",
+                            request, remote_addr
+                        );
+
+                        // The server replies with the next event that the client has
+                        // yet to receive, or the oldest one it has should the client
+                        // be ahead of it. With no such event, it replies that it is
+                        // alive.
+                        let reply = server.handle_request_with_keepalive(header.frame_counter, request);
+
+                        let mut send_buf = DatagramBuf::new();
+                        let encoded_buf = reply.encode(version, &mut send_buf).expect("reply fits");
                         let _ = socket.send_to(encoded_buf, remote_addr).await;
                         println!("SERVER: {:?} event replied to {:?}", reply, remote_addr);
+                        println!("SERVER: {:?}", server.stats());
+                    }
+                    Err(e) => {
+                        server.stats_mut().record(Outcome::DecodeFailed);
+                        println!("SERVER: dropping request from {:?}: {}", remote_addr, e);
                     }
                 }
             }
 
-            Some(event_instant) = event_r.recv() => {
-                events.push((Event::SomeEvent, event_offset, event_instant));
+            Some(()) = event_r.recv() => {
+                server.push_event(Event::SomeEvent);
 
                 // For this example, we will reset the event offset periodically
-                // so that a client can demonstrate how it forgets state.
+                // so that a client can demonstrate how it forgets state. Replies
+                // then convey a new epoch, from which the client detects this.
                 if rand::thread_rng().gen_range(0..10) == 0 {
                     println!("SERVER: Resetting events");
-                    events.clear();
-                    event_offset = 0;
-                } else {
-                    event_offset += 1;
+                    server.reset();
                 }
             }
         }
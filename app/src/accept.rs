@@ -0,0 +1,288 @@
+use flip_flop_data::{
+    DataFrame, DataSource, Header, ParseError, ServerAddress, ServerPort, MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+
+use crate::ReplayWindow;
+
+/// The policies that a receiver applies in deciding whether to accept a
+/// data frame. Configurations are constructed with [AcceptConfig::builder],
+/// which defaults to the most secure choice for each policy.
+///
+/// There is no policy for the reserved bits of the header: they are always
+/// required to be clear, as [DataFrame::parse] enforces. The flags that occupy
+/// them mark the opt-in plaintext, checksummed and compressed frames, each of
+/// which is opened by its own function that checks its flag, so a frame with
+/// any of them set is never one that this decision should accept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcceptConfig {
+    source: DataSource,
+    server_address: Option<ServerAddress>,
+    server_port: Option<ServerPort>,
+    replay_protection: bool,
+    max_payload_len: usize,
+    exact_length: bool,
+}
+
+impl AcceptConfig {
+    /// Begin building a configuration for a receiver that expects frames from
+    /// a given source i.e. a server expects frames sourced by the client.
+    pub fn builder(source: DataSource) -> AcceptConfigBuilder {
+        AcceptConfigBuilder {
+            config: AcceptConfig {
+                source,
+                server_address: None,
+                server_port: None,
+                replay_protection: true,
+                max_payload_len: MAX_ENCRYPTED_PAYLOAD_LEN,
+                exact_length: true,
+            },
+        }
+    }
+}
+
+/// Builds an [AcceptConfig].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcceptConfigBuilder {
+    config: AcceptConfig,
+}
+
+impl AcceptConfigBuilder {
+    /// Accept only frames for a given server address, or for
+    /// [flip_flop_data::BROADCAST_ADDRESS]. By default, frames for any server
+    /// address are accepted, as is required by the client.
+    pub fn server_address(mut self, server_address: ServerAddress) -> Self {
+        self.config.server_address = Some(server_address);
+        self
+    }
+
+    /// Accept only frames for a given server port. By default, frames for any
+    /// server port are accepted.
+    pub fn server_port(mut self, server_port: ServerPort) -> Self {
+        self.config.server_port = Some(server_port);
+        self
+    }
+
+    /// Whether frames with a frame counter that is not newer than the last one
+    /// accepted are rejected. Replay protection is on by default.
+    pub fn replay_protection(mut self, replay_protection: bool) -> Self {
+        self.config.replay_protection = replay_protection;
+        self
+    }
+
+    /// Reject frames with an encrypted payload longer than a given length. By
+    /// default, this is the greatest length permitted by the protocol, and a
+    /// greater length has no effect.
+    pub fn max_payload_len(mut self, max_payload_len: usize) -> Self {
+        self.config.max_payload_len = max_payload_len.min(MAX_ENCRYPTED_PAYLOAD_LEN);
+        self
+    }
+
+    /// Whether datagrams with bytes beyond the end of their frame are rejected
+    /// by [accept_datagram]. Exact lengths are required by default; relax this
+    /// only for a transport that pads its datagrams.
+    pub fn exact_length(mut self, exact_length: bool) -> Self {
+        self.config.exact_length = exact_length;
+        self
+    }
+
+    /// Produce the configuration.
+    pub fn build(self) -> AcceptConfig {
+        self.config
+    }
+}
+
+/// The reasons that a data frame may not be accepted.
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    /// The frame could not be parsed.
+    Parse(ParseError),
+    /// The frame was not from the expected source.
+    UnexpectedSource,
+    /// The frame was for another server address, and not a broadcast.
+    UnexpectedServerAddress,
+    /// The frame was for another server port.
+    UnexpectedServerPort,
+    /// The frame's encrypted payload was longer than permitted.
+    PayloadTooLong,
+    /// The frame's counter was not newer than the last one accepted.
+    Replayed,
+    /// The datagram held bytes beyond the end of its frame.
+    TrailingBytes,
+}
+
+/// Decide whether to accept a datagram holding a single data frame, as
+/// [accept_frame] does, having first required its length to be exactly that
+/// of the frame unless the configuration relaxes this.
+pub fn accept_datagram<'a>(
+    datagram: &'a [u8],
+    config: &AcceptConfig,
+    replay_window: &mut ReplayWindow,
+) -> Result<(Header, &'a [u8]), Rejection> {
+    let frame = DataFrame::from_bytes(datagram).map_err(Rejection::Parse)?;
+    check_length(&frame, datagram.len(), config)?;
+    accept_frame(&frame, config, replay_window)
+}
+
+pub(crate) fn check_length(
+    frame: &DataFrame,
+    datagram_len: usize,
+    config: &AcceptConfig,
+) -> Result<(), Rejection> {
+    if config.exact_length && datagram_len != frame.encoded_len() {
+        return Err(Rejection::TrailingBytes);
+    }
+    Ok(())
+}
+
+/// Decide whether to accept a data frame given a receiver's configuration,
+/// returning its header and encrypted payload if so. When replay protection
+/// is enabled, the replay window of the frame's sender is advanced on
+/// acceptance.
+pub fn accept_frame<'a>(
+    frame: &DataFrame<'a>,
+    config: &AcceptConfig,
+    replay_window: &mut ReplayWindow,
+) -> Result<(Header, &'a [u8]), Rejection> {
+    let (header, encrypted_payload) = frame.parse().map_err(Rejection::Parse)?;
+    if header.source != config.source {
+        return Err(Rejection::UnexpectedSource);
+    }
+    if !header.is_broadcast()
+        && matches!(config.server_address, Some(a) if a != header.server_address)
+    {
+        return Err(Rejection::UnexpectedServerAddress);
+    }
+    if matches!(config.server_port, Some(p) if p != header.server_port) {
+        return Err(Rejection::UnexpectedServerPort);
+    }
+    if encrypted_payload.len() > config.max_payload_len {
+        return Err(Rejection::PayloadTooLong);
+    }
+    if config.replay_protection && !replay_window.accept(header.frame_counter) {
+        return Err(Rejection::Replayed);
+    }
+    Ok((header, encrypted_payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flip_flop_data::BROADCAST_ADDRESS;
+
+    fn header(server_address: u8, frame_counter: u16) -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(server_address),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter,
+        }
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = AcceptConfig::builder(DataSource::Client).build();
+        let mut window = ReplayWindow::new();
+
+        let payload = [0; 127];
+        let header = header(31, 1);
+        let frame = DataFrame::new(&header, &payload);
+        assert_eq!(
+            accept_frame(&frame, &config, &mut window),
+            Ok((header, &payload[..]))
+        );
+        assert_eq!(
+            accept_frame(&frame, &config, &mut window),
+            Err(Rejection::Replayed)
+        );
+
+        let config = AcceptConfig::builder(DataSource::Server).build();
+        assert_eq!(
+            accept_frame(&frame, &config, &mut window),
+            Err(Rejection::UnexpectedSource)
+        );
+    }
+
+    #[test]
+    fn test_non_default_config() {
+        let config = AcceptConfig::builder(DataSource::Client)
+            .server_address(ServerAddress::new_unchecked(30))
+            .server_port(ServerPort::new_unchecked(2))
+            .replay_protection(false)
+            .max_payload_len(4)
+            .build();
+        let mut window = ReplayWindow::new();
+
+        let payload = [0; 4];
+        let frame_header = header(30, 1);
+        let frame = DataFrame::new(&frame_header, &payload);
+        assert!(accept_frame(&frame, &config, &mut window).is_ok());
+        assert!(accept_frame(&frame, &config, &mut window).is_ok());
+
+        let frame_header = header(29, 2);
+        let frame = DataFrame::new(&frame_header, &payload);
+        assert_eq!(
+            accept_frame(&frame, &config, &mut window),
+            Err(Rejection::UnexpectedServerAddress)
+        );
+
+        let frame_header = header(30, 3);
+        let payload = [0; 5];
+        let frame = DataFrame::new(&frame_header, &payload);
+        assert_eq!(
+            accept_frame(&frame, &config, &mut window),
+            Err(Rejection::PayloadTooLong)
+        );
+    }
+
+    #[test]
+    fn test_exact_length() {
+        let header = header(31, 1);
+        let frame = DataFrame::new(&header, &[0; 4]);
+        let mut datagram = [0; 64];
+        let len = frame.to_bytes(&mut datagram).unwrap();
+
+        let config = AcceptConfig::builder(DataSource::Client)
+            .replay_protection(false)
+            .build();
+        let mut window = ReplayWindow::new();
+        assert!(accept_datagram(&datagram[..len], &config, &mut window).is_ok());
+        assert_eq!(
+            accept_datagram(&datagram[..len + 1], &config, &mut window),
+            Err(Rejection::TrailingBytes)
+        );
+
+        let config = AcceptConfig::builder(DataSource::Client)
+            .replay_protection(false)
+            .exact_length(false)
+            .build();
+        assert!(accept_datagram(&datagram[..len + 1], &config, &mut window).is_ok());
+    }
+
+    #[test]
+    fn test_broadcast_reaches_all() {
+        let mut window = ReplayWindow::new();
+        let configs = [1, 2].map(|a| {
+            AcceptConfig::builder(DataSource::Client)
+                .server_address(ServerAddress::new_unchecked(a))
+                .replay_protection(false)
+                .build()
+        });
+
+        // A unicast frame reaches only its addressee...
+        let frame_header = header(1, 1);
+        let frame = DataFrame::new(&frame_header, &[]);
+        assert!(accept_frame(&frame, &configs[0], &mut window).is_ok());
+        assert_eq!(
+            accept_frame(&frame, &configs[1], &mut window),
+            Err(Rejection::UnexpectedServerAddress)
+        );
+
+        // ...whereas a broadcast reaches all.
+        let frame_header = header(BROADCAST_ADDRESS.get(), 2);
+        let frame = DataFrame::new(&frame_header, &[]);
+        for config in &configs {
+            assert!(accept_frame(&frame, config, &mut window).is_ok());
+        }
+    }
+}
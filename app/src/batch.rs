@@ -0,0 +1,157 @@
+use heapless::Vec;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{WireMessage, BATCH_ENTRY_OVERHEAD};
+
+/// A reply conveying several consecutive events at once, so that a client that
+/// has fallen behind can catch up in one exchange rather than one per event.
+/// Each event is conveyed with its offset and age in delta ticks, as per
+/// [crate::EventReply], and in ascending order of offset. At most `N` events
+/// are conveyed, with `N` being less than 128.
+///
+/// A BatchReply has the following little endian byte layout, being a count of
+/// entries followed by the entries themselves:
+///
+/// |   0   |  ..   | 0 | 1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 | 9 | A | B |  ..  |
+/// +-------+-------+---+---+---+---+---+---+---+---+---+---+---+---+------+
+/// | count | event |     offset    |          delta_ticks          |  ..  |
+///
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(bound = "")]
+pub struct BatchReply<E: DeserializeOwned + Serialize, const N: usize> {
+    /// The events along with their offsets and delta ticks, oldest first. An
+    /// empty batch means that there are no more events.
+    pub events: Vec<(E, u32, u64), N>,
+}
+
+impl<E: DeserializeOwned + Serialize, const N: usize> BatchReply<E, N> {
+    /// The offset of the last event conveyed, being what a client should
+    /// record as having received, if any.
+    pub fn last_event_offset(&self) -> Option<u32> {
+        self.events.last().map(|(_, o, _)| *o)
+    }
+}
+
+impl<E, const N: usize> WireMessage for BatchReply<E, N>
+where
+    E: DeserializeOwned + Serialize + WireMessage,
+{
+    const MAX_ENCODED_LEN: usize = 1 + N * (E::MAX_ENCODED_LEN + BATCH_ENTRY_OVERHEAD);
+
+    fn encoded_len(&self) -> usize {
+        1 + self
+            .events
+            .iter()
+            .map(|(e, _, _)| e.encoded_len() + BATCH_ENTRY_OVERHEAD)
+            .sum::<usize>()
+    }
+}
+
+/// Given events, offsets and times in the order that they are to be conveyed,
+/// e.g. as returned by [crate::EventLog::events_after], return a batch reply
+/// containing up to `max` of them. Events are included until the next one would
+/// exceed a budget of bytes for the encoded reply, so that the reply always
+/// fits within the payload of a datagram e.g. a budget of the datagram's size less
+/// `HEADER_SIZE` and `MAC_SIZE` from `flip-flop-data`.
+pub fn event_reply_batch<'a, E, T, DS, const N: usize>(
+    events: impl IntoIterator<Item = &'a (E, u32, T)>,
+    max: usize,
+    budget_bytes: usize,
+    mut duration_since: DS,
+) -> BatchReply<E, N>
+where
+    DS: FnMut(T) -> u64,
+    E: 'a + Clone + DeserializeOwned + Serialize + WireMessage,
+    T: 'a + Copy,
+{
+    let mut batch = BatchReply { events: Vec::new() };
+    let mut remaining = budget_bytes.saturating_sub(1);
+    for (e, o, t) in events.into_iter().take(max.min(N)) {
+        let len = e.encoded_len() + BATCH_ENTRY_OVERHEAD;
+        if len > remaining {
+            break;
+        }
+        remaining -= len;
+        let _ = batch.events.push((e.clone(), *o, duration_since(*t)));
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventLog;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Event {
+        Temperature(i16),
+        Reset,
+    }
+
+    impl WireMessage for Event {
+        const MAX_ENCODED_LEN: usize = 1 + 2;
+
+        fn encoded_len(&self) -> usize {
+            match self {
+                Event::Temperature(_) => 3,
+                Event::Reset => 1,
+            }
+        }
+    }
+
+    fn log() -> EventLog<Event, u64, 8> {
+        let mut log = EventLog::new();
+        log.push(Event::Reset, 10);
+        log.push(Event::Temperature(20), 20);
+        log.push(Event::Temperature(-5), 30);
+        log.push(Event::Reset, 40);
+        log
+    }
+
+    #[test]
+    fn test_lagging_client_catches_up() {
+        let log = log();
+
+        // The client has last seen offset 0, so is three events behind.
+        let mut buf = [0; 64];
+        let reply =
+            event_reply_batch::<_, _, _, 4>(log.events_after(0, 4), 4, buf.len(), |t| 50 - t);
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = postcard::from_bytes::<BatchReply<Event, 4>>(encoded).unwrap();
+        assert_eq!(
+            reply.events,
+            [
+                (Event::Temperature(20), 1, 30),
+                (Event::Temperature(-5), 2, 20),
+                (Event::Reset, 3, 10),
+            ]
+        );
+        assert_eq!(reply.last_event_offset(), Some(3));
+    }
+
+    #[test]
+    fn test_batch_is_truncated() {
+        let log = log();
+
+        // Room for the count and the first two events, but not the third.
+        let mut buf = [0; 1 + 2 * (3 + BATCH_ENTRY_OVERHEAD)];
+        let reply = event_reply_batch::<_, _, _, 4>(log.events_after(0, 4), 4, buf.len(), |t| t);
+        assert_eq!(reply.events.len(), 2);
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), buf.len());
+
+        // Also truncated by the maximum requested and the capacity.
+        let reply = event_reply_batch::<_, _, _, 4>(log.events_after(0, 4), 1, 64, |t| t);
+        assert_eq!(reply.last_event_offset(), Some(1));
+        let reply = event_reply_batch::<_, _, _, 2>(log.events_after(0, 4), 4, 64, |t| t);
+        assert_eq!(reply.last_event_offset(), Some(2));
+
+        // No events fit, or there are none to convey.
+        let reply = event_reply_batch::<_, _, _, 4>(log.events_after(0, 4), 4, 8, |t| t);
+        assert_eq!(reply.last_event_offset(), None);
+        let reply = event_reply_batch::<_, _, _, 4>(log.events_after(3, 4), 4, 64, |t| t);
+        assert_eq!(reply.last_event_offset(), None);
+    }
+}
@@ -0,0 +1,463 @@
+use flip_flop_data::ProtocolVersion;
+use heapless::Vec;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    classify_epoch_offset, decode_reply, Clock, CommandRequest, CompatReply, DiscriminantSet,
+    OffsetTransition, Reply,
+};
+
+/// The reasons that a [Client] may fail to convey a request.
+#[derive(Debug, PartialEq)]
+pub enum ClientError {
+    /// No reply was received to a request despite it having been sent the
+    /// maximum number of times.
+    TimedOut,
+    /// The request could not be encoded within the client's buffer.
+    Encode,
+    /// The datagram received is not a reply.
+    Decode,
+}
+
+/// A transport-independent client that sends command requests to a server,
+/// sending them again should no reply be received in time. The client is
+/// driven by its host, which calls [Client::poll] regularly and passes each
+/// datagram received to [Client::receive]. Requests are sent with a closure
+/// that conveys the bytes given to it, e.g. by sealing them within a data
+/// frame and sending them on a socket. Encoded requests are of at most `N`
+/// bytes, and are laid out as the client's protocol version does, as are the
+/// replies it receives.
+///
+/// A request is sent again when its timeout elapses, with the timeout doubling
+/// each time until the request has been sent the maximum number of times.
+/// Should the replies to both a request and its retransmission be received,
+/// the event they convey is only surfaced once, given that the event last
+/// applied is recorded by its epoch and offset. Likewise, a late reply
+/// conveying an event older than the last one applied is not surfaced.
+///
+/// A retransmission conveys the same bytes to the closure as the request first
+/// sent. Where the server replies to retransmissions from a
+/// [crate::DedupTable], the closure must send the same datagram again rather
+/// than sealing the bytes afresh with a new frame counter, as the server
+/// recognises a retransmission by its frame counter. The datagram sealed for
+/// the pending request is then to be retained until [Client::receive]
+/// returns a reply to it.
+pub struct Client<K, S, const N: usize> {
+    clock: K,
+    send: S,
+    version: ProtocolVersion,
+    last_applied: Option<(u16, u32)>,
+    acked_offset: u32,
+    acked_epoch: u16,
+    subscriptions: DiscriminantSet,
+    timeout: u64,
+    max_attempts: u8,
+    pending: Option<Pending<N>>,
+}
+
+struct Pending<const N: usize> {
+    request: Vec<u8, N>,
+    attempts: u8,
+    timeout: u64,
+    deadline: u64,
+}
+
+impl<K, S, const N: usize> Client<K, S, N>
+where
+    K: Clock,
+    S: FnMut(&[u8]),
+{
+    /// Create a client that sends requests with a closure, timing them out
+    /// after a number of ticks of a clock. Requests are sent at most 4 times,
+    /// subscribe to all events, and are of [ProtocolVersion::LATEST].
+    pub fn new(clock: K, send: S, timeout: u64) -> Self {
+        Self {
+            clock,
+            send,
+            version: ProtocolVersion::LATEST,
+            last_applied: None,
+            acked_offset: 0,
+            acked_epoch: 0,
+            subscriptions: DiscriminantSet::ALL,
+            timeout,
+            max_attempts: 4,
+            pending: None,
+        }
+    }
+
+    /// Send each request at most a given number of times.
+    pub fn max_attempts(mut self, max_attempts: u8) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Lay out requests and replies as a given protocol version does e.g. having
+    /// been replied to with [Reply::UnsupportedVersion]. The frames conveying
+    /// requests are to be sent in the same version.
+    pub fn version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Subscribe to only the events whose discriminants are members of a set.
+    pub fn subscriptions(mut self, subscriptions: DiscriminantSet) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    /// The offset of the last event received from the server, or 0 if none
+    /// has been received.
+    pub fn last_event_offset(&self) -> u32 {
+        self.last_applied.map_or(0, |(_, offset)| offset)
+    }
+
+    /// Acknowledge having committed the event of a given epoch and offset, and
+    /// so those before it, with subsequent requests. The server may then forget
+    /// them. The epoch is that of the reply conveying the event, so that the
+    /// server can ignore acknowledgements of events it has since forgotten.
+    pub fn acknowledge(&mut self, epoch: u16, offset: u32) {
+        self.acked_epoch = epoch;
+        self.acked_offset = offset.wrapping_add(1);
+    }
+
+    /// True if a request has been sent that has yet to be replied to.
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Send a request conveying a command, or none to poll for the next event.
+    /// Any request yet to be replied to is superseded.
+    pub fn request<C>(&mut self, command: Option<C>) -> Result<(), ClientError>
+    where
+        C: DeserializeOwned + Serialize,
+    {
+        let request = CommandRequest {
+            last_event_offset: self.last_event_offset(),
+            subscriptions: self.subscriptions,
+            acked_offset: self.acked_offset,
+            acked_epoch: self.acked_epoch,
+            command,
+        };
+        let mut buf = [0; N];
+        let encoded = request
+            .encode(self.version, &mut buf)
+            .map_err(|_| ClientError::Encode)?;
+        let request = Vec::from_slice(encoded).map_err(|_| ClientError::Encode)?;
+        (self.send)(&request);
+        self.pending = Some(Pending {
+            request,
+            attempts: 1,
+            timeout: self.timeout,
+            deadline: self.clock.now().saturating_add(self.timeout),
+        });
+        Ok(())
+    }
+
+    /// Send the pending request again if its timeout has elapsed, returning an
+    /// error once it has been sent the maximum number of times without a reply.
+    pub fn poll(&mut self) -> Result<(), ClientError> {
+        let now = self.clock.now();
+        let pending = match &mut self.pending {
+            Some(pending) if now >= pending.deadline => pending,
+            _ => return Ok(()),
+        };
+        if pending.attempts >= self.max_attempts {
+            self.pending = None;
+            return Err(ClientError::TimedOut);
+        }
+        pending.attempts += 1;
+        pending.timeout = pending.timeout.saturating_mul(2);
+        pending.deadline = now.saturating_add(pending.timeout);
+        (self.send)(&pending.request);
+        Ok(())
+    }
+
+    /// Handle a datagram received from the server, returning the reply that it
+    /// conveys. A reply conveying the event last received, or one before it
+    /// within the same epoch, is a duplicate e.g. a late reply to a request
+    /// that has since been sent again, as per [classify_epoch_offset]. `None`
+    /// is then returned for it with any pending request remaining so.
+    ///
+    /// An event that the client does not recognise, e.g. of a variant that its
+    /// server has since gained, is received as any other, being returned as a
+    /// reply with no event yet with its offset, as per [decode_reply]. The
+    /// client's next request then moves past it, as it does past an event
+    /// conveyed as [Reply::Truncated].
+    pub fn receive<E>(&mut self, bytes: &[u8]) -> Result<Option<Reply<E>>, ClientError>
+    where
+        E: DeserializeOwned + Serialize,
+    {
+        let (received, reply) =
+            match decode_reply::<E>(self.version, bytes).ok_or(ClientError::Decode)? {
+                CompatReply::Event(event_reply) => (
+                    event_reply
+                        .event
+                        .is_some()
+                        .then_some((event_reply.epoch, event_reply.offset)),
+                    Reply::Event(event_reply.into_known()),
+                ),
+                CompatReply::Other(reply @ Reply::Truncated { epoch, offset }) => {
+                    (Some((epoch, offset)), reply)
+                }
+                CompatReply::Other(reply) => (None, reply),
+            };
+        if let Some(received) = received {
+            if matches!(
+                self.last_applied
+                    .map(|last| classify_epoch_offset(last, received)),
+                Some(OffsetTransition::Duplicate)
+            ) {
+                return Ok(None);
+            }
+            self.last_applied = Some(received);
+        }
+        self.pending = None;
+        Ok(Some(reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Command;
+    use crate::{event_reply, CommandReply, WireMessage};
+    use core::cell::{Cell, RefCell};
+    use flip_flop_data::{HEADER_SIZE, MAC_SIZE};
+    use serde::Deserialize;
+
+    struct TestClock<'a>(&'a Cell<u64>);
+
+    impl Clock for TestClock<'_> {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn reply_bytes(offset: u32) -> std::vec::Vec<u8> {
+        let reply = Reply::Event(event_reply(Some(&('a', offset, 0)), |t| t));
+        let mut buf = [0; 32];
+        postcard::to_slice(&reply, &mut buf).unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_dropped_reply_is_retransmitted_once() {
+        let now = Cell::new(0);
+        let sent = RefCell::new(std::vec::Vec::new());
+        let mut client = Client::<_, _, 16>::new(
+            TestClock(&now),
+            |bytes: &[u8]| sent.borrow_mut().push(bytes.to_vec()),
+            10,
+        );
+
+        client.request(Some(Command::Open)).unwrap();
+        assert_eq!(sent.borrow().len(), 1);
+
+        // The reply is dropped, so the request is sent again once timed out.
+        now.set(9);
+        client.poll().unwrap();
+        assert_eq!(sent.borrow().len(), 1);
+        now.set(10);
+        client.poll().unwrap();
+        assert_eq!(sent.borrow().len(), 2);
+        assert_eq!(sent.borrow()[0], sent.borrow()[1]);
+
+        // The reply to the retransmission is received, and then a late reply
+        // to the original request conveying the same event.
+        let mut applied = 0;
+        for _ in 0..2 {
+            if let Some(Reply::Event(reply)) = client.receive::<char>(&reply_bytes(1)).unwrap() {
+                assert_eq!(reply.into_event(), Some(('a', 1)));
+                applied += 1;
+            }
+        }
+        assert_eq!(applied, 1);
+        assert_eq!(client.last_event_offset(), 1);
+        assert!(!client.is_pending());
+
+        // No further retransmissions are made.
+        now.set(100);
+        client.poll().unwrap();
+        assert_eq!(sent.borrow().len(), 2);
+
+        // The next request conveys the event received.
+        client.request::<Command>(None).unwrap();
+        let request = postcard::from_bytes::<CommandRequest<Command>>(&sent.borrow()[2]).unwrap();
+        assert_eq!(request.last_event_offset, 1);
+        assert_eq!(request.acked_offset, 0);
+
+        // Once the event is committed, it is acknowledged by the next request.
+        client.acknowledge(0, 1);
+        client.request::<Command>(None).unwrap();
+        let request = postcard::from_bytes::<CommandRequest<Command>>(&sent.borrow()[3]).unwrap();
+        assert_eq!(request.acked_offset, 2);
+    }
+
+    #[test]
+    fn test_late_reply_is_not_applied() {
+        let now = Cell::new(0);
+        let mut client = Client::<_, _, 16>::new(TestClock(&now), |_: &[u8]| {}, 10);
+        let mut receive = |bytes: &[u8]| match client.receive::<char>(bytes).unwrap() {
+            Some(Reply::Event(reply)) => reply.event.map(|_| reply.offset),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // The first event is applied, however its offset...
+        assert_eq!(receive(&reply_bytes(0)), Some(0));
+        // ...and a late reply for an older event, following a newer one, is
+        // not applied again.
+        assert_eq!(receive(&reply_bytes(1)), Some(1));
+        assert_eq!(receive(&reply_bytes(2)), Some(2));
+        assert_eq!(client.receive::<char>(&reply_bytes(1)), Ok(None));
+        assert_eq!(client.last_event_offset(), 2);
+
+        // An event of a new epoch is applied, being after a reset.
+        let reply = Reply::Event(CommandReply {
+            epoch: 1,
+            ..event_reply(Some(&('a', 1, 0)), |t| t)
+        });
+        let mut buf = [0; 32];
+        let bytes = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert!(client.receive::<char>(bytes).unwrap().is_some());
+        assert_eq!(client.last_event_offset(), 1);
+    }
+
+    #[test]
+    fn test_unrecognised_event_is_moved_past() {
+        // An event of a variant that the client does not recognise, as
+        // conveyed by a newer server.
+        #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        enum NewEvent {
+            Opened,
+            Heated(u16),
+        }
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        enum OldEvent {
+            Opened,
+        }
+        let reply_bytes = |event, offset| {
+            let reply = Reply::Event(event_reply(Some(&(event, offset, 0)), |t| t));
+            let mut buf = [0; 32];
+            postcard::to_slice(&reply, &mut buf).unwrap().to_vec()
+        };
+
+        let now = Cell::new(0);
+        let sent = RefCell::new(std::vec::Vec::new());
+        let mut client = Client::<_, _, 32>::new(
+            TestClock(&now),
+            |bytes: &[u8]| sent.borrow_mut().push(bytes.to_vec()),
+            10,
+        );
+        client.request::<Command>(None).unwrap();
+
+        // The unrecognised event is received without an event, but with its
+        // offset, which the next request moves past...
+        match client.receive::<OldEvent>(&reply_bytes(NewEvent::Heated(20), 3)) {
+            Ok(Some(Reply::Event(CommandReply {
+                offset: 3,
+                event: None,
+                ..
+            }))) => (),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        assert_eq!(client.last_event_offset(), 3);
+        client.request::<Command>(None).unwrap();
+        let request =
+            CommandRequest::<Command>::decode(ProtocolVersion::LATEST, &sent.borrow()[1]).unwrap();
+        assert_eq!(request.last_event_offset, 3);
+
+        // ...such that the later events are received.
+        match client.receive::<OldEvent>(&reply_bytes(NewEvent::Opened, 4)) {
+            Ok(Some(Reply::Event(reply))) => {
+                assert_eq!(reply.into_event(), Some((OldEvent::Opened, 4)))
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        assert_eq!(client.last_event_offset(), 4);
+    }
+
+    #[test]
+    fn test_oversized_event_is_moved_past() {
+        let now = Cell::new(0);
+        let sent = RefCell::new(std::vec::Vec::new());
+        let mut client = Client::<_, _, 32>::new(
+            TestClock(&now),
+            |bytes: &[u8]| sent.borrow_mut().push(bytes.to_vec()),
+            10,
+        );
+
+        // A server whose datagrams fit a single byte event, but not 'é'.
+        let reply_len = Reply::Event(event_reply(Some(&('a', 0, 0)), |t| t)).encoded_len();
+        let mut server = crate::Server::<char, _, 8>::new(TestClock(&now))
+            .max_datagram(HEADER_SIZE + reply_len + MAC_SIZE, MAC_SIZE);
+        for event in ['a', 'é', 'b'] {
+            server.push_event(event);
+        }
+
+        let mut poll = || {
+            client.request::<Command>(None).unwrap();
+            let request = sent.borrow_mut().pop().unwrap();
+            let request = CommandRequest::<Command>::decode(ProtocolVersion::LATEST, &request);
+            let mut buf = [0; 32];
+            let reply = server.handle_request(request.unwrap());
+            let reply = reply.encode(ProtocolVersion::LATEST, &mut buf).unwrap();
+            client.receive::<char>(reply).unwrap()
+        };
+        // Having received the event at offset 0, the client is behind 'é'...
+        assert_eq!(
+            poll(),
+            Some(Reply::Truncated {
+                epoch: 0,
+                offset: 1
+            })
+        );
+        // ...which it moves past to receive the event after it.
+        assert!(matches!(
+            poll(),
+            Some(Reply::Event(CommandReply {
+                event: Some('b'),
+                offset: 2,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_retransmissions_back_off_and_time_out() {
+        let now = Cell::new(0);
+        let sent = Cell::new(0);
+        let mut client =
+            Client::<_, _, 16>::new(TestClock(&now), |_: &[u8]| sent.set(sent.get() + 1), 10)
+                .max_attempts(3);
+
+        client.request::<Command>(None).unwrap();
+
+        // Sent again after 10, and then 20 ticks...
+        now.set(10);
+        client.poll().unwrap();
+        now.set(29);
+        client.poll().unwrap();
+        assert_eq!(sent.get(), 2);
+        now.set(30);
+        client.poll().unwrap();
+        assert_eq!(sent.get(), 3);
+
+        // ...and timing out 40 ticks later having been sent 3 times.
+        now.set(69);
+        assert_eq!(client.poll(), Ok(()));
+        now.set(70);
+        assert_eq!(client.poll(), Err(ClientError::TimedOut));
+        assert!(!client.is_pending());
+        assert_eq!(sent.get(), 3);
+    }
+
+    #[test]
+    fn test_request_too_long() {
+        let now = Cell::new(0);
+        let mut client = Client::<_, _, 4>::new(TestClock(&now), |_: &[u8]| {}, 10);
+        assert_eq!(
+            client.request(Some(Command::Open)),
+            Err(ClientError::Encode)
+        );
+        assert_eq!(client.receive::<char>(&[9]), Err(ClientError::Decode));
+    }
+}
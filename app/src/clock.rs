@@ -0,0 +1,104 @@
+/// A server's monotonic notion of time, expressed in ticks agreed between a
+/// client and its servers e.g. ticks can represent seconds. Abstracting the
+/// clock permits servers to use whatever time source is available to them,
+/// such as a hardware timer.
+pub trait Clock {
+    /// The number of ticks in a second. By default, ticks are seconds.
+    const TICKS_PER_SECOND: u64 = 1;
+
+    /// The current time in ticks. Successive calls must never go backwards.
+    fn now(&self) -> u64;
+
+    /// The whole seconds elapsed since a time in ticks, saturating at
+    /// `u32::MAX`. A time in the future is taken as having just elapsed.
+    fn elapsed_secs(&self, since: u64) -> u32 {
+        let secs = self.now().saturating_sub(since) / Self::TICKS_PER_SECOND;
+        secs.try_into().unwrap_or(u32::MAX)
+    }
+}
+
+/// A clock whose ticks are the seconds since it was created, as measured by
+/// tokio's monotonic clock. Available with the `tokio` feature, for servers
+/// running on a host.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug)]
+pub struct TokioClock {
+    started: tokio::time::Instant,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioClock {
+    /// Create a clock whose time is presently 0.
+    pub fn new() -> Self {
+        Self {
+            started: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Default for TokioClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clocked_event_reply;
+    use core::cell::Cell;
+
+    // A clock that is only advanced by the test, with ticks of milliseconds.
+    struct MockClock(Cell<u64>);
+
+    impl MockClock {
+        fn advance(&self, ticks: u64) {
+            self.0.set(self.0.get() + ticks);
+        }
+    }
+
+    impl Clock for MockClock {
+        const TICKS_PER_SECOND: u64 = 1000;
+
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn test_age_of_event() {
+        let clock = MockClock(Cell::new(10_000));
+        let event = ('a', 1, 10_000);
+        clock.advance(2_500);
+
+        // The event recorded 2500 ticks in the past is that old, being two whole
+        // seconds.
+        let reply = clocked_event_reply(Some(&event), &clock);
+        assert_eq!(reply.age.get(), 2);
+        assert_eq!(clock.elapsed_secs(event.2), 2);
+
+        // Events from the future have just elapsed, and those from long ago
+        // saturate.
+        assert_eq!(clock.elapsed_secs(20_000), 0);
+        clock.advance(u64::MAX - clock.now());
+        assert_eq!(clock.elapsed_secs(0), u32::MAX);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_tokio_clock() {
+        let clock = TokioClock::new();
+        assert_eq!(clock.now(), 0);
+        tokio::time::advance(core::time::Duration::from_millis(3_500)).await;
+        assert_eq!(clock.now(), 3);
+        assert_eq!(clock.elapsed_secs(1), 2);
+    }
+}
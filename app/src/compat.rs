@@ -0,0 +1,361 @@
+//! Decoding of events by clients that may be older than their servers.
+//!
+//! Events are usually enums, and a server may gain new variants before all of
+//! its clients are updated. A client decoding a reply with an event variant it
+//! does not recognise would otherwise fail to decode the event, and so lose its
+//! offset. [decode_event_reply] instead yields the unrecognised variant's
+//! discriminant and bytes so that the client can acknowledge the offset and
+//! move on, as does [decode_reply] for a whole [crate::Reply].
+//!
+//! For this to work, new variants must only ever be appended to an enum, and
+//! existing variants must never be reordered, removed or have their data
+//! changed. The same also applies to commands, where a server receiving a
+//! command it does not recognise should reply with a [crate::Reply::Nack].
+
+use flip_flop_data::ProtocolVersion;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    wire::{COMMAND_REPLY_FIXED_LEN, REPLY_V0_MIN_LEN, SERVER_TIME_LEN},
+    AgeSecs, CommandReply, Reply,
+};
+
+/// An event that may or may not have been recognised by the client.
+#[derive(Debug, PartialEq)]
+pub enum CompatEvent<'a, E> {
+    /// The event was decoded.
+    Known(E),
+    /// The event's discriminant was not recognised. The bytes are those that
+    /// follow the discriminant, being the event's data, if any.
+    Unknown(u8, &'a [u8]),
+}
+
+/// A [crate::CommandReply] as decoded by [decode_event_reply].
+#[derive(Debug, PartialEq)]
+pub struct CompatEventReply<'a, E> {
+    /// The age of the event.
+    pub age: AgeSecs,
+    /// The frame counter of the request replied to.
+    pub frame_counter: u16,
+    /// The server's current time, if conveyed.
+    pub server_time: Option<u64>,
+    /// The epoch of the server's events.
+    pub epoch: u16,
+    /// The offset of the event, or 0 if there is no event.
+    pub offset: u32,
+    /// The event, if any.
+    pub event: Option<CompatEvent<'a, E>>,
+}
+
+impl<'a, E: DeserializeOwned + Serialize> CompatEventReply<'a, E> {
+    /// The reply as a [CommandReply], with an unrecognised event being omitted
+    /// while its offset is retained.
+    pub fn into_known(self) -> CommandReply<E> {
+        CommandReply {
+            age: self.age,
+            frame_counter: self.frame_counter,
+            server_time: self.server_time,
+            epoch: self.epoch,
+            offset: self.offset,
+            event: match self.event {
+                Some(CompatEvent::Known(e)) => Some(e),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// A [Reply] as decoded by [decode_reply].
+#[derive(Debug, PartialEq)]
+pub enum CompatReply<'a, E: DeserializeOwned + Serialize> {
+    /// A reply conveying an event, whether recognised or not, or that there
+    /// are no more events.
+    Event(CompatEventReply<'a, E>),
+    /// Any other reply, which conveys no event.
+    Other(Reply<E>),
+}
+
+/// Decode the bytes of a [crate::CommandReply], using a function to decode its
+/// event from the bytes that encode it, commencing with its discriminant e.g.
+/// `|bytes| postcard::from_bytes(bytes).ok()`. Events that cannot be decoded
+/// are returned as [CompatEvent::Unknown] along with their offset.
+///
+/// Returns `None` if the bytes are not those of an event reply.
+pub fn decode_event_reply<'a, E, D>(bytes: &'a [u8], decode: D) -> Option<CompatEventReply<'a, E>>
+where
+    D: FnOnce(&'a [u8]) -> Option<E>,
+{
+    // The server's time, if conveyed, follows the age and frame counter.
+    let time_len = match bytes.get(4)? {
+        0 => 0,
+        1 => SERVER_TIME_LEN,
+        _ => return None,
+    };
+    let fixed_len = COMMAND_REPLY_FIXED_LEN + time_len;
+    if bytes.len() < fixed_len {
+        return None;
+    }
+    let (fixed, event_bytes) = bytes.split_at(fixed_len);
+    let age = AgeSecs::from_secs(u16::from_le_bytes(fixed[..2].try_into().ok()?).into());
+    let frame_counter = u16::from_le_bytes(fixed[2..4].try_into().ok()?);
+    let server_time = match time_len {
+        0 => None,
+        _ => Some(u64::from_le_bytes(fixed[5..13].try_into().ok()?)),
+    };
+    let (epoch, offset) = fixed[fixed_len - 6..].split_at(2);
+    let epoch = u16::from_le_bytes(epoch.try_into().ok()?);
+    let offset = u32::from_le_bytes(offset.try_into().ok()?);
+
+    let event = decode_event(event_bytes, decode)?;
+
+    Some(CompatEventReply {
+        age,
+        frame_counter,
+        server_time,
+        epoch,
+        offset,
+        event,
+    })
+}
+
+// Decode an event from a discriminant followed by the event's data, if any,
+// with no bytes conveying no event.
+fn decode_event<'a, E, D>(bytes: &'a [u8], decode: D) -> Option<Option<CompatEvent<'a, E>>>
+where
+    D: FnOnce(&'a [u8]) -> Option<E>,
+{
+    match bytes.first() {
+        None => Some(None),
+        // Discriminants of 128 or more would be encoded as more than one byte.
+        Some(discriminant) if discriminant & 0x80 != 0 => None,
+        Some(&discriminant) => Some(Some(
+            decode(bytes)
+                .map(CompatEvent::Known)
+                .unwrap_or(CompatEvent::Unknown(discriminant, &bytes[1..])),
+        )),
+    }
+}
+
+/// Decode the bytes of a [Reply] as laid out by a protocol version, as per
+/// [Reply::decode], except that a reply conveying an event that the client
+/// does not recognise is decoded as per [decode_event_reply] rather than as
+/// there being no more events. The client can then record the event's offset
+/// as received and move past it.
+///
+/// Returns `None` if the bytes are not those of a reply.
+pub fn decode_reply<E>(version: ProtocolVersion, bytes: &[u8]) -> Option<CompatReply<'_, E>>
+where
+    E: DeserializeOwned + Serialize,
+{
+    let from_bytes = |bytes| postcard::from_bytes(bytes).ok();
+    match version {
+        // An event reply of version 0 is not preceded by a variant, with its
+        // event being followed by its offset.
+        ProtocolVersion::V0 if bytes.len() >= REPLY_V0_MIN_LEN => {
+            let (delta_ticks, event_and_offset) = bytes.split_at(REPLY_V0_MIN_LEN);
+            let delta_ticks = u64::from_le_bytes(delta_ticks.try_into().ok()?);
+            let (event, offset) = if event_and_offset.is_empty() {
+                (None, 0)
+            } else {
+                let offset_at = event_and_offset.len().checked_sub(4)?;
+                let (event_bytes, offset) = event_and_offset.split_at(offset_at);
+                if event_bytes.is_empty() {
+                    return None;
+                }
+                let offset = u32::from_le_bytes(offset.try_into().ok()?);
+                (decode_event(event_bytes, from_bytes)?, offset)
+            };
+            Some(CompatReply::Event(CompatEventReply {
+                age: AgeSecs::from_secs(delta_ticks),
+                frame_counter: 0,
+                server_time: None,
+                epoch: 0,
+                offset,
+                event,
+            }))
+        }
+        ProtocolVersion::V1 if bytes.first() == Some(&0) => {
+            decode_event_reply(&bytes[1..], from_bytes).map(CompatReply::Event)
+        }
+        _ => Reply::decode(version, bytes).ok().map(CompatReply::Other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandReply;
+    use serde::{Deserialize, Serialize};
+
+    // An event as known to an older client...
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum OldEvent {
+        Opened,
+        Moved(u8),
+    }
+
+    // ...and as since extended by the server.
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum NewEvent {
+        Opened,
+        Moved(u8),
+        Heated(u16),
+    }
+
+    fn encode(event: Option<(NewEvent, u32)>, buf: &mut [u8]) -> &[u8] {
+        encode_at(None, event, buf)
+    }
+
+    fn encode_at(
+        server_time: Option<u64>,
+        event: Option<(NewEvent, u32)>,
+        buf: &mut [u8],
+    ) -> &[u8] {
+        let (event, offset) = event.map_or((None, 0), |(e, o)| (Some(e), o));
+        let reply = CommandReply {
+            age: AgeSecs::from_secs(10),
+            frame_counter: 11,
+            server_time,
+            epoch: 3,
+            offset,
+            event,
+        };
+        postcard::to_slice(&reply, buf).unwrap()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<CompatEventReply<'_, OldEvent>> {
+        decode_event_reply(bytes, |bytes| postcard::from_bytes(bytes).ok())
+    }
+
+    #[test]
+    fn test_decode_unrecognised_event() {
+        let mut buf = [0; 32];
+        let bytes = encode(Some((NewEvent::Heated(0x1234), 7)), &mut buf);
+
+        // The event is not recognised by the old client, but its offset is.
+        let reply = decode(bytes).unwrap();
+        assert_eq!(reply.age.get(), 10);
+        assert_eq!(reply.frame_counter, 11);
+        assert_eq!((reply.epoch, reply.offset), (3, 7));
+        assert_eq!(reply.event, Some(CompatEvent::Unknown(2, &[0x34, 0x12])));
+
+        // Decoding as a CommandReply loses the event, such that it cannot be
+        // told apart from there being no more events.
+        assert!(postcard::from_bytes::<CommandReply<OldEvent>>(bytes)
+            .unwrap()
+            .event
+            .is_none());
+    }
+
+    #[test]
+    fn test_decode_recognised_event() {
+        let mut buf = [0; 32];
+        let bytes = encode(Some((NewEvent::Moved(3), 8)), &mut buf);
+        let reply = decode(bytes).unwrap();
+        assert_eq!(reply.event, Some(CompatEvent::Known(OldEvent::Moved(3))));
+        assert_eq!(reply.offset, 8);
+
+        let bytes = encode(Some((NewEvent::Opened, 9)), &mut buf);
+        assert_eq!(
+            decode(bytes).unwrap().event,
+            Some(CompatEvent::Known(OldEvent::Opened))
+        );
+
+        let bytes = encode(None, &mut buf);
+        assert_eq!(decode(bytes).unwrap().event, None);
+
+        // The server's time may precede the event.
+        let bytes = encode_at(Some(20), Some((NewEvent::Moved(3), 8)), &mut buf);
+        let reply = decode(bytes).unwrap();
+        assert_eq!(reply.server_time, Some(20));
+        assert_eq!((reply.epoch, reply.offset), (3, 8));
+        assert_eq!(reply.event, Some(CompatEvent::Known(OldEvent::Moved(3))));
+    }
+
+    #[test]
+    fn test_decode_malformed_reply() {
+        let mut buf = [0; 32];
+        let bytes = encode(Some((NewEvent::Opened, 9)), &mut buf);
+        assert_eq!(decode(&bytes[..COMMAND_REPLY_FIXED_LEN - 1]), None);
+
+        // Discriminants of more than one byte are not those of events.
+        let mut bytes = bytes.to_vec();
+        bytes[COMMAND_REPLY_FIXED_LEN] = 0x80;
+        assert_eq!(decode(&bytes), None);
+
+        // The server's time must be conveyed in full, if at all.
+        let bytes = encode_at(Some(20), None, &mut buf);
+        assert_eq!(decode(&bytes[..bytes.len() - 1]), None);
+        let mut bytes = bytes.to_vec();
+        bytes[4] = 2;
+        assert_eq!(decode(&bytes), None);
+    }
+
+    #[test]
+    fn test_decode_reply() {
+        let mut buf = [0; 32];
+        let encode_reply = |reply: Reply<NewEvent>, version, buf: &mut [u8; 32]| {
+            let len = reply.encode(version, buf).unwrap().len();
+            buf[..len].to_vec()
+        };
+        let heated = || {
+            Reply::Event(CommandReply {
+                epoch: 3,
+                ..crate::event_reply(Some(&(NewEvent::Heated(0x1234), 7, 0)), |_| 10)
+            })
+        };
+
+        // An unrecognised event is decoded along with its offset, whether
+        // conveyed in version 0 or 1...
+        let bytes = encode_reply(heated(), ProtocolVersion::V1, &mut buf);
+        match decode_reply::<OldEvent>(ProtocolVersion::V1, &bytes).unwrap() {
+            CompatReply::Event(reply) => {
+                assert_eq!((reply.epoch, reply.offset), (3, 7));
+                assert_eq!(reply.event, Some(CompatEvent::Unknown(2, &[0x34, 0x12])));
+                assert_eq!(reply.into_known().into_event(), None);
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        let bytes = encode_reply(heated(), ProtocolVersion::V0, &mut buf);
+        match decode_reply::<OldEvent>(ProtocolVersion::V0, &bytes).unwrap() {
+            CompatReply::Event(reply) => {
+                assert_eq!((reply.age.get(), reply.epoch, reply.offset), (10, 0, 7));
+                assert_eq!(reply.event, Some(CompatEvent::Unknown(2, &[0x34, 0x12])));
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+
+        // ...as is a recognised one, and there being no more events.
+        let moved = Reply::Event(crate::event_reply(Some(&(NewEvent::Moved(3), 8, 0)), |t| t));
+        let bytes = encode_reply(moved, ProtocolVersion::V0, &mut buf);
+        match decode_reply::<OldEvent>(ProtocolVersion::V0, &bytes).unwrap() {
+            CompatReply::Event(reply) => {
+                assert_eq!(
+                    reply.into_known().into_event(),
+                    Some((OldEvent::Moved(3), 8))
+                )
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        let none = Reply::Event(crate::event_reply::<NewEvent, u64, _>(None, |t| t));
+        for version in [ProtocolVersion::V0, ProtocolVersion::V1] {
+            let bytes = encode_reply(none.clone(), version, &mut buf);
+            match decode_reply::<OldEvent>(version, &bytes).unwrap() {
+                CompatReply::Event(reply) => assert_eq!(reply.event, None),
+                reply => panic!("unexpected reply {:?}", reply),
+            }
+        }
+
+        // Other replies are decoded as such.
+        let bytes = encode_reply(Reply::EventsPending(2), ProtocolVersion::V1, &mut buf);
+        assert_eq!(
+            decode_reply::<OldEvent>(ProtocolVersion::V1, &bytes),
+            Some(CompatReply::Other(Reply::EventsPending(2)))
+        );
+        assert_eq!(decode_reply::<OldEvent>(ProtocolVersion::V1, &[]), None);
+        assert_eq!(
+            decode_reply::<OldEvent>(ProtocolVersion::V0, &[0; 10]),
+            None
+        );
+    }
+}
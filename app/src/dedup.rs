@@ -0,0 +1,180 @@
+use flip_flop_data::{DataSource, Header, ServerAddress, ServerPort};
+use heapless::Deque;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Reply;
+
+/// Identifies a request by the header of the data frame that it was received
+/// within, being unique to the request until the client's frame counter
+/// cycles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestKey {
+    pub source: DataSource,
+    pub server_address: ServerAddress,
+    pub server_port: ServerPort,
+    pub frame_counter: u16,
+}
+
+impl From<&Header> for RequestKey {
+    fn from(header: &Header) -> Self {
+        Self {
+            source: header.source,
+            server_address: header.server_address,
+            server_port: header.server_port,
+            frame_counter: header.frame_counter,
+        }
+    }
+}
+
+/// The replies to the last `N` requests handled, so that a request received
+/// again, as when a client retransmits it having lost the reply, is answered
+/// with the same reply rather than being handled again. Commands that change
+/// the server's state are thereby acted upon once, however often they are
+/// received. Once full, the oldest reply is forgotten to make room for the
+/// next.
+///
+/// A retransmitted request is recognised by being received within a frame
+/// having the same [RequestKey], as when the datagram sealed for it is sent
+/// again. Such a frame is a replay, and so is only to be replied to from the
+/// table once it has been authenticated, lest a forged header elicit a reply.
+/// `ServerEndpoint::process_with_dedup` does this with the `endpoint` feature.
+///
+/// A client must therefore resend the same datagram when retransmitting,
+/// rather than sealing the request again with a new frame counter, which would
+/// be handled as a new request. A host driving a `Client` is to retain
+/// the datagram sealed for the pending request, sending it again when the
+/// client retransmits.
+pub struct DedupTable<E: DeserializeOwned + Serialize, const N: usize> {
+    entries: Deque<(RequestKey, Reply<E>), N>,
+}
+
+impl<E: DeserializeOwned + Serialize, const N: usize> DedupTable<E, N> {
+    /// Create an empty table.
+    pub const fn new() -> Self {
+        Self {
+            entries: Deque::new(),
+        }
+    }
+
+    /// The reply to a request if it has been handled recently.
+    pub fn get(&self, key: &RequestKey) -> Option<&Reply<E>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, reply)| reply)
+    }
+
+    /// Record the reply to a request, forgetting the oldest should the table
+    /// be full.
+    pub fn insert(&mut self, key: RequestKey, reply: Reply<E>) {
+        if self.entries.is_full() {
+            let _ = self.entries.pop_front();
+        }
+        let _ = self.entries.push_back((key, reply));
+    }
+
+    /// Reply to a request with the reply recorded for it, or else by handling
+    /// it and recording its reply.
+    pub fn reply<F>(&mut self, key: RequestKey, handle: F) -> Reply<E>
+    where
+        E: Clone,
+        F: FnOnce() -> Reply<E>,
+    {
+        if let Some(reply) = self.get(&key) {
+            return reply.clone();
+        }
+        let reply = handle();
+        self.insert(key, reply.clone());
+        reply
+    }
+
+    /// The number of replies recorded.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if no replies are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget all replies recorded e.g. as the server has been reset.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<E: DeserializeOwned + Serialize, const N: usize> Default for DedupTable<E, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{poll, Command, Event, FixedClock};
+    use crate::{CommandRequest, Server};
+
+    fn key(frame_counter: u16) -> RequestKey {
+        RequestKey {
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter,
+        }
+    }
+
+    #[test]
+    fn test_repeated_request_is_handled_once() {
+        let mut server = Server::<Event, _, 4>::new(FixedClock);
+        server.push_event(Event::Toggled(0));
+        let mut table = DedupTable::<Event, 2>::new();
+        let mut handled = 0;
+
+        // Each toggle handled records an event, being replied to the client.
+        let mut request = |table: &mut DedupTable<Event, 2>, frame_counter| {
+            table.reply(key(frame_counter), || {
+                handled += 1;
+                let offset = server.push_event(Event::Toggled(handled));
+                server.handle_request(CommandRequest {
+                    command: Some(Command::Toggle),
+                    ..poll(offset - 1)
+                })
+            })
+        };
+
+        let first = request(&mut table, 10);
+        assert_eq!(request(&mut table, 10), first);
+        assert_eq!(request(&mut table, 10), first);
+
+        // A new request is handled, and the oldest reply forgotten once full.
+        let second = request(&mut table, 11);
+        assert_ne!(second, first);
+        request(&mut table, 12);
+        assert_eq!(request(&mut table, 11), second);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&key(10)), None);
+        assert_ne!(request(&mut table, 10), first);
+        assert_eq!(handled, 4);
+    }
+
+    #[test]
+    fn test_key_is_from_header() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 7,
+        };
+        assert_eq!(RequestKey::from(&header), key(7));
+        assert_eq!(
+            RequestKey::from(&Header {
+                version: 1,
+                ..header
+            }),
+            key(7)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies the kind of a command or event, usually the variant of an enum,
+/// so that sets of kinds can be declared and conveyed compactly.
+/// Discriminants range from 0 to 31.
+///
+/// ```
+/// use flip_flop_app::Discriminant;
+///
+/// enum Command {
+///     Open,
+///     Close,
+///     Configure(u8),
+/// }
+///
+/// impl Discriminant for Command {
+///     fn discriminant(&self) -> u8 {
+///         match self {
+///             Command::Open => 0,
+///             Command::Close => 1,
+///             Command::Configure(_) => 2,
+///         }
+///     }
+/// }
+/// ```
+pub trait Discriminant {
+    /// The discriminant of this value, from 0 to 31.
+    fn discriminant(&self) -> u8;
+}
+
+/// A set of discriminants, held as a bitmap where bit n is set if
+/// discriminant n is a member. A set is conveyed as its bitmap i.e. a
+/// little endian u32.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct DiscriminantSet(pub u32);
+
+impl DiscriminantSet {
+    /// A set with no members.
+    pub const EMPTY: Self = Self(0);
+
+    /// A set with all discriminants as members.
+    pub const ALL: Self = Self(u32::MAX);
+
+    /// This set with a discriminant added. Discriminants greater than 31
+    /// cannot be members and are ignored.
+    pub const fn with(self, discriminant: u8) -> Self {
+        if discriminant < 32 {
+            Self(self.0 | (1 << discriminant))
+        } else {
+            self
+        }
+    }
+
+    /// True if a discriminant is a member of this set.
+    pub const fn contains(&self, discriminant: u8) -> bool {
+        discriminant < 32 && self.0 & (1 << discriminant) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership() {
+        let set = DiscriminantSet::EMPTY.with(0).with(31).with(32);
+        assert_eq!(set, DiscriminantSet(0x8000_0001));
+        assert!(set.contains(0));
+        assert!(!set.contains(1));
+        assert!(set.contains(31));
+        assert!(!set.contains(32));
+        assert!(DiscriminantSet::ALL.contains(5));
+    }
+}
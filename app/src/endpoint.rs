@@ -0,0 +1,734 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use flip_flop_data::{
+    crypto::{CryptoError, Opener, Sealer},
+    peek_source, DataFrame, DataSource, Header, ParseError, MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+
+use crate::{
+    accept::check_length, accept_frame, AcceptConfig, CommandHandler, CommandRequest, DedupTable,
+    Discriminant, Outcome, Peer, Rejection, ReplayWindow, RequestKey, ServerRuntime, WireMessage,
+};
+
+/// The reasons that a datagram received by a [ServerEndpoint] may fail to be
+/// processed.
+#[derive(Debug, PartialEq)]
+pub enum ProcessError {
+    /// The datagram is not a data frame.
+    Parse(ParseError),
+    /// The data frame was not accepted given the endpoint's configuration.
+    Rejected(Rejection),
+    /// The command failed to open, or the reply failed to seal.
+    Crypto(CryptoError),
+    /// The opened payload is not a command request.
+    Decode,
+    /// The reply could not be encoded within the buffer provided for it.
+    Encode,
+}
+
+/// A server's handling of the datagrams it receives from a client, being
+/// everything between its transport and its [ServerRuntime]. Each datagram is
+/// expected to hold a data frame whose payload has been sealed with the `crypto`
+/// feature of `flip-flop-data`, and is replied to with one of its own. The
+/// nonce of each frame is [Header::nonce] with a salt shared by the client and
+/// server.
+///
+/// Replies are sent with frame counters starting from 0, or from the counter
+/// given to [ServerEndpoint::starting_at]. As a nonce must never be reused with
+/// a key, a server that restarts must either change its key or resume from
+/// where it left off.
+///
+/// Outcomes are counted for the peer that each datagram is from, as per
+/// [ServerRuntime::tracking_peers].
+pub struct ServerEndpoint<H, const P: usize = 0> {
+    runtime: ServerRuntime<H, P>,
+    config: AcceptConfig,
+    replay_window: ReplayWindow,
+    opener: Opener,
+    sealer: Sealer,
+    salt: [u8; 6],
+    frame_counter: u16,
+}
+
+impl<H, const P: usize> ServerEndpoint<H, P> {
+    /// Create an endpoint that accepts frames according to a configuration,
+    /// opening them with the client's key and sealing replies with the
+    /// server's key.
+    pub fn new(
+        runtime: ServerRuntime<H, P>,
+        config: AcceptConfig,
+        client_key: &[u8; 16],
+        server_key: &[u8; 16],
+        salt: [u8; 6],
+    ) -> Self {
+        Self {
+            runtime,
+            config,
+            replay_window: ReplayWindow::new(),
+            opener: Opener::new(client_key),
+            sealer: Sealer::new(server_key),
+            salt,
+            frame_counter: 0,
+        }
+    }
+
+    /// Send the next reply with a given frame counter.
+    pub fn starting_at(mut self, frame_counter: u16) -> Self {
+        self.frame_counter = frame_counter;
+        self
+    }
+
+    /// The frame counter that the next reply will be sent with.
+    pub fn frame_counter(&self) -> u16 {
+        self.frame_counter
+    }
+
+    /// The runtime that requests are handled by.
+    pub fn runtime(&self) -> &ServerRuntime<H, P> {
+        &self.runtime
+    }
+
+    /// The runtime that requests are handled by, mutably e.g. for recording
+    /// events with its handler.
+    pub fn runtime_mut(&mut self) -> &mut ServerRuntime<H, P> {
+        &mut self.runtime
+    }
+
+    /// Process a datagram received from the client, writing the datagram to
+    /// reply with to `out` and returning its length. Datagrams sourced by a
+    /// server, as may be received on a shared medium, are ignored and have no
+    /// reply. Broadcast datagrams, being addressed to
+    /// [flip_flop_data::BROADCAST_ADDRESS], have their requests handled but are
+    /// not replied to, as every server receiving them would otherwise reply at
+    /// once.
+    ///
+    /// A frame's counter is only regarded as having been seen once the frame
+    /// has been authenticated, so that a forged frame cannot cause the
+    /// client's subsequent frames to be rejected.
+    ///
+    /// Each outcome is counted by the runtime's [crate::Stats], and for the
+    /// peer that the datagram is from, as per [ServerRuntime::record].
+    ///
+    /// Replies are sent in the protocol version of the request, as negotiated
+    /// by [ServerRuntime::negotiate_version], with requests and replies being
+    /// laid out as that version does, as per [CommandRequest::decode] and
+    /// [crate::Reply::encode]. Requests of a version newer than the runtime supports
+    /// are replied to with [crate::Reply::UnsupportedVersion] without being decoded.
+    pub fn process<C, E>(
+        &mut self,
+        datagram: &[u8],
+        out: &mut [u8],
+    ) -> Result<Option<usize>, ProcessError>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        self.process_with::<C, E, 0>(datagram, out, None)
+    }
+
+    /// Process a datagram as per [ServerEndpoint::process], except that the
+    /// replies to requests handled are recorded in a table, and a request
+    /// received again is replied to from it without being handled again. A
+    /// client that retransmits a request having lost its reply then receives
+    /// the same reply, with its command being acted upon once.
+    ///
+    /// A retransmission is recognised as a frame rejected as replayed, which
+    /// is only replied to once it has been authenticated, and so a forged frame
+    /// cannot elicit a reply. The reply is sealed afresh with the endpoint's
+    /// next frame counter. A replayed frame whose request is absent from the
+    /// table is rejected as [Rejection::Replayed]. Retransmissions must
+    /// therefore be the same datagram as first sent, as per [RequestKey], and
+    /// replay protection must be enabled by the endpoint's configuration.
+    pub fn process_with_dedup<C, E, const N: usize>(
+        &mut self,
+        datagram: &[u8],
+        out: &mut [u8],
+        table: &mut DedupTable<E, N>,
+    ) -> Result<Option<usize>, ProcessError>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        self.process_with(datagram, out, Some(table))
+    }
+
+    fn process_with<C, E, const N: usize>(
+        &mut self,
+        datagram: &[u8],
+        out: &mut [u8],
+        table: Option<&mut DedupTable<E, N>>,
+    ) -> Result<Option<usize>, ProcessError>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        if peek_source(datagram) == Some(DataSource::Server) {
+            return Ok(None);
+        }
+        let peer = Peer::of_datagram(datagram);
+        let runtime = &mut self.runtime;
+        runtime.record(peer, Outcome::Received);
+        let frame = DataFrame::from_bytes(datagram).map_err(|e| {
+            runtime.record(peer, Outcome::ParseFailed);
+            ProcessError::Parse(e)
+        })?;
+
+        if let Err(rejection) = check_length(&frame, datagram.len(), &self.config) {
+            runtime.record(peer, &rejection);
+            return Err(ProcessError::Rejected(rejection));
+        }
+
+        let mut replay_window = self.replay_window;
+        let (header, encrypted_payload, replayed) =
+            match accept_frame(&frame, &self.config, &mut replay_window) {
+                Ok((header, encrypted_payload)) => (header, encrypted_payload, false),
+                // A replayed frame may be a retransmission, whose reply is only
+                // looked up once the frame has been authenticated.
+                Err(Rejection::Replayed) if table.is_some() => {
+                    let (header, encrypted_payload) = frame.parse().map_err(ProcessError::Parse)?;
+                    (header, encrypted_payload, true)
+                }
+                Err(rejection) => {
+                    runtime.record(peer, &rejection);
+                    return Err(ProcessError::Rejected(rejection));
+                }
+            };
+
+        let mut plaintext = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = self
+            .opener
+            .open(
+                &header,
+                &header.nonce(&self.salt),
+                encrypted_payload,
+                &mut plaintext,
+            )
+            .map_err(|e| {
+                match e {
+                    CryptoError::Unauthenticated => {
+                        self.runtime.record(peer, Outcome::Unauthenticated)
+                    }
+                    CryptoError::Parse(_) => self.runtime.record(peer, Outcome::ParseFailed),
+                    _ => (),
+                }
+                ProcessError::Crypto(e)
+            })?;
+
+        let key = RequestKey::from(&header);
+        let (version, reply) = if replayed {
+            let reply = table
+                .and_then(|table| table.get(&key).cloned())
+                .ok_or_else(|| {
+                    self.runtime.record(peer, Outcome::Replayed);
+                    ProcessError::Rejected(Rejection::Replayed)
+                })?;
+            let version = self
+                .runtime
+                .negotiate_version::<E>(header.version)
+                .unwrap_or(self.runtime.highest_version());
+            (version, reply)
+        } else {
+            self.replay_window = replay_window;
+            let (version, reply) = match self.runtime.negotiate_version(header.version) {
+                Ok(version) => {
+                    let request =
+                        CommandRequest::<C>::decode(version, &plaintext[..len]).map_err(|_| {
+                            self.runtime.record(peer, Outcome::DecodeFailed);
+                            ProcessError::Decode
+                        })?;
+                    let reply = self.runtime.handle_from(peer, request);
+                    (version, reply.echoing(header.frame_counter))
+                }
+                Err(reply) => (self.runtime.highest_version(), reply),
+            };
+            if let Some(table) = table {
+                table.insert(key, reply.clone());
+            }
+            (version, reply)
+        };
+        if header.is_broadcast() {
+            return Ok(None);
+        }
+
+        let mut plaintext = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let plaintext = reply
+            .encode(version, &mut plaintext)
+            .map_err(|_| ProcessError::Encode)?;
+        let header = Header {
+            version: version.get(),
+            source: header.source.opposite(),
+            frame_counter: self.frame_counter,
+            ..header
+        };
+        let mut encrypted_payload = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = self
+            .sealer
+            .seal_next(
+                &header,
+                &header.nonce(&self.salt),
+                plaintext,
+                &mut encrypted_payload,
+            )
+            .map_err(ProcessError::Crypto)?;
+        self.frame_counter = self.frame_counter.wrapping_add(1);
+
+        let frame = DataFrame::new(&header, &encrypted_payload[..len]);
+        let len = frame.to_bytes(out).map_err(|_| ProcessError::Encode)?;
+        Ok(Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{poll, Command, Event, FixedClock, CLIENT_KEY, SERVER_KEY};
+    use crate::{
+        CatchUpPolicy, CommandReply, EventLog, EventLogHandler, LoopbackTransport, Reply, Stats,
+    };
+    use flip_flop_data::{ProtocolVersion, ServerAddress, ServerPort};
+
+    const SALT: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    fn transport() -> LoopbackTransport {
+        LoopbackTransport::new(
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+            ServerAddress::new_unchecked(1),
+            ServerPort::new_unchecked(2),
+        )
+    }
+
+    fn deliver<H>(
+        transport: &mut LoopbackTransport,
+        endpoint: &mut ServerEndpoint<H>,
+        datagram: &[u8],
+    ) -> Result<Option<(Header, Reply<Event>)>, ProcessError>
+    where
+        H: CommandHandler<Command, Event>,
+    {
+        transport.deliver(endpoint, datagram)
+    }
+
+    fn request<H>(
+        transport: &mut LoopbackTransport,
+        endpoint: &mut ServerEndpoint<H>,
+        last_event_offset: u32,
+        command: Option<Command>,
+    ) -> Result<Option<(Header, Reply<Event>)>, ProcessError>
+    where
+        H: CommandHandler<Command, Event>,
+    {
+        transport.request(
+            endpoint,
+            &CommandRequest {
+                command,
+                ..poll(last_event_offset)
+            },
+        )
+    }
+
+    #[test]
+    fn test_process() {
+        let mut log = EventLog::new();
+        log.push(Event::Opened, 90);
+        log.push(Event::Opened, 95);
+        let runtime = ServerRuntime::new(EventLogHandler::<_, _, 4> {
+            log,
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        });
+        let config = AcceptConfig::builder(DataSource::Client)
+            .server_address(ServerAddress::new_unchecked(1))
+            .build();
+        let mut endpoint =
+            ServerEndpoint::new(runtime, config, CLIENT_KEY, SERVER_KEY, SALT).starting_at(10);
+        let mut transport = transport();
+
+        // A poll and a command are each replied to with the next event, and with
+        // successive frame counters. The reply echoes the frame counter of the
+        // request.
+        transport.header.frame_counter = 7;
+        let (header, reply) = request(&mut transport, &mut endpoint, 0, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (header.source, header.server_address, header.frame_counter),
+            (DataSource::Server, ServerAddress::new_unchecked(1), 10)
+        );
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply {
+                age,
+                frame_counter: 7,
+                offset: 1,
+                event: Some(Event::Opened),
+                ..
+            }) if age.get() == 5
+        ));
+        let (header, reply) = request(&mut transport, &mut endpoint, 1, Some(Command::Open))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.frame_counter, 11);
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply { event: None, .. })
+        ));
+        assert_eq!(endpoint.frame_counter(), 12);
+
+        // A replayed datagram is rejected.
+        let mut datagram = [0; 64];
+        let len = transport
+            .datagram(&poll::<Command>(1), &mut datagram)
+            .unwrap();
+        let datagram = &mut datagram[..len];
+        assert!(deliver(&mut transport, &mut endpoint, datagram).is_ok());
+        assert_eq!(
+            deliver(&mut transport, &mut endpoint, datagram),
+            Err(ProcessError::Rejected(Rejection::Replayed))
+        );
+
+        // A tampered datagram fails to open, and does not advance the replay
+        // window.
+        let mut tampered = [0; 64];
+        let len = transport
+            .datagram(&poll::<Command>(1), &mut tampered)
+            .unwrap();
+        let tampered = &mut tampered[..len];
+        tampered[len - 1] ^= 1;
+        assert_eq!(
+            deliver(&mut transport, &mut endpoint, tampered),
+            Err(ProcessError::Crypto(CryptoError::Unauthenticated))
+        );
+        transport.header.frame_counter -= 1;
+        assert!(request(&mut transport, &mut endpoint, 1, None)
+            .unwrap()
+            .is_some());
+
+        // Truncated datagrams fail to parse, and those sourced by a server are
+        // ignored.
+        assert!(matches!(
+            deliver(&mut transport, &mut endpoint, &datagram[..len - 1]),
+            Err(ProcessError::Parse(ParseError::Truncated { .. }))
+        ));
+        datagram[3] |= 0x04;
+        assert_eq!(deliver(&mut transport, &mut endpoint, datagram), Ok(None));
+    }
+
+    #[test]
+    fn test_retransmission_is_replied_from_table() {
+        // Each command handled is replied with the number handled so far.
+        let mut handled = 0;
+        let handler = |_: Option<Command>, _, _| {
+            handled += 1;
+            Reply::<Event>::EventsPending(handled)
+        };
+        let config = AcceptConfig::builder(DataSource::Client).build();
+        let mut endpoint = ServerEndpoint::new(
+            ServerRuntime::new(handler),
+            config,
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        let mut table = DedupTable::<Event, 4>::new();
+        let mut client = transport();
+        let request = CommandRequest {
+            command: Some(Command::Open),
+            ..poll(0)
+        };
+
+        // The datagram of a request is sent, and then again having lost its
+        // reply. The same reply is received, sealed with the next counter.
+        let mut datagram = [0; 64];
+        let len = client.datagram(&request, &mut datagram).unwrap();
+        let datagram = &mut datagram[..len];
+        let mut other = [0; 64];
+        let other_len = client
+            .datagram(
+                &CommandRequest {
+                    last_event_offset: 1,
+                    ..request
+                },
+                &mut other,
+            )
+            .unwrap();
+
+        let mut process = |endpoint: &mut ServerEndpoint<_>, datagram: &[u8]| {
+            let mut out = [0; 64];
+            endpoint
+                .process_with_dedup::<Command, _, 4>(datagram, &mut out, &mut table)
+                .map(|len| client.open::<Event>(&out[..len.unwrap()]).unwrap())
+        };
+        let (header, reply) = process(&mut endpoint, datagram).unwrap();
+        assert_eq!((header.frame_counter, reply), (0, Reply::EventsPending(1)));
+        let (header, reply) = process(&mut endpoint, datagram).unwrap();
+        assert_eq!((header.frame_counter, reply), (1, Reply::EventsPending(1)));
+        assert_eq!(endpoint.runtime().stats().replayed, 0);
+
+        // A forged retransmission fails to authenticate, and a replay of a
+        // request whose reply was not recorded is rejected.
+        datagram[len - 1] ^= 1;
+        assert_eq!(
+            process(&mut endpoint, datagram),
+            Err(ProcessError::Crypto(CryptoError::Unauthenticated))
+        );
+        let mut out = [0; 64];
+        assert!(endpoint
+            .process::<Command, Event>(&other[..other_len], &mut out)
+            .is_ok());
+        assert_eq!(
+            process(&mut endpoint, &other[..other_len]),
+            Err(ProcessError::Rejected(Rejection::Replayed))
+        );
+        assert_eq!(endpoint.runtime().stats().replayed, 1);
+
+        // Each request was handled once, however often it was received.
+        assert_eq!(handled, 2);
+    }
+
+    #[test]
+    fn test_process_records_stats() {
+        let handler = |_: Option<Command>, _, _| Reply::<Event>::Nack;
+        let config = AcceptConfig::builder(DataSource::Client)
+            .server_address(ServerAddress::new_unchecked(1))
+            .build();
+        let mut endpoint = ServerEndpoint::new(
+            ServerRuntime::new(handler),
+            config,
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        let mut transport = transport();
+        let request = poll::<Command>(0);
+        let stats = |endpoint: &ServerEndpoint<_>| *endpoint.runtime().stats();
+
+        // A replayed frame is counted as such...
+        let mut datagram = [0; 64];
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        assert!(deliver(&mut transport, &mut endpoint, &datagram[..len]).is_ok());
+        assert!(deliver(&mut transport, &mut endpoint, &datagram[..len]).is_err());
+        assert_eq!(stats(&endpoint).received, 2);
+        assert_eq!(stats(&endpoint).replayed, 1);
+
+        // ...as is a truncated one failing to parse...
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        assert!(deliver(&mut transport, &mut endpoint, &datagram[..len - 1]).is_err());
+        assert_eq!(stats(&endpoint).parse_failed, 1);
+
+        // ...and one for another server address, one failing to authenticate
+        // and one that is not a command request.
+        transport.header.server_address = ServerAddress::new_unchecked(2);
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        assert!(deliver(&mut transport, &mut endpoint, &datagram[..len]).is_err());
+        transport.header.server_address = ServerAddress::new_unchecked(1);
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        datagram[len - 1] ^= 1;
+        assert!(deliver(&mut transport, &mut endpoint, &datagram[..len]).is_err());
+
+        let len = transport.seal(&[], &mut datagram).unwrap();
+        assert_eq!(
+            deliver(&mut transport, &mut endpoint, &datagram[..len]),
+            Err(ProcessError::Decode)
+        );
+
+        assert_eq!(
+            stats(&endpoint),
+            Stats {
+                received: 6,
+                parse_failed: 1,
+                replayed: 1,
+                rejected: 1,
+                unauthenticated: 1,
+                decode_failed: 1,
+                stale: 0,
+                reply_truncated: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_records_stats_by_peer() {
+        let runtime = ServerRuntime::new(EventLogHandler::<Event, _, 4> {
+            log: EventLog::new(),
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        });
+        let config = AcceptConfig::builder(DataSource::Client)
+            .server_address(ServerAddress::new_unchecked(1))
+            .build();
+        let mut endpoint = ServerEndpoint::new(
+            runtime.tracking_peers::<1>(),
+            config,
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        endpoint
+            .runtime_mut()
+            .handler_mut()
+            .log
+            .push(Event::Opened, 100);
+        let mut transport = transport();
+        let peer = Peer::from(&transport.header);
+        let poll = |endpoint: &mut ServerEndpoint<_, 1>,
+                    transport: &mut LoopbackTransport,
+                    last_event_offset| {
+            let request = poll::<Command>(last_event_offset);
+            let mut datagram = [0; 64];
+            let len = transport.datagram(&request, &mut datagram).unwrap();
+            let mut out = [0; 64];
+            endpoint.process::<Command, Event>(&datagram[..len], &mut out)
+        };
+
+        // A client yet to receive an event is not stale, whereas one beyond
+        // those assigned is, as counted for its peer...
+        assert!(poll(&mut endpoint, &mut transport, 0).is_ok());
+        assert!(poll(&mut endpoint, &mut transport, 5).is_ok());
+        let stats = *endpoint.runtime().peer_stats().get(&peer).unwrap();
+        assert_eq!((stats.received, stats.stale), (2, 1));
+
+        // ...with those of peers beyond the number tracked, and of datagrams
+        // too short to be attributed, being counted as others.
+        transport.header.server_address = ServerAddress::new_unchecked(2);
+        assert!(poll(&mut endpoint, &mut transport, 0).is_err());
+        let mut out = [0; 64];
+        assert!(endpoint
+            .process::<Command, Event>(&[0; 2], &mut out)
+            .is_err());
+        let stats = endpoint.runtime().peer_stats();
+        assert_eq!(stats.iter().count(), 1);
+        let others = stats.others();
+        assert_eq!(
+            (others.received, others.rejected, others.parse_failed),
+            (2, 1, 1)
+        );
+        assert_eq!(endpoint.runtime().stats().received, 4);
+        assert_eq!(endpoint.runtime().stats().stale, 1);
+    }
+
+    #[test]
+    fn test_broadcast_is_not_replied() {
+        let commands = core::cell::Cell::new(0);
+        let handler = |command: Option<Command>, _, _| {
+            if command.is_some() {
+                commands.set(commands.get() + 1);
+            }
+            Reply::<Event>::Nack
+        };
+        let mut endpoints = [1, 2].map(|a| {
+            let config = AcceptConfig::builder(DataSource::Client)
+                .server_address(ServerAddress::new_unchecked(a))
+                .replay_protection(false)
+                .build();
+            ServerEndpoint::new(
+                ServerRuntime::new(handler),
+                config,
+                CLIENT_KEY,
+                SERVER_KEY,
+                SALT,
+            )
+        });
+        let mut transport = transport();
+
+        // A unicast request reaches only its addressee, which replies...
+        let mut datagram = [0; 64];
+        let request = CommandRequest {
+            command: Some(Command::Open),
+            ..poll(0)
+        };
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        let (header, reply) = deliver(&mut transport, &mut endpoints[0], &datagram[..len])
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.server_address, ServerAddress::new_unchecked(1));
+        assert_eq!(reply, Reply::Nack);
+        assert_eq!(
+            deliver(&mut transport, &mut endpoints[1], &datagram[..len]),
+            Err(ProcessError::Rejected(Rejection::UnexpectedServerAddress))
+        );
+        assert_eq!(commands.get(), 1);
+
+        // ...whereas a broadcast request reaches all, none of which reply.
+        transport.header.server_address = flip_flop_data::BROADCAST_ADDRESS;
+        let len = transport.datagram(&request, &mut datagram).unwrap();
+        for endpoint in &mut endpoints {
+            assert_eq!(
+                deliver(&mut transport, endpoint, &datagram[..len]),
+                Ok(None)
+            );
+        }
+        assert_eq!(commands.get(), 3);
+        assert_eq!(endpoints[0].frame_counter(), 1);
+        assert_eq!(endpoints[1].frame_counter(), 0);
+    }
+
+    #[test]
+    fn test_version_mismatch_is_replied() {
+        let handler = |_: Option<Command>, _, _| Reply::<Event>::Nack;
+        let config = AcceptConfig::builder(DataSource::Client)
+            .replay_protection(false)
+            .build();
+        let mut endpoint = ServerEndpoint::new(
+            ServerRuntime::new(handler).supporting_up_to(ProtocolVersion::V0),
+            config,
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        let mut transport = transport();
+
+        // A version 1 client is told that only version 0 is supported, in
+        // version 0, rather than its request failing to be interpreted...
+        transport.header.version = 1;
+        let (header, reply) = request(&mut transport, &mut endpoint, 0, Some(Command::Open))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.version, 0);
+        assert_eq!(
+            reply,
+            Reply::UnsupportedVersion {
+                highest: ProtocolVersion::V0
+            }
+        );
+
+        // ...and so downgrades to version 0, which is handled. Having no NACK,
+        // version 0 conveys that there are no more events.
+        transport.header.version = 0;
+        let (header, reply) = request(&mut transport, &mut endpoint, 0, Some(Command::Open))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.version, 0);
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply { event: None, .. })
+        ));
+
+        // A server supporting version 1 replies to each client in its own
+        // version.
+        let handler = |_: Option<Command>, _, _| Reply::<Event>::Nack;
+        let mut endpoint = ServerEndpoint::new(
+            ServerRuntime::new(handler),
+            AcceptConfig::builder(DataSource::Client)
+                .replay_protection(false)
+                .build(),
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        for version in [0, 1] {
+            transport.header.version = version;
+            let (header, reply) = request(&mut transport, &mut endpoint, 0, None)
+                .unwrap()
+                .unwrap();
+            assert_eq!(header.version, version);
+            assert_eq!(matches!(reply, Reply::Nack), version == 1);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use heapless::Deque;
+
+use crate::WireMessage;
+
+/// The bytes required to convey an event within a batch in addition to the
+/// event itself, being its offset and age.
+pub const BATCH_ENTRY_OVERHEAD: usize = 4 + 8;
+
+/// Which event a server replies to a client that is behind with, being several
+/// events short of the latest.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CatchUpPolicy {
+    /// The oldest event that the client has yet to receive, so that the client
+    /// receives every event in order of offset.
+    #[default]
+    OldestUnseen,
+    /// The latest event, skipping over those in between, for clients that are
+    /// only interested in the server's current state.
+    LatestOnly,
+}
+
+/// A server's history of events, retaining at most `N` of them. Once full,
+/// recording a new event evicts the oldest one. Each event is recorded along
+/// with the offset it is assigned and the time it occurred, in a form that
+/// [crate::event_reply] can reply with directly.
+///
+/// Offsets start at 0 and increment by one for each event recorded. Should an
+/// offset overflow to zero then all prior events are forgotten, as a client
+/// will also forget them in this situation.
+///
+/// The log also has an epoch, starting at 0, which is bumped each time that its
+/// events are forgotten, be it by [EventLog::clear] or by an offset overflowing.
+/// Replies convey the epoch so that clients can detect these resets.
+pub struct EventLog<E, T, const N: usize> {
+    events: Deque<(E, u32, T), N>,
+    next_offset: u32,
+    epoch: u16,
+}
+
+impl<E, T, const N: usize> EventLog<E, T, N> {
+    /// Create an empty event log.
+    pub const fn new() -> Self {
+        Self {
+            events: Deque::new(),
+            next_offset: 0,
+            epoch: 0,
+        }
+    }
+
+    /// Record an event that occurred at a given time, returning the
+    /// offset it has been assigned.
+    pub fn push(&mut self, event: E, time: T) -> u32 {
+        if self.next_offset == 0 && !self.events.is_empty() {
+            self.events.clear();
+            self.epoch = self.epoch.wrapping_add(1);
+        }
+        if self.events.is_full() {
+            let _ = self.events.pop_front();
+        }
+        let offset = self.next_offset;
+        let _ = self.events.push_back((event, offset, time));
+        self.next_offset = offset.wrapping_add(1);
+        offset
+    }
+
+    /// Forget all events, with the next one recorded being assigned an
+    /// offset of 0, and bump the epoch.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.next_offset = 0;
+        self.epoch = self.epoch.wrapping_add(1);
+    }
+
+    /// Forget the events with offsets before a given one of a given epoch, as
+    /// a client has acknowledged committing them, returning the number
+    /// forgotten. An acknowledgement of another epoch is ignored, as it is of
+    /// events since forgotten e.g. having been conveyed by a client unaware
+    /// that the log has since been cleared, and an offset beyond the next one
+    /// to be assigned cannot have been acknowledged of the events retained.
+    pub fn prune_before(&mut self, epoch: u16, offset: u32) -> usize {
+        if epoch != self.epoch || offset > self.next_offset {
+            return 0;
+        }
+        let mut pruned = 0;
+        while matches!(self.events.front(), Some((_, o, _)) if *o < offset) {
+            let _ = self.events.pop_front();
+            pruned += 1;
+        }
+        pruned
+    }
+
+    /// The number of times that events have been forgotten, wrapping after
+    /// 0xFFFF.
+    pub fn epoch(&self) -> u16 {
+        self.epoch
+    }
+
+    /// The offset that will be assigned to the next event recorded.
+    pub fn next_offset(&self) -> u32 {
+        self.next_offset
+    }
+
+    /// The number of events retained.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// True if there are no events retained.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// All events retained, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &(E, u32, T)> {
+        self.events.iter()
+    }
+
+    /// Up to `max` of the events with an offset greater than the one given,
+    /// in ascending order of offset i.e. the next events that a client
+    /// having last seen `offset` should receive.
+    pub fn events_after(&self, offset: u32, max: usize) -> impl Iterator<Item = &(E, u32, T)> {
+        self.events
+            .iter()
+            .skip_while(move |(_, o, _)| *o <= offset)
+            .take(max)
+    }
+
+    /// The event to reply to a client having last seen `offset` with, being
+    /// the next one after it, or the oldest one retained should the client have
+    /// an offset beyond any assigned e.g. as the log has since been cleared.
+    /// Only events satisfying a predicate are considered e.g. those that the
+    /// client subscribes to, with others being skipped.
+    pub fn next_event<P>(&self, offset: u32, predicate: P) -> Option<&(E, u32, T)>
+    where
+        P: FnMut(&E) -> bool,
+    {
+        self.select_event(offset, CatchUpPolicy::OldestUnseen, predicate)
+    }
+
+    /// The event to reply to a client having last seen `offset` with, as per
+    /// [EventLog::next_event], given a policy for clients that are behind. With
+    /// [CatchUpPolicy::LatestOnly], the event is the latest after the offset
+    /// rather than the next one, or the latest retained should the client have
+    /// an offset beyond any assigned.
+    pub fn select_event<P>(
+        &self,
+        offset: u32,
+        policy: CatchUpPolicy,
+        mut predicate: P,
+    ) -> Option<&(E, u32, T)>
+    where
+        P: FnMut(&E) -> bool,
+    {
+        let matching = |(e, _, _): &&(E, u32, T)| predicate(e);
+        // All events are candidates for a client beyond any offset assigned.
+        let seen = (offset < self.next_offset).then_some(offset);
+        let mut events = self
+            .iter()
+            .skip_while(move |(_, o, _)| seen.is_some_and(|seen| *o <= seen));
+        match policy {
+            CatchUpPolicy::OldestUnseen => events.find(matching),
+            CatchUpPolicy::LatestOnly => events.filter(matching).last(),
+        }
+    }
+}
+
+impl<E: WireMessage, T, const N: usize> EventLog<E, T, N> {
+    /// A batch of the events with an offset greater than the one given, in
+    /// ascending order of offset, along with the offset that the client should
+    /// acknowledge having received the batch. Events are included until the
+    /// next one would exceed a budget of bytes, where each event requires its
+    /// encoded length plus [BATCH_ENTRY_OVERHEAD].
+    ///
+    /// Events are not removed from the log, as a batch may be lost in transit
+    /// and need to be sent again.
+    pub fn drain_batch(
+        &self,
+        last_offset: u32,
+        budget_bytes: usize,
+    ) -> (heapless::Vec<&(E, u32, T), N>, u32) {
+        let mut batch = heapless::Vec::new();
+        let mut new_offset = last_offset;
+        let mut remaining = budget_bytes;
+        for entry in self.events_after(last_offset, N) {
+            let len = entry.0.encoded_len() + BATCH_ENTRY_OVERHEAD;
+            if len > remaining {
+                break;
+            }
+            remaining -= len;
+            new_offset = entry.1;
+            let _ = batch.push(entry);
+        }
+        (batch, new_offset)
+    }
+}
+
+impl<E, T, const N: usize> Default for EventLog<E, T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A brute-force equivalent of events_after, given every event ever
+    // recorded and the capacity of the log.
+    fn reference_events_after(
+        all_events: &[(char, u32, u64)],
+        capacity: usize,
+        offset: u32,
+        max: usize,
+    ) -> Vec<(char, u32, u64)> {
+        let retained = &all_events[all_events.len().saturating_sub(capacity)..];
+        let mut events = retained
+            .iter()
+            .filter(|(_, o, _)| *o > offset)
+            .cloned()
+            .collect::<Vec<_>>();
+        events.sort_by_key(|(_, o, _)| *o);
+        events.truncate(max);
+        events
+    }
+
+    #[test]
+    fn test_events_after() {
+        const CAPACITY: usize = 4;
+
+        let mut log = EventLog::<char, u64, CAPACITY>::new();
+        let mut all_events = Vec::new();
+
+        for (i, event) in ('a'..='j').enumerate() {
+            let time = (i * 10) as u64;
+            let offset = log.push(event, time);
+            assert_eq!(offset, i as u32);
+            all_events.push((event, offset, time));
+
+            for offset in 0..=(i as u32 + 1) {
+                for max in 0..=CAPACITY + 1 {
+                    assert_eq!(
+                        log.events_after(offset, max).cloned().collect::<Vec<_>>(),
+                        reference_events_after(&all_events, CAPACITY, offset, max),
+                        "offset {} max {} after {} events",
+                        offset,
+                        max,
+                        i + 1
+                    );
+                }
+            }
+        }
+
+        // The ring has wrapped, so the oldest retained offset is no longer 0.
+        assert_eq!(log.iter().next(), Some(&('g', 6, 60)));
+        assert_eq!(
+            log.events_after(0, 2).cloned().collect::<Vec<_>>(),
+            [('g', 6, 60), ('h', 7, 70)]
+        );
+    }
+
+    #[test]
+    fn test_next_event_after_wraparound() {
+        let mut log = EventLog::<char, u64, 3>::new();
+        for (t, e) in ('a'..='e').enumerate() {
+            log.push(e, t as u64);
+        }
+
+        // 'a' and 'b' have been evicted to make room, oldest first.
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.iter().next(), Some(&('c', 2, 2)));
+
+        // A client that has fallen behind the evictions resumes from the
+        // oldest retained, and one that is up to date has no next event.
+        assert_eq!(log.next_event(0, |_| true), Some(&('c', 2, 2)));
+        assert_eq!(log.next_event(2, |_| true), Some(&('d', 3, 3)));
+        assert_eq!(log.next_event(4, |_| true), None);
+
+        // A client beyond any offset assigned is replied the oldest.
+        assert_eq!(log.next_event(9, |_| true), Some(&('c', 2, 2)));
+
+        // Events not satisfying the predicate are skipped.
+        assert_eq!(log.next_event(2, |e| *e == 'e'), Some(&('e', 4, 4)));
+        assert_eq!(log.next_event(9, |e| *e != 'c'), Some(&('d', 3, 3)));
+        assert_eq!(log.next_event(0, |_| false), None);
+    }
+
+    #[test]
+    fn test_catch_up_policies() {
+        let mut log = EventLog::<char, u64, 8>::new();
+        for (t, e) in ('a'..='f').enumerate() {
+            log.push(e, t as u64);
+        }
+        let select = |offset, policy, predicate: fn(&char) -> bool| {
+            log.select_event(offset, policy, predicate)
+                .map(|(e, _, _)| *e)
+        };
+
+        // A client several events behind is replied the next event it has yet
+        // to receive, or the latest, skipping those in between.
+        assert_eq!(select(1, CatchUpPolicy::OldestUnseen, |_| true), Some('c'));
+        assert_eq!(select(1, CatchUpPolicy::LatestOnly, |_| true), Some('f'));
+
+        // Only events satisfying the predicate are considered by either.
+        let vowel_or_d = |e: &char| "aeiou".contains(*e) || *e == 'd';
+        assert_eq!(
+            select(1, CatchUpPolicy::OldestUnseen, vowel_or_d),
+            Some('d')
+        );
+        assert_eq!(select(1, CatchUpPolicy::LatestOnly, vowel_or_d), Some('e'));
+
+        // Up to date clients have no event under either, whereas those beyond
+        // any offset assigned are replied the oldest or latest retained.
+        for policy in [CatchUpPolicy::OldestUnseen, CatchUpPolicy::LatestOnly] {
+            assert_eq!(select(5, policy, |_| true), None);
+        }
+        assert_eq!(select(9, CatchUpPolicy::OldestUnseen, |_| true), Some('a'));
+        assert_eq!(select(9, CatchUpPolicy::LatestOnly, |_| true), Some('f'));
+        assert_eq!(CatchUpPolicy::default(), CatchUpPolicy::OldestUnseen);
+    }
+
+    #[test]
+    fn test_drain_batch() {
+        // Events encoded as their length.
+        struct Event(usize);
+
+        impl WireMessage for Event {
+            const MAX_ENCODED_LEN: usize = 8;
+
+            fn encoded_len(&self) -> usize {
+                self.0
+            }
+        }
+
+        let mut log = EventLog::<Event, u64, 8>::new();
+        for len in [1, 2, 3, 4, 5] {
+            log.push(Event(len), 0);
+        }
+
+        let lens = |(batch, new_offset): (heapless::Vec<&(Event, u32, u64), 8>, u32)| {
+            (
+                batch
+                    .iter()
+                    .map(|(e, _, _)| e.0)
+                    .collect::<std::vec::Vec<_>>(),
+                new_offset,
+            )
+        };
+
+        // Exactly enough for offsets 1 and 2, and not enough for 3.
+        let budget = 2 + 3 + 2 * BATCH_ENTRY_OVERHEAD;
+        assert_eq!(lens(log.drain_batch(0, budget)), (vec![2, 3], 2));
+        assert_eq!(lens(log.drain_batch(0, budget + 3)), (vec![2, 3], 2));
+
+        // Continuing from what was acknowledged.
+        assert_eq!(lens(log.drain_batch(2, budget + 4)), (vec![4, 5], 4));
+
+        // The next event exceeds the budget, so the offset does not advance.
+        assert_eq!(
+            lens(log.drain_batch(0, BATCH_ENTRY_OVERHEAD + 1)),
+            (vec![], 0)
+        );
+        assert_eq!(lens(log.drain_batch(4, 1000)), (vec![], 4));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut log = EventLog::<char, u64, 2>::new();
+        log.push('a', 0);
+        log.push('b', 1);
+        assert_eq!(log.epoch(), 0);
+        log.clear();
+        assert!(log.is_empty());
+        assert_eq!(log.epoch(), 1);
+        assert_eq!(log.push('c', 2), 0);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.epoch(), 1);
+    }
+
+    #[test]
+    fn test_prune_before() {
+        let mut log = EventLog::<char, u64, 4>::new();
+        for (t, e) in ('a'..='f').enumerate() {
+            log.push(e, t as u64);
+        }
+
+        // The ring has wrapped, retaining offsets 2..=5. Acknowledging those
+        // before 4 forgets 2 and 3, retaining those unacknowledged.
+        assert_eq!(log.prune_before(0, 1), 0);
+        assert_eq!(log.prune_before(0, 4), 2);
+        assert_eq!(
+            log.iter().cloned().collect::<Vec<_>>(),
+            [('e', 4, 4), ('f', 5, 5)]
+        );
+
+        // Capacity freed is used by the next events recorded, with none being
+        // evicted before they are acknowledged.
+        log.push('g', 6);
+        log.push('h', 7);
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.iter().next(), Some(&('e', 4, 4)));
+
+        // An acknowledgement beyond any offset assigned is ignored, whereas
+        // one of every event retained forgets them all.
+        assert_eq!(log.prune_before(0, 9), 0);
+        assert_eq!(log.prune_before(0, 8), 4);
+        assert!(log.is_empty());
+        assert_eq!(log.next_offset(), 8);
+
+        // Once cleared, an acknowledgement of the prior epoch is ignored even
+        // though its offset has since been assigned.
+        log.clear();
+        for (t, e) in ('a'..='d').enumerate() {
+            log.push(e, t as u64);
+        }
+        assert_eq!(log.prune_before(0, 3), 0);
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.prune_before(1, 3), 3);
+    }
+
+    #[test]
+    fn test_overflow_bumps_epoch() {
+        let mut log = EventLog::<char, u64, 2>::new();
+        log.next_offset = u32::MAX;
+        assert_eq!(log.push('a', 0), u32::MAX);
+        assert_eq!(log.epoch(), 0);
+        assert_eq!(log.push('b', 1), 0);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.epoch(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+//! Fixtures shared by the tests of the crate's modules.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Clock, CommandRequest, Discriminant, DiscriminantSet, WireMessage};
+
+#[cfg(feature = "endpoint")]
+pub const CLIENT_KEY: &[u8; 16] = b"0123456789ABCDEF";
+#[cfg(feature = "endpoint")]
+pub const SERVER_KEY: &[u8; 16] = b"FEDCBA9876543210";
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub enum Command {
+    Open,
+    Close,
+    Erase,
+    Toggle,
+}
+
+impl Discriminant for Command {
+    fn discriminant(&self) -> u8 {
+        match self {
+            Command::Open => 0,
+            Command::Close => 1,
+            Command::Erase => 2,
+            Command::Toggle => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Event {
+    Started,
+    Opened,
+    Toggled(u8),
+}
+
+impl Discriminant for Event {
+    fn discriminant(&self) -> u8 {
+        match self {
+            Event::Started => 0,
+            Event::Opened => 1,
+            Event::Toggled(_) => 2,
+        }
+    }
+}
+
+impl WireMessage for Event {
+    const MAX_ENCODED_LEN: usize = 2;
+}
+
+// Events discriminated by their position within the alphabet.
+impl Discriminant for char {
+    fn discriminant(&self) -> u8 {
+        (u32::from(*self) % 32) as u8
+    }
+}
+
+pub struct FixedClock;
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        100
+    }
+}
+
+/// A request polling for the event following an offset, subscribing to all
+/// events and acknowledging none.
+pub fn poll<C: DeserializeOwned + Serialize>(last_event_offset: u32) -> CommandRequest<C> {
+    CommandRequest {
+        last_event_offset,
+        subscriptions: DiscriminantSet::ALL,
+        acked_offset: 0,
+        acked_epoch: 0,
+        command: None,
+    }
+}
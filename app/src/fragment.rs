@@ -0,0 +1,406 @@
+use flip_flop_data::{DataSource, Header, ServerAddress, ServerPort};
+use heapless::Vec;
+
+use crate::Clock;
+
+/// The bytes that prefix the data of each fragment within its payload, being
+/// the fragment's index followed by the total number of fragments.
+pub const FRAGMENT_PREFIX_LEN: usize = 2;
+
+/// The greatest number of fragments that a payload may be split into.
+pub const MAX_FRAGMENTS: usize = 32;
+
+/// The reasons that a payload may fail to be fragmented or reassembled.
+#[derive(Debug, PartialEq)]
+pub enum FragmentError {
+    /// The fragment's prefix is missing or invalid, or the fragment is
+    /// inconsistent with others of the same payload.
+    Malformed,
+    /// The payload requires more than [MAX_FRAGMENTS] fragments, or is longer
+    /// than a buffer to hold it.
+    TooLong,
+}
+
+/// A part of a payload, to be conveyed within a data frame of its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment<'a> {
+    /// The position of this fragment within the payload, from 0.
+    pub index: u8,
+    /// The number of fragments that the payload is split into.
+    pub total: u8,
+    /// The part of the payload conveyed.
+    pub data: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    /// Write this fragment to a buffer as the payload of a data frame, being
+    /// the index and total followed by the data, returning the number of bytes
+    /// written.
+    ///
+    /// | 0     | 1     | ..   |
+    /// +-------+-------+------+
+    /// | index | total | data |
+    ///
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize, FragmentError> {
+        let len = FRAGMENT_PREFIX_LEN + self.data.len();
+        let buf = buf.get_mut(..len).ok_or(FragmentError::TooLong)?;
+        buf[0] = self.index;
+        buf[1] = self.total;
+        buf[FRAGMENT_PREFIX_LEN..].copy_from_slice(self.data);
+        Ok(len)
+    }
+
+    /// Read a fragment from the payload of a data frame, as written by
+    /// [Fragment::write].
+    pub fn read(payload: &'a [u8]) -> Result<Self, FragmentError> {
+        match payload {
+            [index, total, data @ ..] if index < total && *total as usize <= MAX_FRAGMENTS => {
+                Ok(Self {
+                    index: *index,
+                    total: *total,
+                    data,
+                })
+            }
+            _ => Err(FragmentError::Malformed),
+        }
+    }
+}
+
+/// The fragments of a payload, in order, as returned by [fragments].
+#[derive(Clone, Debug)]
+pub struct Fragments<'a> {
+    chunks: core::slice::Chunks<'a, u8>,
+    index: u8,
+    total: u8,
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.total {
+            return None;
+        }
+        let fragment = Fragment {
+            index: self.index,
+            total: self.total,
+            data: self.chunks.next().unwrap_or(&[]),
+        };
+        self.index += 1;
+        Some(fragment)
+    }
+}
+
+/// Split a payload that is too long for one datagram into fragments whose
+/// data is at most `fragment_len` bytes, with only the last fragment being
+/// shorter. Each fragment is to be written with [Fragment::write] and conveyed
+/// within its own data frame, and so the frame's payload requires
+/// [FRAGMENT_PREFIX_LEN] bytes more than the fragment's data, plus any MAC.
+///
+/// The frames of a payload are sent with consecutive frame counters, with the
+/// fragment at index 0 having the first, so that each frame still has a nonce
+/// of its own. A [Reassembler] then identifies the fragments of a payload by
+/// their source, server address and port, and the frame counter of the first.
+pub fn fragments(payload: &[u8], fragment_len: usize) -> Result<Fragments<'_>, FragmentError> {
+    if fragment_len == 0 {
+        return Err(FragmentError::Malformed);
+    }
+    let total = payload.len().div_ceil(fragment_len).max(1);
+    if total > MAX_FRAGMENTS {
+        return Err(FragmentError::TooLong);
+    }
+    Ok(Fragments {
+        chunks: payload.chunks(fragment_len),
+        index: 0,
+        total: total as u8,
+    })
+}
+
+type PayloadKey = (DataSource, ServerAddress, ServerPort, u16);
+
+struct Partial<const N: usize> {
+    key: PayloadKey,
+    total: u8,
+    received: u32,
+    len: usize,
+    deadline: u64,
+    buf: [u8; N],
+}
+
+/// Reassembles the payloads split by [fragments], buffering the fragments
+/// received for up to `M` payloads at once, each of at most `N` bytes. The
+/// fragment length is to be the same as the sender's, so that the position of
+/// each fragment within its payload is known whatever order they arrive in.
+///
+/// Duplicated fragments are ignored. Payloads whose fragments have not all
+/// arrived within a timeout of the first are discarded, as are the oldest ones
+/// when fragments arrive for another payload and there is no room to buffer it.
+pub struct Reassembler<K, const M: usize, const N: usize> {
+    clock: K,
+    fragment_len: usize,
+    timeout: u64,
+    partials: Vec<Partial<N>, M>,
+}
+
+impl<K: Clock, const M: usize, const N: usize> Reassembler<K, M, N> {
+    /// Create a reassembler for fragments of a given length, discarding
+    /// payloads that remain incomplete after a number of ticks of a clock.
+    pub fn new(clock: K, fragment_len: usize, timeout: u64) -> Self {
+        Self {
+            clock,
+            fragment_len,
+            timeout,
+            partials: Vec::new(),
+        }
+    }
+
+    /// The number of payloads that have fragments yet to arrive.
+    pub fn pending(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Discard the payloads whose timeout has elapsed, returning how many
+    /// were discarded.
+    pub fn expire(&mut self) -> usize {
+        let now = self.clock.now();
+        let before = self.partials.len();
+        self.partials.retain(|p| now < p.deadline);
+        before - self.partials.len()
+    }
+
+    /// Handle the payload of a data frame having been parsed, and opened if
+    /// sealed, as a fragment. Once all of a payload's fragments have arrived,
+    /// the payload is written to `out` and its length returned.
+    pub fn receive(
+        &mut self,
+        header: &Header,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<Option<usize>, FragmentError> {
+        let fragment = Fragment::read(payload)?;
+        let is_last = fragment.index + 1 == fragment.total;
+        if fragment.data.len() > self.fragment_len
+            || (!is_last && fragment.data.len() != self.fragment_len)
+        {
+            return Err(FragmentError::Malformed);
+        }
+        let start = fragment.index as usize * self.fragment_len;
+        let end = start + fragment.data.len();
+        if end > N {
+            return Err(FragmentError::TooLong);
+        }
+
+        self.expire();
+        let key = (
+            header.source,
+            header.server_address,
+            header.server_port,
+            header.frame_counter.wrapping_sub(fragment.index as u16),
+        );
+        let i = match self.partials.iter().position(|p| p.key == key) {
+            Some(i) => i,
+            None => {
+                if self.partials.is_full() {
+                    let oldest =
+                        (0..self.partials.len()).min_by_key(|&i| self.partials[i].deadline);
+                    if let Some(oldest) = oldest {
+                        self.partials.swap_remove(oldest);
+                    }
+                }
+                let partial = Partial {
+                    key,
+                    total: fragment.total,
+                    received: 0,
+                    len: 0,
+                    deadline: self.clock.now().saturating_add(self.timeout),
+                    buf: [0; N],
+                };
+                if self.partials.push(partial).is_err() {
+                    return Err(FragmentError::TooLong);
+                }
+                self.partials.len() - 1
+            }
+        };
+
+        let partial = &mut self.partials[i];
+        if partial.total != fragment.total {
+            return Err(FragmentError::Malformed);
+        }
+        let bit = 1 << fragment.index;
+        if partial.received & bit != 0 {
+            return Ok(None);
+        }
+        partial.received |= bit;
+        partial.buf[start..end].copy_from_slice(fragment.data);
+        if is_last {
+            partial.len = end;
+        }
+
+        if partial.received.count_ones() < partial.total as u32 {
+            return Ok(None);
+        }
+        let partial = self.partials.swap_remove(i);
+        let out = out.get_mut(..partial.len).ok_or(FragmentError::TooLong)?;
+        out.copy_from_slice(&partial.buf[..partial.len]);
+        Ok(Some(partial.len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use flip_flop_data::DataFrame;
+
+    struct TestClock<'a>(&'a Cell<u64>);
+
+    impl Clock for TestClock<'_> {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn header(server_address: u8, frame_counter: u16) -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(server_address),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter,
+        }
+    }
+
+    // Write each fragment of a payload into a datagram of its own, with
+    // consecutive frame counters.
+    fn datagrams(payload: &[u8], frame_counter: u16) -> std::vec::Vec<std::vec::Vec<u8>> {
+        fragments(payload, 4)
+            .unwrap()
+            .map(|fragment| {
+                let header = header(1, frame_counter.wrapping_add(fragment.index as u16));
+                let mut plaintext = [0; 8];
+                let len = fragment.write(&mut plaintext).unwrap();
+                let mut datagram = [0; 16];
+                let len = DataFrame::new(&header, &plaintext[..len])
+                    .to_bytes(&mut datagram)
+                    .unwrap();
+                datagram[..len].to_vec()
+            })
+            .collect()
+    }
+
+    fn receive<K: Clock>(
+        reassembler: &mut Reassembler<K, 2, 16>,
+        datagram: &[u8],
+        out: &mut [u8],
+    ) -> Result<Option<usize>, FragmentError> {
+        let (header, payload) = DataFrame::from_bytes(datagram).unwrap().parse().unwrap();
+        reassembler.receive(&header, payload, out)
+    }
+
+    #[test]
+    fn test_fragments() {
+        let fragments = fragments(b"0123456789", 4)
+            .unwrap()
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(
+            fragments
+                .iter()
+                .map(|f| (f.index, f.total, f.data))
+                .collect::<std::vec::Vec<_>>(),
+            [
+                (0, 3, b"0123".as_slice()),
+                (1, 3, b"4567".as_slice()),
+                (2, 3, b"89".as_slice())
+            ]
+        );
+
+        let mut buf = [0; 8];
+        let len = fragments[2].write(&mut buf).unwrap();
+        assert_eq!(&buf[..len], [2, 3, b'8', b'9']);
+        assert_eq!(Fragment::read(&buf[..len]), Ok(fragments[2]));
+
+        assert_eq!(super::fragments(&[], 4).unwrap().count(), 1);
+        assert!(matches!(
+            super::fragments(&[0; 33], 1),
+            Err(FragmentError::TooLong)
+        ));
+        assert_eq!(Fragment::read(&[3, 3]), Err(FragmentError::Malformed));
+        assert_eq!(Fragment::read(&[0]), Err(FragmentError::Malformed));
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let now = Cell::new(0);
+        let mut reassembler = Reassembler::<_, 2, 16>::new(TestClock(&now), 4, 10);
+        let datagrams = datagrams(b"0123456789", u16::MAX);
+        assert_eq!(datagrams.len(), 3);
+
+        // The fragments arrive out of order, with one of them duplicated.
+        let mut out = [0; 16];
+        for i in [2, 0, 2] {
+            assert_eq!(receive(&mut reassembler, &datagrams[i], &mut out), Ok(None));
+        }
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(
+            receive(&mut reassembler, &datagrams[1], &mut out),
+            Ok(Some(10))
+        );
+        assert_eq!(&out[..10], b"0123456789");
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_incomplete_payload_is_dropped() {
+        let now = Cell::new(0);
+        let mut reassembler = Reassembler::<_, 2, 16>::new(TestClock(&now), 4, 10);
+        let first = datagrams(b"0123456789", 100);
+        let second = datagrams(b"abcdefgh", 200);
+
+        // The final fragment of the first payload never arrives, and so it is
+        // discarded once timed out, whereas the second payload completes.
+        let mut out = [0; 16];
+        for datagram in &first[..2] {
+            assert_eq!(receive(&mut reassembler, datagram, &mut out), Ok(None));
+        }
+        now.set(9);
+        assert_eq!(reassembler.expire(), 0);
+        assert_eq!(receive(&mut reassembler, &second[0], &mut out), Ok(None));
+        now.set(10);
+        assert_eq!(reassembler.expire(), 1);
+        assert_eq!(receive(&mut reassembler, &second[1], &mut out), Ok(Some(8)));
+        assert_eq!(&out[..8], b"abcdefgh");
+
+        // The final fragment arriving late begins a payload that never
+        // completes.
+        assert_eq!(receive(&mut reassembler, &first[2], &mut out), Ok(None));
+        now.set(20);
+        assert_eq!(reassembler.expire(), 1);
+    }
+
+    #[test]
+    fn test_malformed_fragments() {
+        let now = Cell::new(0);
+        let mut reassembler = Reassembler::<_, 2, 16>::new(TestClock(&now), 4, 10);
+        let mut out = [0; 16];
+
+        // A fragment other than the last must be the full length...
+        assert_eq!(
+            reassembler.receive(&header(1, 0), &[0, 2, 1, 2], &mut out),
+            Err(FragmentError::Malformed)
+        );
+        // ...and the payload must fit within the buffer.
+        assert_eq!(
+            reassembler.receive(&header(1, 4), &[4, 5, 1], &mut out),
+            Err(FragmentError::TooLong)
+        );
+        // Fragments of the same payload must agree on the total.
+        assert_eq!(
+            reassembler.receive(&header(1, 0), &[0, 2, 1, 2, 3, 4], &mut out),
+            Ok(None)
+        );
+        assert_eq!(
+            reassembler.receive(&header(1, 1), &[1, 3, 1, 2, 3, 4], &mut out),
+            Err(FragmentError::Malformed)
+        );
+    }
+}
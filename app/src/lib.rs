@@ -1,17 +1,181 @@
 #![cfg_attr(not(test), no_std)]
 #![doc = include_str!("../../README.md")]
+//! # Example
+//!
+//! A complete exchange between a client and a server, with commands and events
+//! conveyed within data frames whose payloads are sealed with the `crypto`
+//! feature of `flip-flop-data`. Each direction is sealed with its own key, and
+//! with a nonce that varies with the frame counter, so that no nonce is ever
+//! reused with a key.
+//!
+//! ```
+//! use flip_flop_app::{event_reply, CommandReply, CommandRequest, DiscriminantSet, Reply};
+//! use flip_flop_data::{
+//!     crypto::{Opener, Sealer},
+//!     DataFrame, DataSource, Header, ProtocolVersion, ServerAddress, ServerPort,
+//! };
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! enum Command {
+//!     Open,
+//! }
+//!
+//! #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+//! enum Event {
+//!     Opened,
+//! }
+//!
+//! // The keys and nonce salt are shared by the client and server out of band.
+//! let client_key = b"0123456789ABCDEF";
+//! let server_key = b"FEDCBA9876543210";
+//! let salt = [1, 2, 3, 4, 5, 6];
+//!
+//! // The client seals a command into a datagram...
+//! let header = Header {
+//!     version: ProtocolVersion::V1.get(),
+//!     source: DataSource::Client,
+//!     server_address: ServerAddress::new_unchecked(1),
+//!     server_port: ServerPort::new_unchecked(0),
+//!     frame_counter: 1,
+//! };
+//! let request = CommandRequest {
+//!     last_event_offset: 0,
+//!     subscriptions: DiscriminantSet::ALL,
+//!     acked_offset: 0,
+//!     acked_epoch: 0,
+//!     command: Some(Command::Open),
+//! };
+//! let mut plaintext = [0; 32];
+//! let plaintext = request.encode(ProtocolVersion::V1, &mut plaintext).unwrap();
+//! let mut encrypted_payload = [0; 32];
+//! let len = Sealer::new(client_key)
+//!     .seal_next(&header, &header.nonce(&salt), plaintext, &mut encrypted_payload)
+//!     .unwrap();
+//! let frame = DataFrame::new(&header, &encrypted_payload[..len]);
+//! let mut datagram = [0; 64];
+//! let len = frame.to_bytes(&mut datagram).unwrap();
+//! let datagram = &datagram[..len];
+//!
+//! // ...which the server decodes and opens...
+//! let (header, encrypted_payload) = DataFrame::from_bytes(datagram).unwrap().parse().unwrap();
+//! let mut plaintext = [0; 32];
+//! let len = Opener::new(client_key)
+//!     .open(&header, &header.nonce(&salt), encrypted_payload, &mut plaintext)
+//!     .unwrap();
+//! let version = header.protocol_version().unwrap();
+//! let request = CommandRequest::<Command>::decode(version, &plaintext[..len]).unwrap();
+//! assert_eq!(request.command, Some(Command::Open));
+//!
+//! // ...and replies with an event, sealed into a datagram of its own...
+//! let event = (Event::Opened, request.last_event_offset + 1, 0);
+//! let reply = Reply::Event(event_reply(Some(&event), |t| t)).echoing(header.frame_counter);
+//! let header = Header {
+//!     source: DataSource::Server,
+//!     frame_counter: 1,
+//!     ..header
+//! };
+//! let mut plaintext = [0; 32];
+//! let plaintext = reply.encode(version, &mut plaintext).unwrap();
+//! let mut encrypted_payload = [0; 32];
+//! let len = Sealer::new(server_key)
+//!     .seal_next(&header, &header.nonce(&salt), plaintext, &mut encrypted_payload)
+//!     .unwrap();
+//! let frame = DataFrame::new(&header, &encrypted_payload[..len]);
+//! let mut datagram = [0; 64];
+//! let len = frame.to_bytes(&mut datagram).unwrap();
+//! let datagram = &datagram[..len];
+//!
+//! // ...which the client decodes and opens.
+//! let (header, encrypted_payload) = DataFrame::from_bytes(datagram).unwrap().parse().unwrap();
+//! let mut plaintext = [0; 32];
+//! let len = Opener::new(server_key)
+//!     .open(&header, &header.nonce(&salt), encrypted_payload, &mut plaintext)
+//!     .unwrap();
+//! let reply = Reply::<Event>::decode(version, &plaintext[..len]).unwrap();
+//! assert!(matches!(
+//!     reply,
+//!     Reply::Event(CommandReply { offset: 1, event: Some(Event::Opened), frame_counter: 1, .. })
+//! ));
+//! ```
 
+use flip_flop_data::ProtocolVersion;
 use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 
+mod accept;
+mod batch;
+#[cfg(feature = "client")]
+mod client;
+mod clock;
+mod compat;
+mod dedup;
+mod discriminant;
+#[cfg(feature = "endpoint")]
+mod endpoint;
+mod event_log;
+#[cfg(test)]
+mod fixtures;
+mod fragment;
+#[cfg(feature = "endpoint")]
+mod loopback;
+mod reader;
+mod replay;
+mod role;
+mod routing;
+mod sequence;
+mod server;
+mod session;
+mod stats;
+mod wire;
+
+pub use accept::{accept_datagram, accept_frame, AcceptConfig, AcceptConfigBuilder, Rejection};
+pub use batch::{event_reply_batch, BatchReply};
+#[cfg(feature = "client")]
+pub use client::{Client, ClientError};
+pub use clock::Clock;
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use compat::{decode_event_reply, decode_reply, CompatEvent, CompatEventReply, CompatReply};
+pub use dedup::{DedupTable, RequestKey};
+pub use discriminant::{Discriminant, DiscriminantSet};
+#[cfg(feature = "endpoint")]
+pub use endpoint::{ProcessError, ServerEndpoint};
+pub use event_log::{CatchUpPolicy, EventLog, BATCH_ENTRY_OVERHEAD};
+pub use fragment::{
+    fragments, Fragment, FragmentError, Fragments, Reassembler, FRAGMENT_PREFIX_LEN, MAX_FRAGMENTS,
+};
+#[cfg(feature = "endpoint")]
+pub use loopback::LoopbackTransport;
+pub use reader::{FrameReader, Frames, MAX_FRAME_SIZE};
+pub use replay::{CounterResync, ReplayGuard, ReplayWindow};
+pub use role::RoleFilter;
+pub use routing::{AddressedReply, AddressedRequest};
+pub use sequence::{classify_epoch_offset, classify_offset, OffsetTransition};
+pub use server::{CommandHandler, EventLogHandler, Server, ServerRuntime};
+pub use session::{ControlCommand, SessionResets};
+pub use stats::{Outcome, Peer, PeerStats, Stats};
+pub use wire::{max_frame_size, max_payload_len, Datagram, WireMessage};
+
 /// A Command may only be sent by a client, of which there is only one
 /// client on the bus. Command requests take a type that provides their
 /// command; usually an enum. Command requests convey the last [EventReply]
 /// offset that the client has processed for the associated server, starting at
-/// 0 as the default.
+/// 0 as the default, along with the set of event discriminants that the client
+/// subscribes to, and the offset up to which it has committed events so that
+/// the server can forget them.
 /// Note that the addressing of servers is left to a lower layer e.g. UDP, or a
 /// serial-based transport.
 ///
-/// A CommandRequest has the following little endian byte layout:
+/// A CommandRequest has the following little endian byte layout in
+/// [ProtocolVersion::V1], as per [CommandRequest::encode]:
+///
+/// | 0 | 1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 | 9 | A | B | C | D |    ..   |
+/// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---------+
+/// |     offset    | subscriptions |  acked_offset | epoch | command |
+///
+/// [ProtocolVersion::V0] has the original layout, conveying neither
+/// subscriptions nor an acknowledgement. A server decodes such a request as
+/// subscribing to all events and acknowledging none:
 ///
 /// | 0 | 1 | 2 | 3 |    ..   |
 /// +---+---+---+---+---------+
@@ -21,6 +185,19 @@ use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializ
 pub struct CommandRequest<C: DeserializeOwned + Serialize> {
     /// The last offset of the server recorded by the client.
     pub last_event_offset: u32,
+    /// The discriminants of the events that the client wishes to receive e.g.
+    /// [DiscriminantSet::ALL]. A server skips over other events when replying,
+    /// as though the client had received them.
+    pub subscriptions: DiscriminantSet,
+    /// The offset following that of the last event that the client has
+    /// committed, being distinct from the last one received, such that the
+    /// server may forget the events before it. 0 acknowledges none.
+    pub acked_offset: u32,
+    /// The epoch of the events acknowledged, as conveyed by [CommandReply::epoch].
+    /// A server ignores acknowledgements of another epoch, as their offsets
+    /// are of events that it has since forgotten, and would otherwise forget
+    /// events of the new epoch that the client has yet to receive.
+    pub acked_epoch: u16,
     /// The command to issue, or None if we wish to just get the next event
     /// available.
     #[serde(
@@ -30,15 +207,80 @@ pub struct CommandRequest<C: DeserializeOwned + Serialize> {
     pub command: Option<C>,
 }
 
-/// An EventRequest may only be emitted by a server, of which there can be many, and
-/// only in relation to having received a [CommandRequest] from a client. Event requests
-/// take a type that provides their identifier; usually an enum. Event requests convey
-/// the offset they are associated with. If an offset overflows to zero then it is the
-/// server's responsibility to convey any important events that the client may need.
-/// It is the client's responsibility to clear state in relation to previous events when
-/// an offset less than or equal to the one it requested. An event request also conveys a
-/// delta in time in a form that the client and its servers understand, and relative to
-/// the server's current notion of time.
+/// A CommandReply may only be emitted by a server, of which there can be many, and
+/// only in relation to having received a [CommandRequest] from a client. Command
+/// replies take a type that provides their events; usually an enum. Command replies
+/// convey the offset of the event they are associated with. If an offset overflows to
+/// zero then it is the server's responsibility to convey any important events that the
+/// client may need. It is the client's responsibility to clear state in relation to
+/// previous events when an offset less than or equal to the one it requested. A command
+/// reply also conveys the age of the event in whole seconds, relative to the server's
+/// current notion of time, and echoes the frame counter of the request it replies to.
+///
+/// Offsets are conveyed along with the epoch of the server's events, being bumped
+/// each time that the server forgets them. A client comparing both, e.g. with
+/// [classify_epoch_offset], can then tell a reset from normal progress even
+/// when the offset following it coincides with one previously received.
+///
+/// A CommandReply has the following little endian byte layout in
+/// [ProtocolVersion::V1], following the variant of its [Reply]:
+///
+/// | 0 | 1 | 2 | 3 |  4   | 5 | 6 | 7 | 8 | 9 | A |  ..   |
+/// +---+---+---+---+------+---+---+---+---+---+---+-------+
+/// |  age  | frame | time | epoch |     offset    | event |
+///
+/// where time is 0 as the server's current time is not conveyed. A server may
+/// also convey its time, as per [Server::conveying_server_time], so that a
+/// client may estimate round-trip times and the offset between its clock and
+/// the server's. Time is then 1, and followed by the server's time:
+///
+/// | 0 | 1 | 2 | 3 |  4   | 5 | 6 | 7 | 8 | 9 | A | B | C | D | E | F | 10 | 11 | 12 |  ..   |
+/// +---+---+---+---+------+---+---+---+---+---+---+---+---+---+---+---+----+----+----+-------+
+/// |  age  | frame | time |          server_time          | epoch |      offset      | event |
+///
+/// [ProtocolVersion::V0] conveys an [EventReply] instead.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CommandReply<E: DeserializeOwned + Serialize> {
+    /// The age of the event in relation to the server's notion of current time,
+    /// or 0 if there is no event. A saturated age means that the event is at
+    /// least this old.
+    pub age: AgeSecs,
+    /// The frame counter of the request replied to, as per [Reply::echoing], so
+    /// that a replayed reply cannot be mistaken for a fresh one, or 0 if the
+    /// reply was not produced from a data frame.
+    pub frame_counter: u16,
+    /// The server's current time, as provided by its [Clock], if the server
+    /// conveys it.
+    pub server_time: Option<u64>,
+    /// The epoch of the server's events, as per [EventLog::epoch], or 0 if the
+    /// reply was not produced from a log.
+    pub epoch: u16,
+    /// The offset of the event, or 0 if there is no event. Offsets are expected
+    /// to increment by one each time. Therefore, it is possible for a client to
+    /// determine if there is an event missing and possibly re-request it.
+    pub offset: u32,
+    /// The event to reply, or None if there are no more events.
+    #[serde(
+        deserialize_with = "deserialise_last_field",
+        serialize_with = "serialise_last_field"
+    )]
+    pub event: Option<E>,
+}
+
+impl<E: DeserializeOwned + Serialize> CommandReply<E> {
+    /// The event along with its offset, if there is an event.
+    pub fn into_event(self) -> Option<(E, u32)> {
+        let offset = self.offset;
+        self.event.map(|e| (e, offset))
+    }
+}
+
+/// An EventReply is what a server replies to a [CommandRequest] with in
+/// [ProtocolVersion::V0], being the original layout of a [CommandReply]. It
+/// conveys neither the frame counter, server time nor epoch, which are
+/// decoded as 0, nor is it preceded by the variant of a [Reply]. Its age is
+/// conveyed as delta ticks of a second each, with an age of [AgeSecs::MAX]
+/// conveyed as [SATURATED_DELTA_TICKS].
 ///
 /// An EventReply has the following little endian byte layout:
 ///
@@ -47,11 +289,12 @@ pub struct CommandRequest<C: DeserializeOwned + Serialize> {
 /// |          delta_ticks          | event |
 ///
 
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct EventReply<E: DeserializeOwned + Serialize> {
     /// The age of this event in relation to the server's notion of current time,
     /// expressed in a manner agreed between a client and server e.g. ticks can
-    /// represent seconds.
+    /// represent seconds. An age of [SATURATED_DELTA_TICKS] means that the event
+    /// is at least this old.
     pub delta_ticks: u64,
     /// The event to reply along with its offset. Offsets are expected to increment
     /// by one each time. Therefore, it is possible for a client to determine if
@@ -63,27 +306,189 @@ pub struct EventReply<E: DeserializeOwned + Serialize> {
     pub event: Option<(E, u32)>,
 }
 
-/// Given an event, offset and time, return an event reply containing it.
-pub fn event_reply<E, T, DS>(maybe_event: Option<&(E, u32, T)>, duration_since: DS) -> EventReply<E>
+impl<E: DeserializeOwned + Serialize> From<EventReply<E>> for CommandReply<E> {
+    fn from(reply: EventReply<E>) -> Self {
+        let (event, offset) = reply.event.map_or((None, 0), |(e, o)| (Some(e), o));
+        Self {
+            age: AgeSecs::from_secs(reply.delta_ticks),
+            frame_counter: 0,
+            server_time: None,
+            epoch: 0,
+            offset,
+            event,
+        }
+    }
+}
+
+/// A Reply is what a server sends in response to a [CommandRequest]. Usually
+/// this is a [CommandReply], but a server may also decline to act upon a
+/// command. Being the counterpart of [CommandRequest], both ends share this
+/// definition, and so applications need only define their events. The frame
+/// counter of the request replied to is echoed by both [Reply::Event] and
+/// [Reply::Alive], as per [Reply::echoing], so that the client can relate a
+/// reply to its request.
+///
+/// A Reply has the following little endian byte layout in
+/// [ProtocolVersion::V1], as per [Reply::encode], where the variant is 0 for an
+/// event reply, 1 for a NACK, 2 for events pending, 3 for alive and 4 for an
+/// unsupported version:
+///
+/// |    0    |  ..   |
+/// +---------+-------+
+/// | variant | event |
+///
+/// |    0    | 1 | 2 | 3 | 4 |
+/// +---------+---+---+---+---+
+/// | variant |     count     |
+///
+/// |    0    |   1   |   2   |
+/// +---------+-------+-------+
+/// | variant | frame_counter |
+///
+/// |    0    |    1    |
+/// +---------+---------+
+/// | variant | highest |
+///
+/// [ProtocolVersion::V0] has the original layout, conveying only an
+/// [EventReply] and without a variant, such that other replies are conveyed as
+/// a reply with no event. An unsupported version is the exception, being laid out as
+/// above so that a client of either version can downgrade.
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(bound = "")]
+pub enum Reply<E: DeserializeOwned + Serialize> {
+    /// A reply conveying an event, or that there are no more events.
+    Event(CommandReply<E>),
+    /// The command was not accepted by the server and has not been acted upon.
+    Nack,
+    /// The number of events that the client has yet to receive, conveyed
+    /// instead of the next event in reply to a [ControlCommand::Heartbeat].
+    EventsPending(u32),
+    /// The server is alive, but has no event that the client has yet to
+    /// receive. Conveyed instead of an event reply with no event, as per
+    /// [Server::handle_request_with_keepalive], so that a client can tell an
+    /// idle server from one that has gone away and push back its liveness
+    /// deadline. The frame counter is that of the request replied to, so that
+    /// a replayed keepalive cannot be mistaken for a fresh one.
+    Alive { frame_counter: u16 },
+    /// The request's protocol version is newer than the server supports, and
+    /// so its command has not been interpreted. The server's highest supported
+    /// version is conveyed, with the reply being sent in it, so that the client
+    /// can downgrade to it. As per [ServerRuntime::negotiate_version].
+    UnsupportedVersion { highest: ProtocolVersion },
+    /// The event of a given epoch and offset would not fit within a datagram,
+    /// and so is conveyed as this instead, as per [ServerRuntime::max_datagram].
+    /// The client is to move past it, so that it can receive events after it.
+    Truncated { epoch: u16, offset: u32 },
+}
+
+impl<E: DeserializeOwned + Serialize> Reply<E> {
+    /// Echo the frame counter of the request replied to, being that of the
+    /// data frame that conveyed it. Only [Reply::Event] and [Reply::Alive]
+    /// convey a frame counter, with other replies being unchanged.
+    pub fn echoing(self, frame_counter: u16) -> Self {
+        match self {
+            Reply::Event(reply) => Reply::Event(CommandReply {
+                frame_counter,
+                ..reply
+            }),
+            Reply::Alive { .. } => Reply::Alive { frame_counter },
+            reply => reply,
+        }
+    }
+}
+
+/// The delta ticks conveyed by a [ProtocolVersion::V0] reply for an event that
+/// is too old for its age to be represented. The event is at least this old.
+pub const SATURATED_DELTA_TICKS: u64 = u64::MAX;
+
+/// Convert the age of an event into delta ticks, saturating at
+/// [SATURATED_DELTA_TICKS] rather than truncating when the event is too
+/// old to be represented e.g. when converting from `Duration::as_millis`.
+pub fn saturating_delta_ticks(ticks: u128) -> u64 {
+    ticks.try_into().unwrap_or(SATURATED_DELTA_TICKS)
+}
+
+/// The age of an event in whole seconds within 16 bits, as conveyed by
+/// [CommandReply::age]. An age too great to be represented saturates at
+/// [AgeSecs::MAX], meaning that the event is at least this old, rather than
+/// wrapping around and appearing fresh.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct AgeSecs(u16);
+
+impl AgeSecs {
+    /// The greatest age represented, being that of any event at least 65535
+    /// seconds old.
+    pub const MAX: Self = Self(u16::MAX);
+
+    /// An age of a number of seconds, clamped to [AgeSecs::MAX].
+    pub fn from_secs(secs: u64) -> Self {
+        Self(secs.try_into().unwrap_or(u16::MAX))
+    }
+
+    /// The age of an event recorded at a time in ticks, as per
+    /// [Clock::elapsed_secs].
+    pub fn since<C: Clock>(clock: &C, ticks: u64) -> Self {
+        Self::from_secs(clock.elapsed_secs(ticks).into())
+    }
+
+    /// The age in seconds.
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+
+    /// True if the event is too old for its age to be represented exactly.
+    pub fn is_saturated(&self) -> bool {
+        *self == Self::MAX
+    }
+}
+
+/// Given an event, offset and time, return a command reply containing it, with
+/// its age being the number of seconds since the time, as per
+/// [AgeSecs::from_secs]. The reply echoes no frame counter until
+/// [Reply::echoing] is applied.
+pub fn event_reply<E, T, DS>(
+    maybe_event: Option<&(E, u32, T)>,
+    duration_since: DS,
+) -> CommandReply<E>
 where
     DS: FnOnce(T) -> u64,
     E: Clone + DeserializeOwned + Serialize,
     T: Copy,
 {
     // It is quite plausible that we have no events. In this case we
-    // reply with a "no more events" enum, an offset of 0 and a delta
-    // ticks of 0.
+    // reply with a "no more events" enum, an offset of 0 and an age of 0.
     maybe_event
-        .map(|(e, o, t)| EventReply {
-            delta_ticks: duration_since(*t),
-            event: Some((e.clone(), *o)),
+        .map(|(e, o, t)| CommandReply {
+            age: AgeSecs::from_secs(duration_since(*t)),
+            frame_counter: 0,
+            server_time: None,
+            epoch: 0,
+            offset: *o,
+            event: Some(e.clone()),
         })
-        .unwrap_or_else(|| EventReply {
-            delta_ticks: 0,
+        .unwrap_or_else(|| CommandReply {
+            age: AgeSecs::from_secs(0),
+            frame_counter: 0,
+            server_time: None,
+            epoch: 0,
+            offset: 0,
             event: None,
         })
 }
 
+/// Given an event, offset and time in ticks, return a command reply containing
+/// it with its age determined by a clock, as per [Clock::elapsed_secs]. The
+/// clock's current time is not conveyed, as per [CommandReply::server_time].
+pub fn clocked_event_reply<E, C>(maybe_event: Option<&(E, u32, u64)>, clock: &C) -> CommandReply<E>
+where
+    C: Clock,
+    E: Clone + DeserializeOwned + Serialize,
+{
+    let now = clock.now();
+    event_reply(maybe_event, |t| now.saturating_sub(t) / C::TICKS_PER_SECOND)
+}
+
 fn deserialise_last_field<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
 where
     D: Deserializer<'de>,
@@ -111,6 +516,7 @@ mod tests {
     #[test]
     fn test_command_serialisation_with_a_command() {
         #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[allow(clippy::enum_variant_names)]
         enum Command {
             SomeCommand,
             SomeOtherCommand,
@@ -119,16 +525,22 @@ mod tests {
 
         let request = CommandRequest {
             last_event_offset: 9,
+            subscriptions: DiscriminantSet::EMPTY.with(1).with(8),
+            acked_offset: 7,
+            acked_epoch: 3,
             command: Some(Command::AndAnotherCommand),
         };
 
         let mut buf = [0; 32];
         let serialised = postcard::to_slice(&request, &mut buf).unwrap();
-        assert_eq!(serialised, [9, 0, 0, 0, 2]);
+        assert_eq!(serialised, [9, 0, 0, 0, 2, 1, 0, 0, 7, 0, 0, 0, 3, 0, 2]);
         assert_eq!(
             postcard::from_bytes::<CommandRequest<Command>>(serialised).unwrap(),
             CommandRequest {
                 last_event_offset: 9,
+                subscriptions: DiscriminantSet(0x0102),
+                acked_offset: 7,
+                acked_epoch: 3,
                 command: Some(Command::AndAnotherCommand),
             }
         );
@@ -137,6 +549,7 @@ mod tests {
     #[test]
     fn test_command_serialisation_with_no_command() {
         #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[allow(clippy::enum_variant_names)]
         enum Command {
             SomeCommand,
             SomeOtherCommand,
@@ -144,16 +557,25 @@ mod tests {
 
         let request = CommandRequest::<Command> {
             last_event_offset: 0,
+            subscriptions: DiscriminantSet::ALL,
+            acked_offset: 0,
+            acked_epoch: 0,
             command: None,
         };
 
         let mut buf = [0; 32];
         let serialised = postcard::to_slice(&request, &mut buf).unwrap();
-        assert_eq!(serialised, [0, 0, 0, 0]);
+        assert_eq!(
+            serialised,
+            [0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0]
+        );
         assert_eq!(
             postcard::from_bytes::<CommandRequest<Command>>(serialised).unwrap(),
             CommandRequest {
                 last_event_offset: 0,
+                subscriptions: DiscriminantSet::ALL,
+                acked_offset: 0,
+                acked_epoch: 0,
                 command: None,
             }
         );
@@ -162,15 +584,16 @@ mod tests {
     #[test]
     fn test_event_serialisation() {
         #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        #[allow(clippy::enum_variant_names)]
         enum Event {
             SomeEvent,
             SomeOtherEvent,
         }
 
-        let reply = event_reply(Some(&(Event::SomeOtherEvent, 9, 0)), |_| 10);
+        let reply = Reply::Event(event_reply(Some(&(Event::SomeOtherEvent, 9, 0)), |_| 10));
 
         let mut buf = [0; 32];
-        let serialised = postcard::to_slice(&reply, &mut buf).unwrap();
+        let serialised = reply.encode(ProtocolVersion::V0, &mut buf).unwrap();
         assert_eq!(serialised, [10, 0, 0, 0, 0, 0, 0, 0, 1, 9, 0, 0, 0]);
         assert_eq!(
             postcard::from_bytes::<EventReply<Event>>(serialised).unwrap(),
@@ -184,15 +607,16 @@ mod tests {
     #[test]
     fn test_event_serialisation_with_no_more_events() {
         #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        #[allow(clippy::enum_variant_names)]
         enum Event {
             SomeEvent,
             SomeOtherEvent,
         }
 
-        let reply = event_reply::<Event, u32, _>(None, |_| 10);
+        let reply = Reply::Event(event_reply::<Event, u32, _>(None, |_| 10));
 
         let mut buf = [0; 32];
-        let serialised = postcard::to_slice(&reply, &mut buf).unwrap();
+        let serialised = reply.encode(ProtocolVersion::V0, &mut buf).unwrap();
         assert_eq!(serialised, [0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(
             postcard::from_bytes::<EventReply<Event>>(serialised).unwrap(),
@@ -202,4 +626,140 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_command_reply_serialisation() {
+        #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        enum Event {
+            SomeEvent,
+            SomeOtherEvent,
+        }
+
+        let reply =
+            Reply::Event(event_reply(Some(&(Event::SomeOtherEvent, 9, 0)), |_| 10)).echoing(300);
+
+        let mut buf = [0; 32];
+        let serialised = reply.encode(ProtocolVersion::V1, &mut buf).unwrap();
+        assert_eq!(serialised, [0, 10, 0, 44, 1, 0, 0, 0, 9, 0, 0, 0, 1]);
+        assert_eq!(
+            postcard::from_bytes::<Reply<Event>>(serialised).unwrap(),
+            Reply::Event(CommandReply {
+                age: AgeSecs::from_secs(10),
+                frame_counter: 300,
+                server_time: None,
+                epoch: 0,
+                offset: 9,
+                event: Some(Event::SomeOtherEvent),
+            })
+        );
+
+        // With no more events, the offset is still conveyed.
+        let reply = Reply::Event(event_reply::<Event, u32, _>(None, |_| 10));
+        let serialised = reply.encode(ProtocolVersion::V1, &mut buf).unwrap();
+        assert_eq!(serialised, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_saturated_age() {
+        use core::time::Duration;
+
+        let secs = |age: Duration| age.as_secs();
+
+        let reply = event_reply(Some(&('a', 9, Duration::from_secs(5))), secs);
+        assert_eq!(reply.age.get(), 5);
+        assert!(!reply.age.is_saturated());
+
+        let reply = event_reply(Some(&('a', 9, Duration::MAX)), secs);
+        assert!(reply.age.is_saturated());
+
+        // Version 0 conveys the saturated age as delta ticks.
+        let mut buf = [0; 16];
+        let encoded = Reply::Event(reply)
+            .encode(ProtocolVersion::V0, &mut buf)
+            .unwrap();
+        assert_eq!(encoded[..8], SATURATED_DELTA_TICKS.to_le_bytes());
+        assert_eq!(
+            saturating_delta_ticks(Duration::MAX.as_millis()),
+            SATURATED_DELTA_TICKS
+        );
+    }
+
+    #[test]
+    fn test_age_secs_saturates() {
+        struct MillisClock(u64);
+        impl Clock for MillisClock {
+            const TICKS_PER_SECOND: u64 = 1000;
+            fn now(&self) -> u64 {
+                self.0
+            }
+        }
+
+        assert_eq!(AgeSecs::from_secs(65_535).get(), 65_535);
+        assert!(!AgeSecs::from_secs(65_534).is_saturated());
+        assert_eq!(AgeSecs::from_secs(65_536), AgeSecs::MAX);
+        assert_eq!(AgeSecs::from_secs(u64::MAX), AgeSecs::MAX);
+
+        // An event 70000 seconds old reports the greatest age, rather than
+        // wrapping around to 4464 seconds.
+        let clock = MillisClock(70_000_500);
+        assert_eq!(AgeSecs::since(&clock, 0), AgeSecs::MAX);
+        assert_eq!(AgeSecs::since(&clock, 60_000_000).get(), 10_000);
+
+        let reply = clocked_event_reply(Some(&('a', 9, 0)), &clock);
+        assert!(reply.age.is_saturated());
+        let reply = clocked_event_reply(Some(&('a', 9, 70_000_000)), &clock);
+        assert_eq!(reply.age.get(), 0);
+        let reply = event_reply(Some(&('a', 9, 0)), |_| u64::MAX);
+        assert_eq!(reply.age, AgeSecs::MAX);
+
+        let mut buf = [0; 2];
+        assert_eq!(
+            postcard::to_slice(&AgeSecs::MAX, &mut buf).unwrap(),
+            [0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_clocked_event_reply() {
+        struct FixedClock(u64);
+        impl Clock for FixedClock {
+            fn now(&self) -> u64 {
+                self.0
+            }
+        }
+
+        let reply = clocked_event_reply(Some(&('a', 9, 95)), &FixedClock(100));
+        assert_eq!(reply.age.get(), 5);
+        assert_eq!(reply.into_event(), Some(('a', 9)));
+    }
+
+    #[test]
+    fn test_event_serialisation_with_server_time() {
+        #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+        enum Event {
+            SomeEvent,
+        }
+
+        let reply = CommandReply {
+            server_time: Some(10),
+            ..event_reply(Some(&(Event::SomeEvent, 1, 0)), |_| 10)
+        };
+
+        let mut buf = [0; 32];
+        let serialised = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(
+            serialised,
+            [10, 0, 0, 0, 1, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            postcard::from_bytes::<CommandReply<Event>>(serialised).unwrap(),
+            reply
+        );
+
+        // The server's time is not conveyed in version 0.
+        let serialised = Reply::Event(reply)
+            .encode(ProtocolVersion::V0, &mut buf)
+            .unwrap();
+        assert_eq!(serialised, [10, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+    }
 }
@@ -0,0 +1,261 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use flip_flop_data::{
+    crypto::{Opener, Sealer},
+    DataFrame, DataSource, Header, ProtocolVersion, ServerAddress, ServerPort, HEADER_SIZE,
+    MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+
+use crate::{
+    CommandHandler, CommandRequest, Discriminant, ProcessError, Rejection, Reply, ServerEndpoint,
+    WireMessage,
+};
+
+/// Conveys the datagrams of a client to a [ServerEndpoint] in memory, and its
+/// replies back, so that the client and server of a single process can be
+/// tested end to end without sockets. Requests are sealed as the client seals
+/// them, within frames having the transport's header, and replies are opened
+/// as the client opens them, being checked to have the opposite source.
+///
+/// Each request is sent with the header's next frame counter, and laid out as
+/// its protocol version does, being [ProtocolVersion::LATEST] by default. The
+/// header may be changed between requests e.g. to address another server, to
+/// replay a frame counter, or to send in another version.
+pub struct LoopbackTransport {
+    sealer: Sealer,
+    opener: Opener,
+    salt: [u8; 6],
+    /// The header of the next frame sent.
+    pub header: Header,
+}
+
+impl LoopbackTransport {
+    /// Create a transport for a client sealing with its key and opening with
+    /// the server's, sending requests to a server address and port with frame
+    /// counters starting from 0, in the latest protocol version.
+    pub fn new(
+        client_key: &[u8; 16],
+        server_key: &[u8; 16],
+        salt: [u8; 6],
+        server_address: ServerAddress,
+        server_port: ServerPort,
+    ) -> Self {
+        Self {
+            sealer: Sealer::new(client_key),
+            opener: Opener::new(server_key),
+            salt,
+            header: Header {
+                version: ProtocolVersion::LATEST.get(),
+                source: DataSource::Client,
+                server_address,
+                server_port,
+                frame_counter: 0,
+            },
+        }
+    }
+
+    /// Seal a plaintext into a datagram with the next frame counter, writing
+    /// it to `out` and returning its length.
+    pub fn seal(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize, ProcessError> {
+        let header = self.header;
+        self.header.frame_counter = header.frame_counter.wrapping_add(1);
+
+        let mut encrypted_payload = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = self
+            .sealer
+            .seal_next(
+                &header,
+                &header.nonce(&self.salt),
+                plaintext,
+                &mut encrypted_payload,
+            )
+            .map_err(ProcessError::Crypto)?;
+        DataFrame::new(&header, &encrypted_payload[..len])
+            .to_bytes(out)
+            .map_err(|_| ProcessError::Encode)
+    }
+
+    /// Seal a request into a datagram, as per [LoopbackTransport::seal], laid
+    /// out as the header's protocol version does. A version that is not
+    /// supported is laid out as the latest one is.
+    pub fn datagram<C>(
+        &mut self,
+        request: &CommandRequest<C>,
+        out: &mut [u8],
+    ) -> Result<usize, ProcessError>
+    where
+        C: DeserializeOwned + Serialize,
+    {
+        let mut plaintext = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let version = ProtocolVersion::new(self.header.version).unwrap_or(ProtocolVersion::LATEST);
+        let plaintext = request
+            .encode(version, &mut plaintext)
+            .map_err(|_| ProcessError::Encode)?;
+        self.seal(plaintext, out)
+    }
+
+    /// Deliver a datagram to an endpoint, returning the header and reply of the
+    /// datagram replied with, if any.
+    pub fn deliver<H, C, E>(
+        &mut self,
+        endpoint: &mut ServerEndpoint<H>,
+        datagram: &[u8],
+    ) -> Result<Option<(Header, Reply<E>)>, ProcessError>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        let mut out = [0; HEADER_SIZE + MAX_ENCRYPTED_PAYLOAD_LEN];
+        match endpoint.process(datagram, &mut out)? {
+            Some(len) => self.open(&out[..len]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Open a datagram replied by an endpoint, returning its header and reply,
+    /// e.g. having processed a request with
+    /// [ServerEndpoint::process_with_dedup]. The reply is decoded as the
+    /// header's protocol version lays it out.
+    pub fn open<E>(&self, datagram: &[u8]) -> Result<(Header, Reply<E>), ProcessError>
+    where
+        E: DeserializeOwned + Serialize,
+    {
+        let (header, encrypted_payload) = DataFrame::from_bytes(datagram)
+            .and_then(|f| f.parse())
+            .map_err(ProcessError::Parse)?;
+        if header.source != self.header.source.opposite() {
+            return Err(ProcessError::Rejected(Rejection::UnexpectedSource));
+        }
+        let mut plaintext = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = self
+            .opener
+            .open(
+                &header,
+                &header.nonce(&self.salt),
+                encrypted_payload,
+                &mut plaintext,
+            )
+            .map_err(ProcessError::Crypto)?;
+        let version = header.protocol_version().map_err(ProcessError::Parse)?;
+        let reply = Reply::decode(version, &plaintext[..len]).map_err(|_| ProcessError::Decode)?;
+        Ok((header, reply))
+    }
+
+    /// Send a request to an endpoint, returning the header and reply of the
+    /// datagram replied with, if any.
+    pub fn request<H, C, E>(
+        &mut self,
+        endpoint: &mut ServerEndpoint<H>,
+        request: &CommandRequest<C>,
+    ) -> Result<Option<(Header, Reply<E>)>, ProcessError>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        let mut datagram = [0; HEADER_SIZE + MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = self.datagram(request, &mut datagram)?;
+        self.deliver(endpoint, &datagram[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{poll, Command, Event, FixedClock, CLIENT_KEY, SERVER_KEY};
+    use crate::{
+        AcceptConfig, CatchUpPolicy, CommandReply, DiscriminantSet, EventLog, EventLogHandler,
+        ServerRuntime,
+    };
+
+    const SALT: [u8; 6] = [6, 5, 4, 3, 2, 1];
+
+    // Records an event for each command, as a server acting on it would.
+    struct Handler(EventLogHandler<Event, FixedClock, 4>);
+
+    impl CommandHandler<Command, Event> for Handler {
+        fn on_command(
+            &mut self,
+            command: Option<Command>,
+            last_event_offset: u32,
+            subscriptions: DiscriminantSet,
+        ) -> Reply<Event> {
+            if command == Some(Command::Open) {
+                self.0.log.push(Event::Opened, 0);
+            }
+            CommandHandler::<Command, _>::on_command(
+                &mut self.0,
+                None,
+                last_event_offset,
+                subscriptions,
+            )
+        }
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        let server_address = ServerAddress::new_unchecked(1);
+        let server_port = ServerPort::new_unchecked(2);
+        let config = AcceptConfig::builder(DataSource::Client)
+            .server_address(server_address)
+            .build();
+        // The client has received the event of the server starting.
+        let mut log = EventLog::new();
+        log.push(Event::Started, 0);
+        let handler = Handler(EventLogHandler {
+            log,
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        });
+        let mut endpoint = ServerEndpoint::new(
+            ServerRuntime::new(handler),
+            config,
+            CLIENT_KEY,
+            SERVER_KEY,
+            SALT,
+        );
+        let mut transport =
+            LoopbackTransport::new(CLIENT_KEY, SERVER_KEY, SALT, server_address, server_port);
+
+        // The command is sealed, handled and replied to with the event that it
+        // caused, from the server that it was sent to.
+        let (header, reply) = transport
+            .request::<_, _, Event>(
+                &mut endpoint,
+                &CommandRequest {
+                    command: Some(Command::Open),
+                    ..poll(0)
+                },
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.source, DataSource::Server);
+        assert_eq!(
+            (header.server_address, header.server_port),
+            (server_address, server_port)
+        );
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply {
+                event: Some(Event::Opened),
+                offset: 1,
+                ..
+            })
+        ));
+        assert_eq!(transport.header.frame_counter, 1);
+
+        // A reply sealed with another key fails to open.
+        let mut transport =
+            LoopbackTransport::new(CLIENT_KEY, CLIENT_KEY, SALT, server_address, server_port);
+        transport.header.frame_counter = 1;
+        let request = poll::<Command>(1);
+        assert_eq!(
+            transport.request::<_, _, Event>(&mut endpoint, &request),
+            Err(ProcessError::Crypto(
+                flip_flop_data::crypto::CryptoError::Unauthenticated
+            ))
+        );
+    }
+}
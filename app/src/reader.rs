@@ -0,0 +1,227 @@
+use flip_flop_data::{DataFrame, ParseError, HEADER_SIZE, MAX_ENCRYPTED_PAYLOAD_LEN};
+
+/// The greatest size of a data frame as written by [DataFrame::to_bytes].
+pub const MAX_FRAME_SIZE: usize = HEADER_SIZE + MAX_ENCRYPTED_PAYLOAD_LEN;
+
+/// Delimits the data frames of a stream of bytes, as received from a transport
+/// that does not preserve datagram boundaries e.g. a serial link or TCP. Each
+/// frame is delimited by the length of its payload, being the byte following
+/// its header, and so frames may be conveyed back to back, as may be a datagram
+/// holding several frames.
+///
+/// Bytes are fed to the reader as they are received, which yields the frames
+/// that are complete. The bytes of a trailing partial frame are retained until
+/// the rest of the frame is received. `N` is the capacity of the reader's
+/// buffer, and must be at least [MAX_FRAME_SIZE] so that any frame can be
+/// retained while it is completed.
+pub struct FrameReader<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    consumed: usize,
+}
+
+impl<const N: usize> FrameReader<N> {
+    const HOLDS_A_FRAME: () = assert!(N >= MAX_FRAME_SIZE, "buffer too small for a frame");
+
+    /// Create a reader with nothing buffered.
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::HOLDS_A_FRAME;
+        Self {
+            buf: [0; N],
+            len: 0,
+            consumed: 0,
+        }
+    }
+
+    /// The number of bytes buffered of a partial frame.
+    pub fn buffered(&self) -> usize {
+        self.len - self.consumed
+    }
+
+    /// Feed bytes received, returning the frames completed by them, along with
+    /// any completed by bytes previously fed. Frames yielded by a previous call
+    /// are no longer retained.
+    ///
+    /// An error is returned should the reader's buffer be too small for the
+    /// bytes and any partial frame, in which case nothing is fed, and so the
+    /// bytes are to be fed in smaller parts. An error is also returned should a
+    /// frame declare a payload longer than 127 bytes, in which case the stream
+    /// can no longer be delimited and everything buffered is discarded.
+    pub fn read(&mut self, bytes: &[u8]) -> Result<Frames<'_>, ParseError> {
+        self.buf.copy_within(self.consumed..self.len, 0);
+        self.len -= self.consumed;
+        self.consumed = 0;
+
+        let len = self.len + bytes.len();
+        let buf = self
+            .buf
+            .get_mut(self.len..len)
+            .ok_or(ParseError::Truncated {
+                expected: len,
+                got: N,
+            })?;
+        buf.copy_from_slice(bytes);
+        self.len = len;
+
+        while let Some(header) = self.buf[self.consumed..self.len].get(..HEADER_SIZE) {
+            let payload_len = header[HEADER_SIZE - 1] as usize;
+            if payload_len > MAX_ENCRYPTED_PAYLOAD_LEN {
+                self.len = 0;
+                self.consumed = 0;
+                return Err(ParseError::PayloadTooLong(payload_len));
+            }
+            let frame_len = HEADER_SIZE + payload_len;
+            if self.consumed + frame_len > self.len {
+                break;
+            }
+            self.consumed += frame_len;
+        }
+        Ok(Frames {
+            bytes: &self.buf[..self.consumed],
+        })
+    }
+
+    /// Declare that no more bytes are to be received e.g. as the stream has
+    /// closed, discarding anything buffered. An error is returned should a
+    /// partial frame have been buffered, being the frame truncated by the
+    /// stream closing.
+    pub fn finish(&mut self) -> Result<(), ParseError> {
+        let partial = &self.buf[self.consumed..self.len];
+        let result = match partial.len() {
+            0 => Ok(()),
+            got if got < HEADER_SIZE => Err(ParseError::Truncated {
+                expected: HEADER_SIZE,
+                got,
+            }),
+            got => Err(ParseError::Truncated {
+                expected: HEADER_SIZE + partial[HEADER_SIZE - 1] as usize,
+                got,
+            }),
+        };
+        self.len = 0;
+        self.consumed = 0;
+        result
+    }
+}
+
+impl<const N: usize> Default for FrameReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over the frames completed by the bytes fed to a [FrameReader].
+#[derive(Clone, Debug)]
+pub struct Frames<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = DataFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = DataFrame::from_bytes(self.bytes).ok()?;
+        self.bytes = &self.bytes[frame.encoded_len()..];
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flip_flop_data::{DataSource, Header, ServerAddress, ServerPort};
+
+    fn header(frame_counter: u16) -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter,
+        }
+    }
+
+    // The bytes of two frames conveyed back to back.
+    fn stream(out: &mut [u8]) -> usize {
+        let len = DataFrame::new(&header(1), b"first").to_bytes(out).unwrap();
+        len + DataFrame::new(&header(2), b"second")
+            .to_bytes(&mut out[len..])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_two_frames_in_one_read() {
+        let mut bytes = [0; 32];
+        let len = stream(&mut bytes);
+
+        let mut reader = FrameReader::<MAX_FRAME_SIZE>::new();
+        let mut frames = reader.read(&bytes[..len]).unwrap();
+        assert_eq!(frames.next(), Some(DataFrame::new(&header(1), b"first")));
+        assert_eq!(frames.next(), Some(DataFrame::new(&header(2), b"second")));
+        assert_eq!(frames.next(), None);
+        assert_eq!(reader.buffered(), 0);
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_frame_split_across_reads() {
+        let mut bytes = [0; 32];
+        let len = stream(&mut bytes);
+
+        // Every split of the stream yields the same frames.
+        for split in 0..=len {
+            let mut reader = FrameReader::<MAX_FRAME_SIZE>::new();
+            let mut counters = [0; 2];
+            let mut count = 0;
+            for part in [&bytes[..split], &bytes[split..len]] {
+                for frame in reader.read(part).unwrap() {
+                    counters[count] = frame.parse().unwrap().0.frame_counter;
+                    count += 1;
+                }
+            }
+            assert_eq!(&counters[..count], &[1, 2], "split at {}", split);
+            assert_eq!(reader.finish(), Ok(()));
+        }
+
+        // A partial frame is reported as truncated only once the stream ends.
+        let mut reader = FrameReader::<MAX_FRAME_SIZE>::new();
+        assert_eq!(reader.read(&bytes[..len - 1]).unwrap().count(), 1);
+        assert_eq!(reader.buffered(), HEADER_SIZE + 5);
+        assert_eq!(
+            reader.finish(),
+            Err(ParseError::Truncated {
+                expected: HEADER_SIZE + 6,
+                got: HEADER_SIZE + 5
+            })
+        );
+        assert_eq!(reader.buffered(), 0);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let mut reader = FrameReader::<MAX_FRAME_SIZE>::new();
+        assert_eq!(
+            reader.read(&[0, 0, 0, 0, 128]).err(),
+            Some(ParseError::PayloadTooLong(128))
+        );
+        assert_eq!(reader.buffered(), 0);
+
+        assert_eq!(reader.read(&[0, 0, 0, 0, 127]).unwrap().count(), 0);
+        assert_eq!(
+            reader.read(&[0; MAX_ENCRYPTED_PAYLOAD_LEN + 1]).err(),
+            Some(ParseError::Truncated {
+                expected: MAX_FRAME_SIZE + 1,
+                got: MAX_FRAME_SIZE
+            })
+        );
+        assert_eq!(
+            reader
+                .read(&[0; MAX_ENCRYPTED_PAYLOAD_LEN])
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(reader.finish(), Ok(()));
+    }
+}
@@ -0,0 +1,325 @@
+use flip_flop_data::{DataSource, ServerAddress};
+use serde::{Deserialize, Serialize};
+
+/// Tracks the frame counters received from a peer so that replayed and stale
+/// frames can be rejected. A counter is accepted only if it is newer than the
+/// last one accepted. Frame counters overflow to zero after 0xFFFF, so
+/// "newer" means being ahead of the last counter by less than half of the
+/// counter's range.
+///
+/// A window also tracks any resync that it has outstanding with the peer, as
+/// per [CounterResync].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayWindow {
+    last: Option<u16>,
+    challenge: Option<u16>,
+    proposal: Option<u16>,
+}
+
+impl ReplayWindow {
+    /// Create a window that will accept any counter as its first.
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            challenge: None,
+            proposal: None,
+        }
+    }
+
+    /// The last frame counter accepted, if any.
+    pub fn last(&self) -> Option<u16> {
+        self.last
+    }
+
+    /// Accept a frame counter if it is newer than the last one accepted, in
+    /// which case the window advances to it. Returns false if the frame should
+    /// be dropped as a replay.
+    pub fn accept(&mut self, counter: u16) -> bool {
+        let newer = match self.last {
+            Some(last) => (1..0x8000).contains(&counter.wrapping_sub(last)),
+            None => true,
+        };
+        if newer {
+            self.last = Some(counter);
+        }
+        newer
+    }
+
+    /// Re-establish the window at a counter so that only frames newer than it
+    /// are subsequently accepted, irrespective of what was accepted before.
+    pub fn resync(&mut self, counter: u16) {
+        self.last = Some(counter);
+    }
+
+    /// Challenge the peer to propose its counter, as when its frames are being
+    /// rejected having drifted, returning the message to send it. Only a
+    /// proposal echoing the nonce is then adopted, and only once. The nonce
+    /// must not have been issued before, and so `own_counter` is the frame
+    /// counter that the challenge will be sent with.
+    pub fn challenge(&mut self, own_counter: u16) -> CounterResync {
+        self.challenge = Some(own_counter);
+        CounterResync::Challenge { nonce: own_counter }
+    }
+
+    /// Handle a resync message received from the peer, returning any message
+    /// to be sent back to it. `own_counter` is the frame counter that the reply
+    /// will be sent with.
+    ///
+    /// A proposal is only adopted if it echoes the nonce of the challenge
+    /// outstanding, and an acknowledgement only if it adopts the counter of the
+    /// proposal outstanding, with either then being settled. Others, as when
+    /// replayed, are ignored.
+    pub fn handle_resync(
+        &mut self,
+        message: CounterResync,
+        own_counter: u16,
+    ) -> Option<CounterResync> {
+        match message {
+            CounterResync::Propose { counter, nonce } if self.challenge == Some(nonce) => {
+                self.challenge = None;
+                self.resync(counter);
+                Some(CounterResync::Acknowledge {
+                    adopted: counter,
+                    counter: own_counter,
+                })
+            }
+            CounterResync::Acknowledge { adopted, counter } if self.proposal == Some(adopted) => {
+                self.proposal = None;
+                self.resync(counter);
+                None
+            }
+            CounterResync::Challenge { nonce } => {
+                self.proposal = Some(own_counter);
+                Some(CounterResync::Propose {
+                    counter: own_counter,
+                    nonce,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The number of server addresses that a data frame header can convey.
+const SERVER_ADDRESSES: usize = ServerAddress::MAX as usize + 1;
+
+/// Tracks the frame counters received from each peer so that replayed and stale
+/// frames can be rejected, being a [ReplayWindow] for each source and server
+/// address that a data frame can convey. A server receiving frames from clients
+/// addressing several of its logical servers, or a node on a shared medium
+/// hearing both clients and servers, can thereby guard them all with one value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGuard {
+    windows: [[ReplayWindow; SERVER_ADDRESSES]; 2],
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayGuard {
+    /// Create a guard that will accept any counter as the first from each
+    /// peer.
+    pub const fn new() -> Self {
+        Self {
+            windows: [[ReplayWindow::new(); SERVER_ADDRESSES]; 2],
+        }
+    }
+
+    /// Accept a frame counter if it is newer than the last one accepted with
+    /// the same source and server address, as per [ReplayWindow::accept].
+    /// Returns false if the frame should be dropped as a replay.
+    pub fn check_and_update(
+        &mut self,
+        source: DataSource,
+        server_address: ServerAddress,
+        frame_counter: u16,
+    ) -> bool {
+        self.window_mut(source, server_address)
+            .accept(frame_counter)
+    }
+
+    /// The window for a source and server address, e.g. to resync it.
+    pub fn window_mut(
+        &mut self,
+        source: DataSource,
+        server_address: ServerAddress,
+    ) -> &mut ReplayWindow {
+        let source = match source {
+            DataSource::Client => 0,
+            DataSource::Server => 1,
+        };
+        &mut self.windows[source][server_address.get() as usize]
+    }
+}
+
+/// Messages that re-establish the replay windows of a client and server whose
+/// frame counters have drifted apart e.g. when one of them has restarted. The
+/// side rejecting the other's frames challenges it to propose its current
+/// counter, which it then adopts and acknowledges by also conveying its own
+/// counter, as per [ReplayWindow::challenge] and [ReplayWindow::handle_resync].
+/// Applications convey these by including them within their own command and
+/// event types.
+///
+/// A frame carrying a resync message is necessarily exempt from the replay
+/// check, so it must only be acted upon once authenticated e.g. having been
+/// received within a data frame with a valid MAC. A captured proposal or
+/// acknowledgement cannot be replayed to move a window backwards, as each is
+/// only acted upon in answer to the message outstanding.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CounterResync {
+    /// Proposes that the receiver adopts the sender's current frame counter,
+    /// echoing the nonce of the challenge answered.
+    Propose { counter: u16, nonce: u16 },
+    /// Acknowledges that a proposed counter has been adopted, and conveys the
+    /// acknowledger's own frame counter for the proposer to adopt.
+    Acknowledge { adopted: u16, counter: u16 },
+    /// Challenges the receiver to propose its current frame counter, conveying
+    /// a nonce for the proposal to echo.
+    Challenge { nonce: u16 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(10));
+        assert!(window.accept(11));
+        assert!(!window.accept(11));
+        assert!(!window.accept(5));
+        assert!(window.accept(20));
+
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(0xFFFE));
+        assert!(window.accept(0x0001));
+        assert!(!window.accept(0xFFFF));
+    }
+
+    fn address(address: u8) -> ServerAddress {
+        ServerAddress::new(address).unwrap()
+    }
+
+    #[test]
+    fn test_guard() {
+        let mut guard = ReplayGuard::new();
+
+        // Counters advance independently for each source and address.
+        assert!(guard.check_and_update(DataSource::Client, address(1), 10));
+        assert!(guard.check_and_update(DataSource::Client, address(1), 11));
+        assert!(guard.check_and_update(DataSource::Client, address(2), 5));
+        assert!(guard.check_and_update(DataSource::Server, address(1), 5));
+
+        // Replayed and stale counters are rejected.
+        assert!(!guard.check_and_update(DataSource::Client, address(1), 11));
+        assert!(!guard.check_and_update(DataSource::Client, address(1), 10));
+        assert!(!guard.check_and_update(DataSource::Server, address(1), 5));
+        assert!(guard.check_and_update(DataSource::Client, address(2), 6));
+
+        // Each window can be resynced.
+        guard.window_mut(DataSource::Client, address(1)).resync(5);
+        assert!(guard.check_and_update(DataSource::Client, address(1), 6));
+    }
+
+    #[test]
+    fn test_guard_wraps() {
+        let mut guard = ReplayGuard::new();
+        assert!(guard.check_and_update(DataSource::Client, address(3), 0xFFFE));
+        assert!(guard.check_and_update(DataSource::Client, address(3), 0xFFFF));
+        assert!(guard.check_and_update(DataSource::Client, address(3), 0x0000));
+        assert!(!guard.check_and_update(DataSource::Client, address(3), 0xFFFF));
+        assert!(guard.check_and_update(DataSource::Client, address(3), 0x0001));
+
+        // A counter half of the range behind is regarded as stale rather
+        // than having wrapped.
+        assert!(!guard.check_and_update(DataSource::Client, address(3), 0x8001));
+        assert!(guard.check_and_update(DataSource::Client, address(3), 0x8000));
+    }
+
+    #[test]
+    fn test_drifted_session_resyncs() {
+        let mut server_window = ReplayWindow::new();
+        let mut client_window = ReplayWindow::new();
+
+        // Establish a session...
+        assert!(server_window.accept(1000));
+        assert!(client_window.accept(500));
+
+        // ...and then the client restarts with its counter starting afresh,
+        // so its frames are rejected.
+        let mut client_counter = 1;
+        assert!(!server_window.accept(client_counter));
+
+        // The server challenges the client to propose its counter, which the
+        // server, having authenticated the frame, adopts and acknowledges.
+        let mut server_counter = 501;
+        let challenge = server_window.challenge(server_counter);
+        assert_eq!(challenge, CounterResync::Challenge { nonce: 501 });
+        client_counter += 1;
+        let proposal = client_window
+            .handle_resync(challenge, client_counter)
+            .unwrap();
+        assert_eq!(
+            proposal,
+            CounterResync::Propose {
+                counter: 2,
+                nonce: 501
+            }
+        );
+        server_counter += 1;
+        let ack = server_window
+            .handle_resync(proposal, server_counter)
+            .unwrap();
+        assert_eq!(
+            ack,
+            CounterResync::Acknowledge {
+                adopted: 2,
+                counter: 502
+            }
+        );
+        assert_eq!(client_window.handle_resync(ack, client_counter), None);
+
+        // The session resumes in both directions, and the proposal's frame
+        // cannot be replayed.
+        assert!(!server_window.accept(client_counter));
+        client_counter += 1;
+        assert!(server_window.accept(client_counter));
+        assert!(!client_window.accept(server_counter));
+        assert!(client_window.accept(server_counter + 1));
+
+        // Neither can the proposal nor the acknowledgement be acted upon
+        // again to move the windows back.
+        assert_eq!(server_window.handle_resync(proposal, 600), None);
+        assert!(server_window.accept(client_counter + 1));
+        assert_eq!(client_window.handle_resync(ack, 10), None);
+        assert!(client_window.accept(server_counter + 2));
+    }
+
+    #[test]
+    fn test_unsolicited_resync_is_ignored() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(1000));
+
+        // A proposal is only adopted in answer to the challenge outstanding...
+        let proposal = CounterResync::Propose {
+            counter: 2,
+            nonce: 7,
+        };
+        assert_eq!(window.handle_resync(proposal, 50), None);
+        window.challenge(51);
+        assert_eq!(window.handle_resync(proposal, 52), None);
+        assert_eq!(window.last(), Some(1000));
+
+        // ...and an acknowledgement in answer to the proposal outstanding.
+        let ack = CounterResync::Acknowledge {
+            adopted: 2,
+            counter: 3,
+        };
+        assert_eq!(window.handle_resync(ack, 53), None);
+        assert_eq!(window.last(), Some(1000));
+    }
+}
@@ -0,0 +1,73 @@
+use flip_flop_data::{should_process, DataSource};
+
+/// Drops the frames that a node is to ignore given its role, being those that
+/// it or another node of the same role originated. On a shared medium such as
+/// radio or a multi-drop serial link, a node hears its own transmissions and
+/// those of its peers, and replying to them would feed back. The source is
+/// read from the header as received, and so frames are dropped before they
+/// are parsed or opened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoleFilter {
+    role: DataSource,
+}
+
+impl RoleFilter {
+    /// Create a filter for a node having a given role.
+    pub const fn new(role: DataSource) -> Self {
+        Self { role }
+    }
+
+    /// Create a filter for a server, which processes client sourced frames.
+    pub const fn server() -> Self {
+        Self::new(DataSource::Server)
+    }
+
+    /// Create a filter for a client, which processes server sourced frames.
+    pub const fn client() -> Self {
+        Self::new(DataSource::Client)
+    }
+
+    /// The role of the node.
+    pub const fn role(&self) -> DataSource {
+        self.role
+    }
+
+    /// True if the bytes received are of a frame sourced by the other role,
+    /// being worth parsing and opening. Bytes too few to be a frame are not,
+    /// as per [flip_flop_data::should_process].
+    pub fn should_process(&self, bytes: &[u8]) -> bool {
+        should_process(bytes, self.role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flip_flop_data::{DataFrame, Header, ServerAddress, ServerPort};
+
+    fn frame_bytes(source: DataSource, out: &mut [u8]) -> usize {
+        let header = Header {
+            version: 0,
+            source,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 3,
+        };
+        DataFrame::new(&header, &[0; 4]).to_bytes(out).unwrap()
+    }
+
+    #[test]
+    fn test_server_drops_server_sourced_frames() {
+        let mut bytes = [0; 16];
+        let filter = RoleFilter::server();
+        assert_eq!(filter.role(), DataSource::Server);
+
+        let len = frame_bytes(DataSource::Server, &mut bytes);
+        assert!(!filter.should_process(&bytes[..len]));
+        let len = frame_bytes(DataSource::Client, &mut bytes);
+        assert!(filter.should_process(&bytes[..len]));
+        assert!(!RoleFilter::client().should_process(&bytes[..len]));
+
+        assert!(!filter.should_process(&bytes[..3]));
+    }
+}
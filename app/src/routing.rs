@@ -0,0 +1,206 @@
+use flip_flop_data::{DataSource, Header, ServerAddress, ServerPort};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{CommandRequest, Reply};
+
+/// A [CommandRequest] along with the server address and port that it was sent
+/// to, as conveyed by the header of the data frame that it was received within.
+/// A relay serving several logical servers from one socket can then dispatch
+/// each request to the handler for its address and port. The protocol version
+/// of the header is also retained, so that the reply is sent in the same one.
+#[derive(Debug, PartialEq)]
+pub struct AddressedRequest<C: DeserializeOwned + Serialize> {
+    /// The protocol version of the frame that the request was received within.
+    pub version: u8,
+    /// The address of the server that the request is for.
+    pub server_address: ServerAddress,
+    /// The port of the server that the request is for.
+    pub server_port: ServerPort,
+    /// The request itself.
+    pub request: CommandRequest<C>,
+}
+
+impl<C: DeserializeOwned + Serialize> AddressedRequest<C> {
+    /// Address a request as per the header of the data frame it was received
+    /// within.
+    pub fn new(header: &Header, request: CommandRequest<C>) -> Self {
+        Self {
+            version: header.version,
+            server_address: header.server_address,
+            server_port: header.server_port,
+            request,
+        }
+    }
+
+    /// Whether the request is for a given server address and port.
+    pub fn is_for(&self, server_address: ServerAddress, server_port: ServerPort) -> bool {
+        self.server_address == server_address && self.server_port == server_port
+    }
+
+    /// Address a reply to this request, echoing its protocol version, server
+    /// address and port so that the client can tell which server replied.
+    pub fn reply<E: DeserializeOwned + Serialize>(&self, reply: Reply<E>) -> AddressedReply<E> {
+        AddressedReply {
+            version: self.version,
+            server_address: self.server_address,
+            server_port: self.server_port,
+            reply,
+        }
+    }
+}
+
+/// A [Reply] along with the server address and port that it is from, being
+/// those that the request replied to was sent to, and the protocol version to
+/// send it in, being that of the request.
+#[derive(Debug, PartialEq)]
+pub struct AddressedReply<E: DeserializeOwned + Serialize> {
+    /// The protocol version of the frame to convey the reply within.
+    pub version: u8,
+    /// The address of the server replying.
+    pub server_address: ServerAddress,
+    /// The port of the server replying.
+    pub server_port: ServerPort,
+    /// The reply itself.
+    pub reply: Reply<E>,
+}
+
+impl<E: DeserializeOwned + Serialize> AddressedReply<E> {
+    /// The header of the data frame to convey this reply within, being sourced
+    /// by the server in the request's protocol version, and having the given
+    /// frame counter.
+    pub fn header(&self, frame_counter: u16) -> Header {
+        Header {
+            version: self.version,
+            source: DataSource::Server,
+            server_address: self.server_address,
+            server_port: self.server_port,
+            frame_counter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{poll, Command, FixedClock};
+    use crate::{CommandReply, Server};
+    use flip_flop_data::DataFrame;
+
+    fn port(port: u8) -> ServerPort {
+        ServerPort::new_unchecked(port)
+    }
+
+    // Serves each port from one socket, dispatching each datagram received to
+    // the server for its port and replying with a datagram of its own.
+    struct Relay {
+        servers: [(ServerPort, Server<char, FixedClock, 4>); 2],
+        frame_counter: u16,
+    }
+
+    impl Relay {
+        fn receive(&mut self, datagram: &[u8], out: &mut [u8]) -> Option<usize> {
+            let (header, payload) = DataFrame::from_bytes(datagram).ok()?.parse().ok()?;
+            let request = postcard::from_bytes::<CommandRequest<Command>>(payload).ok()?;
+            let request = AddressedRequest::new(&header, request);
+            let (_, server) = self
+                .servers
+                .iter_mut()
+                .find(|(p, _)| request.is_for(header.server_address, *p))?;
+            let reply = server.handle_addressed_request(request);
+
+            let mut plaintext = [0; 32];
+            let plaintext = postcard::to_slice(&reply.reply, &mut plaintext).ok()?;
+            let header = reply.header(self.frame_counter);
+            self.frame_counter = self.frame_counter.wrapping_add(1);
+            DataFrame::new(&header, plaintext).to_bytes(out).ok()
+        }
+    }
+
+    // A client's datagram polling a port of the relay.
+    fn datagram(server_port: u8, last_event_offset: u32, out: &mut [u8]) -> usize {
+        datagram_of_version(0, server_port, last_event_offset, out)
+    }
+
+    fn datagram_of_version(
+        version: u8,
+        server_port: u8,
+        last_event_offset: u32,
+        out: &mut [u8],
+    ) -> usize {
+        let header = Header {
+            version,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: port(server_port),
+            frame_counter: 0,
+        };
+        let request = poll::<Command>(last_event_offset);
+        let mut plaintext = [0; 32];
+        let plaintext = postcard::to_slice(&request, &mut plaintext).unwrap();
+        DataFrame::new(&header, plaintext).to_bytes(out).unwrap()
+    }
+
+    // Poll a port of the relay, returning the port replied from and the event.
+    fn poll_port(relay: &mut Relay, server_port: u8, last_event_offset: u32) -> (u8, Option<char>) {
+        let mut datagram_buf = [0; 64];
+        let len = datagram(server_port, last_event_offset, &mut datagram_buf);
+        let mut out = [0; 64];
+        let len = relay.receive(&datagram_buf[..len], &mut out).unwrap();
+        let (header, payload) = DataFrame::from_bytes(&out[..len]).unwrap().parse().unwrap();
+        assert_eq!(header.source, DataSource::Server);
+        assert_eq!(header.server_address, ServerAddress::new_unchecked(1));
+        match postcard::from_bytes::<Reply<char>>(payload).unwrap() {
+            Reply::Event(CommandReply { event, .. }) => (header.server_port.get(), event),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn test_ports_have_independent_event_streams() {
+        let mut relay = Relay {
+            servers: [
+                (port(1), Server::new(FixedClock)),
+                (port(2), Server::new(FixedClock)),
+            ],
+            frame_counter: 0,
+        };
+        for e in ['a', 'b', 'c'] {
+            relay.servers[0].1.push_event(e);
+        }
+        for e in ['x', 'y'] {
+            relay.servers[1].1.push_event(e);
+        }
+
+        // Each port replies from its own events, and echoes the port.
+        assert_eq!(poll_port(&mut relay, 1, 0), (1, Some('b')));
+        assert_eq!(poll_port(&mut relay, 2, 0), (2, Some('y')));
+        assert_eq!(poll_port(&mut relay, 1, 1), (1, Some('c')));
+        assert_eq!(poll_port(&mut relay, 2, 1), (2, None));
+        assert_eq!(poll_port(&mut relay, 1, 2), (1, None));
+        assert_eq!(relay.frame_counter, 5);
+
+        // Requests for a port without a server are not replied to.
+        let mut datagram_buf = [0; 64];
+        let len = datagram(3, 0, &mut datagram_buf);
+        assert_eq!(relay.receive(&datagram_buf[..len], &mut [0; 64]), None);
+    }
+
+    #[test]
+    fn test_reply_is_in_the_request_version() {
+        let mut relay = Relay {
+            servers: [
+                (port(1), Server::new(FixedClock)),
+                (port(2), Server::new(FixedClock)),
+            ],
+            frame_counter: 0,
+        };
+        for version in [0, 1] {
+            let mut datagram_buf = [0; 64];
+            let len = datagram_of_version(version, 1, 0, &mut datagram_buf);
+            let mut out = [0; 64];
+            let len = relay.receive(&datagram_buf[..len], &mut out).unwrap();
+            let (header, _) = DataFrame::from_bytes(&out[..len]).unwrap().parse().unwrap();
+            assert_eq!(header.version, version);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+/// How the offset of an event received by a client relates to the offset that
+/// it last recorded for the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OffsetTransition {
+    /// The event immediately follows the last one recorded.
+    Contiguous,
+    /// Events have been missed between the last one recorded and this one,
+    /// e.g. having been dropped by a server that retains only a few.
+    Gap { missed: u32 },
+    /// The offset is less than or equal to the last one recorded, and so the
+    /// server has forgotten its state, or its offset has overflowed to zero.
+    /// The client should clear its state in relation to previous events.
+    Reset,
+    /// The offset is less than or equal to the last one recorded within the
+    /// same epoch, and so the event has already been received e.g. conveyed
+    /// by a duplicated reply. Only returned by [classify_epoch_offset], which
+    /// can tell this apart from a reset.
+    Duplicate,
+}
+
+/// Classify the transition from the last offset that a client recorded for a
+/// server to the offset of an event newly received from it. An application
+/// can then decide whether to resynchronise its state with the server.
+pub fn classify_offset(last_event_offset: u32, received: u32) -> OffsetTransition {
+    if received <= last_event_offset {
+        OffsetTransition::Reset
+    } else if received - last_event_offset == 1 {
+        OffsetTransition::Contiguous
+    } else {
+        OffsetTransition::Gap {
+            missed: received - last_event_offset - 1,
+        }
+    }
+}
+
+/// Classify the transition from the last epoch and offset that a client
+/// recorded for a server to those of an event newly received from it, as
+/// conveyed by [crate::CommandReply]. A change of epoch is always a reset, even
+/// where the offset received would otherwise be taken as progress. Within an
+/// epoch, an offset that is not beyond the last one is a duplicate.
+pub fn classify_epoch_offset(last: (u16, u32), received: (u16, u32)) -> OffsetTransition {
+    let ((last_epoch, last_event_offset), (epoch, offset)) = (last, received);
+    if epoch != last_epoch {
+        OffsetTransition::Reset
+    } else if offset <= last_event_offset {
+        OffsetTransition::Duplicate
+    } else {
+        classify_offset(last_event_offset, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_offset() {
+        assert_eq!(classify_offset(0, 1), OffsetTransition::Contiguous);
+        assert_eq!(classify_offset(9, 10), OffsetTransition::Contiguous);
+        assert_eq!(classify_offset(9, 13), OffsetTransition::Gap { missed: 3 });
+        assert_eq!(classify_offset(9, 9), OffsetTransition::Reset);
+        assert_eq!(classify_offset(9, 0), OffsetTransition::Reset);
+    }
+
+    #[test]
+    fn test_classify_offset_at_limits() {
+        assert_eq!(
+            classify_offset(u32::MAX - 1, u32::MAX),
+            OffsetTransition::Contiguous
+        );
+        assert_eq!(
+            classify_offset(0, u32::MAX),
+            OffsetTransition::Gap {
+                missed: u32::MAX - 1
+            }
+        );
+
+        // An offset overflowing to zero is conveyed as a reset, as the server
+        // is then responsible for conveying any events that the client needs.
+        assert_eq!(classify_offset(u32::MAX, 0), OffsetTransition::Reset);
+        assert_eq!(classify_offset(u32::MAX, 1), OffsetTransition::Reset);
+    }
+
+    #[test]
+    fn test_classify_epoch_offset() {
+        assert_eq!(
+            classify_epoch_offset((0, 9), (0, 10)),
+            OffsetTransition::Contiguous
+        );
+        assert_eq!(
+            classify_epoch_offset((0, 9), (0, 12)),
+            OffsetTransition::Gap { missed: 2 }
+        );
+        assert_eq!(
+            classify_epoch_offset((0, 9), (0, 9)),
+            OffsetTransition::Duplicate
+        );
+        assert_eq!(
+            classify_epoch_offset((0, 9), (0, 3)),
+            OffsetTransition::Duplicate
+        );
+
+        // A new epoch is a reset, whatever the offset.
+        assert_eq!(
+            classify_epoch_offset((0, 9), (1, 9)),
+            OffsetTransition::Reset
+        );
+        assert_eq!(
+            classify_epoch_offset((0, 9), (1, 10)),
+            OffsetTransition::Reset
+        );
+        assert_eq!(
+            classify_epoch_offset((u16::MAX, 9), (0, 0)),
+            OffsetTransition::Reset
+        );
+    }
+}
@@ -0,0 +1,1030 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use flip_flop_data::{ProtocolVersion, HEADER_SIZE};
+
+use crate::{
+    clocked_event_reply, AddressedReply, AddressedRequest, CatchUpPolicy, Clock, CommandReply,
+    CommandRequest, Discriminant, DiscriminantSet, EventLog, Outcome, Peer, PeerStats, Reply,
+    Stats, WireMessage,
+};
+
+/// Handles the commands received by a server, giving it full control over
+/// what is replied. Handlers are called by a [ServerRuntime] and so only
+/// receive requests that it has validated.
+///
+/// Closures taking a command, last event offset and subscriptions are also
+/// handlers.
+pub trait CommandHandler<C, E>
+where
+    C: DeserializeOwned + Serialize,
+    E: DeserializeOwned + Serialize,
+{
+    /// Handle a request's command, or its absence when the client is just
+    /// polling for the next event, given the last event offset that the client
+    /// has recorded and the discriminants of the events it subscribes to.
+    fn on_command(
+        &mut self,
+        command: Option<C>,
+        last_event_offset: u32,
+        subscriptions: DiscriminantSet,
+    ) -> Reply<E>;
+
+    /// Handle the acknowledgement of a request, being the offset following
+    /// that of the last event the client has committed along with its epoch,
+    /// as per [CommandRequest::acked_offset] and [CommandRequest::acked_epoch].
+    /// This is called before the request's command is handled, and only for
+    /// requests that are accepted. By default, acknowledgements are ignored.
+    fn on_acknowledged(&mut self, _acked_epoch: u16, _acked_offset: u32) {}
+
+    /// True if a client's last event offset is beyond any that the handler has
+    /// assigned, as per [Stats::stale]. By default, no offset is stale.
+    fn is_stale(&self, _last_event_offset: u32) -> bool {
+        false
+    }
+}
+
+impl<C, E, F> CommandHandler<C, E> for F
+where
+    C: DeserializeOwned + Serialize,
+    E: DeserializeOwned + Serialize,
+    F: FnMut(Option<C>, u32, DiscriminantSet) -> Reply<E>,
+{
+    fn on_command(
+        &mut self,
+        command: Option<C>,
+        last_event_offset: u32,
+        subscriptions: DiscriminantSet,
+    ) -> Reply<E> {
+        self(command, last_event_offset, subscriptions)
+    }
+}
+
+/// A handler that replies from an [EventLog], with the ages of events determined
+/// by a [Clock]. Commands are not acted upon, only conveying the client's last
+/// event offset; applications acting upon commands wrap this handler with their
+/// own.
+///
+/// The reply is the next event that the client has yet to receive, or that there are
+/// no more events. Should the client have an offset beyond any that the log has
+/// assigned then the log must have been cleared. The oldest event is then replied
+/// so that the client can detect this and forget its prior events.
+///
+/// Only events that the client subscribes to are replied. Others are skipped, with
+/// the offset of the event replied conveying to the client that it has moved past
+/// them.
+///
+/// Events that the client acknowledges having committed are forgotten, so that
+/// the log's capacity is spent on those it has yet to commit.
+///
+/// A client that is several events behind is replied the next of them, or the
+/// latest, as per its [CatchUpPolicy].
+///
+/// Replies convey the clock's current time if `server_time` is set, as per
+/// [CommandReply::server_time].
+pub struct EventLogHandler<E, K, const N: usize> {
+    pub log: EventLog<E, u64, N>,
+    pub clock: K,
+    pub policy: CatchUpPolicy,
+    pub server_time: bool,
+}
+
+impl<E: Discriminant, K, const N: usize> EventLogHandler<E, K, N> {
+    /// The number of events subscribed to that a client has yet to receive, for
+    /// replying to a [crate::ControlCommand::Heartbeat] with
+    /// [Reply::EventsPending].
+    pub fn events_pending(&self, last_event_offset: u32, subscriptions: DiscriminantSet) -> u32 {
+        let subscribed = |(e, _, _): &&(E, u32, u64)| subscriptions.contains(e.discriminant());
+        let count = if last_event_offset >= self.log.next_offset() {
+            self.log.iter().filter(subscribed).count()
+        } else {
+            self.log
+                .events_after(last_event_offset, N)
+                .filter(subscribed)
+                .count()
+        };
+        count as u32
+    }
+}
+
+impl<C, E, K, const N: usize> CommandHandler<C, E> for EventLogHandler<E, K, N>
+where
+    C: DeserializeOwned + Serialize,
+    E: Clone + DeserializeOwned + Discriminant + Serialize,
+    K: Clock,
+{
+    // Events acknowledged are forgotten, freeing capacity for those the client
+    // has yet to commit.
+    fn on_acknowledged(&mut self, acked_epoch: u16, acked_offset: u32) {
+        self.log.prune_before(acked_epoch, acked_offset);
+    }
+
+    // Offsets from the next one to be assigned are replied to as following a
+    // reset, save for a client yet to receive any event from a log yet to
+    // assign any.
+    fn is_stale(&self, last_event_offset: u32) -> bool {
+        let next_offset = self.log.next_offset();
+        last_event_offset >= next_offset && !(last_event_offset == 0 && next_offset == 0)
+    }
+
+    fn on_command(
+        &mut self,
+        _command: Option<C>,
+        last_event_offset: u32,
+        subscriptions: DiscriminantSet,
+    ) -> Reply<E> {
+        let maybe_event = self.log.select_event(last_event_offset, self.policy, |e| {
+            subscriptions.contains(e.discriminant())
+        });
+        let mut reply = clocked_event_reply(maybe_event, &self.clock);
+        reply.epoch = self.log.epoch();
+        if self.server_time {
+            reply.server_time = Some(self.clock.now());
+        }
+        Reply::Event(reply)
+    }
+}
+
+/// Runs a server's handling of the command requests it receives, validating
+/// them before they reach its [CommandHandler]. Outcomes are counted by
+/// [Stats], and also for each of up to `P` peers, as per [PeerStats].
+pub struct ServerRuntime<H, const P: usize = 0> {
+    handler: H,
+    accepted_commands: DiscriminantSet,
+    max_datagram: usize,
+    mac_size: usize,
+    highest_version: ProtocolVersion,
+    stats: Stats,
+    peer_stats: PeerStats<P>,
+}
+
+impl<H> ServerRuntime<H> {
+    /// Create a runtime that passes all requests to a handler.
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            accepted_commands: DiscriminantSet::ALL,
+            max_datagram: usize::MAX,
+            mac_size: 0,
+            highest_version: ProtocolVersion::LATEST,
+            stats: Stats::default(),
+            peer_stats: PeerStats::default(),
+        }
+    }
+}
+
+impl<H, const P: usize> ServerRuntime<H, P> {
+    /// Count outcomes for each of up to `Q` peers, as per [PeerStats], with any
+    /// counted so far for peers being forgotten.
+    pub fn tracking_peers<const Q: usize>(self) -> ServerRuntime<H, Q> {
+        ServerRuntime {
+            handler: self.handler,
+            accepted_commands: self.accepted_commands,
+            max_datagram: self.max_datagram,
+            mac_size: self.mac_size,
+            highest_version: self.highest_version,
+            stats: self.stats,
+            peer_stats: PeerStats::default(),
+        }
+    }
+
+    /// The handler of requests.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// The handler of requests, mutably e.g. for recording events.
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Accept only commands whose discriminants are members of a set. Other
+    /// commands are replied to with [Reply::Nack] without reaching the handler.
+    /// Requests without a command are always accepted given that they are
+    /// mandatory.
+    pub fn accepting(mut self, commands: DiscriminantSet) -> Self {
+        self.accepted_commands = commands;
+        self
+    }
+
+    /// Limit replies to those that, once conveyed within a data frame sealed
+    /// with a MAC of a given size, fit within a datagram of a given size e.g.
+    /// the MTU of the transport. A reply conveying an event that would exceed
+    /// the size is replaced by [Reply::Truncated], so that the client is still
+    /// replied to and can move past the event, and is counted by
+    /// [Stats::reply_truncated]. Other replies are sent as they are. By
+    /// default, replies are not limited.
+    pub fn max_datagram(mut self, max_datagram: usize, mac_size: usize) -> Self {
+        self.max_datagram = max_datagram;
+        self.mac_size = mac_size;
+        self
+    }
+
+    /// Support protocol versions up to a given one, with requests of a newer
+    /// version being replied to with [Reply::UnsupportedVersion]. By default,
+    /// this is [ProtocolVersion::LATEST].
+    pub fn supporting_up_to(mut self, highest_version: ProtocolVersion) -> Self {
+        self.highest_version = highest_version;
+        self
+    }
+
+    /// The highest protocol version supported.
+    pub fn highest_version(&self) -> ProtocolVersion {
+        self.highest_version
+    }
+
+    /// The protocol version to reply to a request in, given the version of the
+    /// header it was received with. This is the request's own version so long
+    /// as it is supported, so that a server downgrades to the version of an
+    /// older client. Otherwise, the reply to send in place of handling the
+    /// request is returned, being [Reply::UnsupportedVersion], which is to be
+    /// sent in [ServerRuntime::highest_version].
+    ///
+    /// The version should be negotiated before the request's payload is
+    /// decoded, as its encoding may differ between versions.
+    pub fn negotiate_version<E>(&self, version: u8) -> Result<ProtocolVersion, Reply<E>>
+    where
+        E: DeserializeOwned + Serialize,
+    {
+        match ProtocolVersion::new(version) {
+            Ok(version) if version <= self.highest_version => Ok(version),
+            _ => Err(Reply::UnsupportedVersion {
+                highest: self.highest_version,
+            }),
+        }
+    }
+
+    /// The counters of notable outcomes so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// The counters of notable outcomes so far, mutably for recording those
+    /// that arise before requests reach the runtime.
+    pub fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    /// The counters of notable outcomes so far for each peer.
+    pub fn peer_stats(&self) -> &PeerStats<P> {
+        &self.peer_stats
+    }
+
+    /// Count an outcome, both by [ServerRuntime::stats] and for the peer that
+    /// it arose from, if known.
+    pub fn record(&mut self, peer: Option<Peer>, outcome: impl Into<Outcome>) {
+        let outcome = outcome.into();
+        self.stats.record(outcome);
+        self.peer_stats.record(peer, outcome);
+    }
+
+    /// Handle a command request, returning the reply to send to the client.
+    /// Requests conveying a last event offset that the handler regards as
+    /// stale, as per [CommandHandler::is_stale], are counted by [Stats::stale].
+    pub fn handle<C, E>(&mut self, request: CommandRequest<C>) -> Reply<E>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        self.handle_from(None, request)
+    }
+
+    /// Handle a command request received from a peer, as per
+    /// [ServerRuntime::handle], counting its outcomes for the peer.
+    pub fn handle_from<C, E>(&mut self, peer: Option<Peer>, request: CommandRequest<C>) -> Reply<E>
+    where
+        H: CommandHandler<C, E>,
+        C: DeserializeOwned + Discriminant + Serialize,
+        E: Clone + DeserializeOwned + Serialize + WireMessage,
+    {
+        if self.handler.is_stale(request.last_event_offset) {
+            self.record(peer, Outcome::Stale);
+        }
+        let reply = match &request.command {
+            Some(command) if !self.accepted_commands.contains(command.discriminant()) => {
+                Reply::Nack
+            }
+            _ => {
+                self.handler
+                    .on_acknowledged(request.acked_epoch, request.acked_offset);
+                self.handler.on_command(
+                    request.command,
+                    request.last_event_offset,
+                    request.subscriptions,
+                )
+            }
+        };
+        match reply {
+            Reply::Event(CommandReply {
+                epoch,
+                offset,
+                event: Some(_),
+                ..
+            }) if HEADER_SIZE + reply.encoded_len() + self.mac_size > self.max_datagram => {
+                self.record(peer, Outcome::ReplyTruncated);
+                Reply::Truncated { epoch, offset }
+            }
+            reply => reply,
+        }
+    }
+}
+
+/// A transport-independent server that records events and replies to the
+/// command requests of a client with them, retaining at most `N` events. This
+/// is a [ServerRuntime] with an [EventLogHandler], for servers whose commands
+/// need not be acted upon beyond conveying what the client has received. A
+/// server's transport, be it UDP or a serial link, then only need convey
+/// requests and replies.
+///
+/// Events are replied in the order that they were recorded, with each reply
+/// conveying the next event that the client has yet to receive. Once a server
+/// is reset, its replies convey a new epoch so that the client can forget its
+/// prior events, and a client having an offset beyond any since assigned is
+/// replied with the oldest event retained.
+pub struct Server<E, K, const N: usize> {
+    runtime: ServerRuntime<EventLogHandler<E, K, N>>,
+}
+
+impl<E, K, const N: usize> Server<E, K, N>
+where
+    E: Clone + DeserializeOwned + Discriminant + Serialize + WireMessage,
+    K: Clock,
+{
+    /// Create a server with no events, whose events are timed by a clock.
+    pub fn new(clock: K) -> Self {
+        Self {
+            runtime: ServerRuntime::new(EventLogHandler {
+                log: EventLog::new(),
+                clock,
+                policy: CatchUpPolicy::OldestUnseen,
+                server_time: false,
+            }),
+        }
+    }
+
+    /// Record an event as having occurred now, returning the offset it has
+    /// been assigned.
+    pub fn push_event(&mut self, event: E) -> u32 {
+        let handler = self.runtime.handler_mut();
+        let now = handler.clock.now();
+        handler.log.push(event, now)
+    }
+
+    /// Reply to clients that are behind as per a policy. By default, this is
+    /// [CatchUpPolicy::OldestUnseen].
+    pub fn catching_up(mut self, policy: CatchUpPolicy) -> Self {
+        self.runtime.handler_mut().policy = policy;
+        self
+    }
+
+    /// Convey the server's current time in replies, as per
+    /// [CommandReply::server_time]. By default, it is not conveyed.
+    pub fn conveying_server_time(mut self) -> Self {
+        self.runtime.handler_mut().server_time = true;
+        self
+    }
+
+    /// Support protocol versions up to a given one, as per
+    /// [ServerRuntime::supporting_up_to].
+    pub fn supporting_up_to(self, highest_version: ProtocolVersion) -> Self {
+        Self {
+            runtime: self.runtime.supporting_up_to(highest_version),
+        }
+    }
+
+    /// The highest protocol version supported.
+    pub fn highest_version(&self) -> ProtocolVersion {
+        self.runtime.highest_version()
+    }
+
+    /// The protocol version to reply to a request in, as per
+    /// [ServerRuntime::negotiate_version].
+    pub fn negotiate_version(&self, version: u8) -> Result<ProtocolVersion, Reply<E>> {
+        self.runtime.negotiate_version(version)
+    }
+
+    /// Forget all events, with the next one recorded being assigned an offset
+    /// of 0, and bump the epoch conveyed by replies.
+    pub fn reset(&mut self) {
+        self.runtime.handler_mut().log.clear();
+    }
+
+    /// The events recorded and retained.
+    pub fn log(&self) -> &EventLog<E, u64, N> {
+        &self.runtime.handler().log
+    }
+
+    /// The counters of notable outcomes so far.
+    pub fn stats(&self) -> &Stats {
+        self.runtime.stats()
+    }
+
+    /// The counters of notable outcomes so far, mutably for recording those
+    /// that arise before requests reach the server e.g. frames failing to
+    /// parse.
+    pub fn stats_mut(&mut self) -> &mut Stats {
+        self.runtime.stats_mut()
+    }
+
+    /// Limit replies to those that fit within a datagram of a given size, as
+    /// per [ServerRuntime::max_datagram].
+    pub fn max_datagram(mut self, max_datagram: usize, mac_size: usize) -> Self {
+        self.runtime = self.runtime.max_datagram(max_datagram, mac_size);
+        self
+    }
+
+    /// Handle a command request, returning the reply to send to the client.
+    /// Requests conveying a last event offset beyond any assigned, being at
+    /// least the next offset to be assigned, are counted by [Stats::stale],
+    /// save for an offset of 0 before any event has been assigned one, as
+    /// from a client yet to receive any.
+    pub fn handle_request<C>(&mut self, request: CommandRequest<C>) -> Reply<E>
+    where
+        C: DeserializeOwned + Discriminant + Serialize,
+    {
+        self.runtime.handle(request)
+    }
+
+    /// Handle a command request addressed to this server, as per
+    /// [Server::handle_request], returning the reply addressed from the same
+    /// server address and port, in the same protocol version.
+    pub fn handle_addressed_request<C>(&mut self, request: AddressedRequest<C>) -> AddressedReply<E>
+    where
+        C: DeserializeOwned + Discriminant + Serialize,
+    {
+        let AddressedRequest {
+            version,
+            server_address,
+            server_port,
+            request,
+        } = request;
+        AddressedReply {
+            version,
+            server_address,
+            server_port,
+            reply: self.handle_request(request),
+        }
+    }
+
+    /// Handle a command request as per [Server::handle_request], echoing the
+    /// frame counter of the request's data frame, except that a reply
+    /// conveying no more events is replaced with [Reply::Alive]. A client
+    /// polling an idle server is thereby assured that the server is alive.
+    pub fn handle_request_with_keepalive<C>(
+        &mut self,
+        frame_counter: u16,
+        request: CommandRequest<C>,
+    ) -> Reply<E>
+    where
+        C: DeserializeOwned + Discriminant + Serialize,
+    {
+        match self.handle_request(request) {
+            Reply::Event(CommandReply { event: None, .. }) => Reply::Alive { frame_counter },
+            reply => reply.echoing(frame_counter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{poll, Command, FixedClock};
+    use crate::{classify_epoch_offset, classify_offset, event_reply, OffsetTransition};
+    use flip_flop_data::MAC_SIZE;
+
+    #[test]
+    fn test_disallowed_command_is_nacked() {
+        let mut handled = Vec::new();
+        let mut runtime = ServerRuntime::new(|command, last_event_offset, _| {
+            handled.push(command);
+            Reply::Event(event_reply(Some(&('a', last_event_offset + 1, 0)), |t| t))
+        })
+        .accepting(DiscriminantSet::EMPTY.with(0).with(1));
+
+        let request = |command| CommandRequest { command, ..poll(1) };
+
+        assert_eq!(runtime.handle(request(Some(Command::Erase))), Reply::Nack);
+        assert!(matches!(
+            runtime.handle(request(Some(Command::Open))),
+            Reply::Event(CommandReply {
+                event: Some('a'),
+                offset: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            runtime.handle(request(None)),
+            Reply::Event(CommandReply {
+                event: Some('a'),
+                offset: 2,
+                ..
+            })
+        ));
+
+        assert_eq!(handled, [Some(Command::Open), None]);
+    }
+
+    #[test]
+    fn test_custom_handler() {
+        // A stateless handler that computes its reply from the command.
+        struct Computer;
+
+        impl CommandHandler<Command, u16> for Computer {
+            fn on_command(
+                &mut self,
+                command: Option<Command>,
+                last_event_offset: u32,
+                _subscriptions: DiscriminantSet,
+            ) -> Reply<u16> {
+                let maybe_event =
+                    command.map(|c| (u16::from(c.discriminant()) * 100, last_event_offset + 1, 0));
+                Reply::Event(event_reply(maybe_event.as_ref(), |t| t))
+            }
+        }
+
+        let mut runtime = ServerRuntime::new(Computer);
+        let reply = runtime.handle(CommandRequest {
+            command: Some(Command::Erase),
+            ..poll(7)
+        });
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply {
+                event: Some(200),
+                offset: 8,
+                ..
+            })
+        ));
+        let reply = runtime.handle(poll(8));
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply { event: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_event_log_handler() {
+        let mut runtime = ServerRuntime::new(EventLogHandler::<_, _, 4> {
+            log: EventLog::new(),
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        });
+        for (t, e) in ('a'..='c').enumerate() {
+            runtime.handler_mut().log.push(e, 90 + t as u64);
+        }
+
+        let mut poll = |last_event_offset| match runtime.handle(poll::<Command>(last_event_offset))
+        {
+            Reply::Event(reply) => (reply.age.get(), reply.into_event()),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // The next event...
+        assert_eq!(poll(0), (9, Some(('b', 1))));
+        assert_eq!(poll(1), (8, Some(('c', 2))));
+        // ...no more events...
+        assert_eq!(poll(2), (0, None));
+        // ...and the log is behind the client, so has been cleared.
+        assert_eq!(poll(7), (10, Some(('a', 0))));
+    }
+
+    #[test]
+    fn test_oversized_reply_is_truncated() {
+        let runtime = ServerRuntime::new(|_, last_event_offset: u32, _| {
+            Reply::Event(event_reply(
+                Some(&(0xFFu32, last_event_offset + 1, 0)),
+                |t| t,
+            ))
+        });
+
+        let poll = |runtime: &mut ServerRuntime<_>| runtime.handle::<Command, u32>(poll(1));
+
+        // A reply with an event requires 4 more bytes than one without, being
+        // the event.
+        let no_event_reply = Reply::Event(event_reply::<u32, u64, _>(None, |t| t));
+        let no_event_frame_len = HEADER_SIZE + no_event_reply.encoded_len() + MAC_SIZE;
+        let event_frame_len = no_event_frame_len + 4;
+
+        let mut runtime = runtime.max_datagram(event_frame_len, MAC_SIZE);
+        assert!(matches!(
+            poll(&mut runtime),
+            Reply::Event(CommandReply {
+                event: Some(0xFF),
+                offset: 2,
+                ..
+            })
+        ));
+        assert_eq!(runtime.stats().reply_truncated, 0);
+
+        let mut runtime = runtime.max_datagram(event_frame_len - 1, MAC_SIZE);
+        assert_eq!(
+            poll(&mut runtime),
+            Reply::Truncated {
+                epoch: 0,
+                offset: 2
+            }
+        );
+        assert_eq!(runtime.stats().reply_truncated, 1);
+
+        // The MAC is accounted for.
+        let mut runtime = runtime.max_datagram(event_frame_len, MAC_SIZE + 1);
+        assert!(matches!(poll(&mut runtime), Reply::Truncated { .. }));
+        assert_eq!(runtime.stats().reply_truncated, 2);
+    }
+
+    #[test]
+    fn test_unsubscribed_events_are_skipped() {
+        let mut runtime = ServerRuntime::new(EventLogHandler::<_, _, 8> {
+            log: EventLog::new(),
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        });
+        for e in ['a', 'b', 'b', 'c', 'b'] {
+            runtime.handler_mut().log.push(e, 100);
+        }
+
+        let subscriptions = DiscriminantSet::EMPTY
+            .with('a'.discriminant())
+            .with('c'.discriminant());
+        let mut poll = |last_event_offset| match runtime.handle(CommandRequest::<Command> {
+            last_event_offset,
+            subscriptions,
+            acked_offset: 0,
+            acked_epoch: 0,
+            command: None,
+        }) {
+            Reply::Event(reply) => reply.into_event(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // Offsets 1 and 2 are skipped...
+        assert_eq!(poll(0), Some(('c', 3)));
+        // ...as is offset 4, with there being no more events subscribed to...
+        assert_eq!(poll(3), None);
+        // ...and the oldest subscribed event when the log is behind the client.
+        assert_eq!(poll(9), Some(('a', 0)));
+    }
+
+    #[test]
+    fn test_server_catches_up() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        for e in 'a'..='d' {
+            server.push_event(e);
+        }
+
+        let mut poll =
+            |last_event_offset| match server.handle_request(poll::<Command>(last_event_offset)) {
+                Reply::Event(reply) => reply.into_event(),
+                reply => panic!("unexpected reply {:?}", reply),
+            };
+
+        // A client having received the first event is replied the others in
+        // turn, and then that there are no more.
+        assert_eq!(poll(0), Some(('b', 1)));
+        assert_eq!(poll(1), Some(('c', 2)));
+        assert_eq!(poll(2), Some(('d', 3)));
+        assert_eq!(poll(3), None);
+    }
+
+    #[test]
+    fn test_server_catches_up_to_latest() {
+        let mut server =
+            Server::<char, _, 4>::new(FixedClock).catching_up(CatchUpPolicy::LatestOnly);
+        for e in 'a'..='d' {
+            server.push_event(e);
+        }
+
+        let mut poll =
+            |last_event_offset| match server.handle_request(poll::<Command>(last_event_offset)) {
+                Reply::Event(reply) => reply.into_event(),
+                reply => panic!("unexpected reply {:?}", reply),
+            };
+
+        // A client having received the first event skips to the latest.
+        assert_eq!(poll(0), Some(('d', 3)));
+        assert_eq!(poll(3), None);
+    }
+
+    #[test]
+    fn test_acknowledged_events_are_pruned() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        for e in 'a'..='f' {
+            server.push_event(e);
+        }
+
+        let mut poll = |last_event_offset, acked_offset| match server.handle_request(
+            CommandRequest::<Command> {
+                acked_offset,
+                ..poll(last_event_offset)
+            },
+        ) {
+            Reply::Event(reply) => reply.into_event(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // The ring has wrapped, retaining offsets 2..=5. A client that has
+        // committed up to offset 3 is replied the next event...
+        assert_eq!(poll(3, 4), Some(('e', 4)));
+        assert_eq!(poll(4, 4), Some(('f', 5)));
+        let offsets = |server: &Server<char, _, 4>| {
+            server.log().iter().map(|(_, o, _)| *o).collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(&server), [4, 5]);
+
+        // ...and the events freed make room for new ones without evicting
+        // those that have yet to be acknowledged.
+        server.push_event('g');
+        server.push_event('h');
+        assert_eq!(offsets(&server), [4, 5, 6, 7]);
+        server.push_event('i');
+        assert_eq!(offsets(&server), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_acknowledgements_of_a_prior_epoch_are_ignored() {
+        let mut server = Server::<char, _, 8>::new(FixedClock);
+        for e in 'a'..='f' {
+            server.push_event(e);
+        }
+        server.reset();
+        for e in 'g'..='n' {
+            server.push_event(e);
+        }
+
+        // A client yet to learn of the reset acknowledges offsets of the prior
+        // epoch, which have since been assigned to events it has yet to
+        // receive. They are kept until acknowledged within the new epoch.
+        let request = |acked_epoch| CommandRequest::<Command> {
+            acked_offset: 6,
+            acked_epoch,
+            ..poll(5)
+        };
+        server.handle_request(request(0));
+        assert_eq!(server.log().len(), 8);
+        server.handle_request(request(1));
+        assert_eq!(server.log().len(), 2);
+    }
+
+    #[test]
+    fn test_rejected_requests_acknowledge_nothing() {
+        let mut runtime = ServerRuntime::new(EventLogHandler::<_, _, 4> {
+            log: EventLog::new(),
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        })
+        .accepting(DiscriminantSet::EMPTY.with(Command::Open.discriminant()));
+        for e in 'a'..='d' {
+            runtime.handler_mut().log.push(e, 100);
+        }
+
+        let request = |command| CommandRequest {
+            acked_offset: 2,
+            command: Some(command),
+            ..poll(3)
+        };
+        assert_eq!(runtime.handle(request(Command::Close)), Reply::<char>::Nack);
+        assert_eq!(runtime.handler().log.len(), 4);
+        assert_ne!(runtime.handle(request(Command::Open)), Reply::<char>::Nack);
+        assert_eq!(runtime.handler().log.len(), 2);
+    }
+
+    #[test]
+    fn test_stale_offset_is_counted() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+
+        let request = |last_event_offset| poll::<Command>(last_event_offset);
+
+        // A client yet to receive an event is not stale when the server has yet
+        // to assign any...
+        server.handle_request(request(0));
+        assert_eq!(server.stats().stale, 0);
+        server.push_event('a');
+        server.push_event('b');
+
+        // ...nor are offsets that the server has assigned...
+        server.handle_request(request(0));
+        server.handle_request(request(1));
+        assert_eq!(server.stats().stale, 0);
+
+        // ...whereas those beyond, including the next to be assigned, are, as
+        // they are replied to as following a reset.
+        server.handle_request(request(2));
+        assert_eq!(server.stats().stale, 1);
+        server.reset();
+        server.handle_request(request(1));
+        assert_eq!(server.stats().stale, 2);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let server = Server::<char, _, 4>::new(FixedClock);
+        assert_eq!(server.highest_version(), ProtocolVersion::LATEST);
+        assert_eq!(server.negotiate_version(0), Ok(ProtocolVersion::V0));
+        assert_eq!(server.negotiate_version(1), Ok(ProtocolVersion::V1));
+        assert_eq!(
+            server.negotiate_version(2),
+            Err(Reply::UnsupportedVersion {
+                highest: ProtocolVersion::V1
+            })
+        );
+
+        // A server supporting only version 0 declines version 1.
+        let server = server.supporting_up_to(ProtocolVersion::V0);
+        assert_eq!(server.negotiate_version(0), Ok(ProtocolVersion::V0));
+        assert_eq!(
+            server.negotiate_version(1),
+            Err(Reply::UnsupportedVersion {
+                highest: ProtocolVersion::V0
+            })
+        );
+    }
+
+    #[test]
+    fn test_up_to_date_client_is_replied_alive() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        server.push_event('a');
+        server.push_event('b');
+
+        let mut poll = |frame_counter, last_event_offset| {
+            server.handle_request_with_keepalive(frame_counter, poll::<Command>(last_event_offset))
+        };
+
+        // A lagging client is replied the next event, for its request...
+        assert!(matches!(
+            poll(7, 0),
+            Reply::Event(CommandReply {
+                frame_counter: 7,
+                event: Some('b'),
+                offset: 1,
+                ..
+            })
+        ));
+
+        // ...and an up-to-date one is replied a keepalive for its request,
+        // rather than a stale event.
+        assert_eq!(poll(8, 1), Reply::Alive { frame_counter: 8 });
+        assert_eq!(poll(9, 1), Reply::Alive { frame_counter: 9 });
+    }
+
+    #[test]
+    fn test_server_time_is_conveyed() {
+        let request = || poll::<Command>(0);
+
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        assert!(matches!(
+            server.handle_request(request()),
+            Reply::Event(CommandReply {
+                server_time: None,
+                ..
+            })
+        ));
+
+        let mut server = Server::<char, _, 4>::new(FixedClock).conveying_server_time();
+        assert!(matches!(
+            server.handle_request(request()),
+            Reply::Event(CommandReply {
+                server_time: Some(100),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_server_reset() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        for e in 'a'..='c' {
+            server.push_event(e);
+        }
+        server.reset();
+        assert!(server.log().is_empty());
+        assert_eq!(server.push_event('x'), 0);
+        assert_eq!(server.push_event('y'), 1);
+
+        // The client is ahead of the server, so is replied its oldest event,
+        // which it classifies as a reset.
+        let reply = server.handle_request(poll::<Command>(2));
+        assert!(matches!(
+            reply,
+            Reply::Event(CommandReply {
+                event: Some('x'),
+                offset: 0,
+                ..
+            })
+        ));
+        assert_eq!(classify_offset(2, 0), OffsetTransition::Reset);
+    }
+
+    #[test]
+    fn test_reset_is_detected_by_epoch() {
+        let mut server = Server::<char, _, 4>::new(FixedClock);
+        server.push_event('a');
+        server.push_event('b');
+
+        let poll = |server: &mut Server<char, _, 4>, last_event_offset| match server
+            .handle_request(poll::<Command>(last_event_offset))
+        {
+            Reply::Event(CommandReply {
+                epoch,
+                offset,
+                event: Some(e),
+                ..
+            }) => (epoch, e, offset),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // The client receives 'b' and records its epoch and offset.
+        let last = (0, 1);
+        assert_eq!(poll(&mut server, 0), (0, 'b', 1));
+
+        // The server resets and records more events than the client had seen,
+        // so that the client's poll is replied with what looks like progress.
+        server.reset();
+        for e in 'x'..='z' {
+            server.push_event(e);
+        }
+        let (epoch, e, offset) = poll(&mut server, 1);
+        assert_eq!((epoch, e, offset), (1, 'z', 2));
+        assert_eq!(
+            classify_offset(last.1, offset),
+            OffsetTransition::Contiguous
+        );
+        assert_eq!(
+            classify_epoch_offset(last, (epoch, offset)),
+            OffsetTransition::Reset
+        );
+
+        // A reply whose offset coincides with the one last recorded is also a
+        // reset, rather than a duplicate of it.
+        let (epoch, e, offset) = poll(&mut server, 0);
+        assert_eq!((epoch, e, offset), (1, 'y', 1));
+        assert_eq!(
+            classify_epoch_offset(last, (epoch, offset)),
+            OffsetTransition::Reset
+        );
+        assert_eq!(
+            classify_epoch_offset(last, (0, 1)),
+            OffsetTransition::Duplicate
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_reports_pending_events() {
+        use crate::ControlCommand;
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        enum Command {
+            Control(ControlCommand),
+        }
+
+        impl Discriminant for Command {
+            fn discriminant(&self) -> u8 {
+                0
+            }
+        }
+
+        // The application wraps the event log handler to reply to heartbeats.
+        let mut log_handler = EventLogHandler::<_, _, 8> {
+            log: EventLog::new(),
+            clock: FixedClock,
+            policy: CatchUpPolicy::OldestUnseen,
+            server_time: false,
+        };
+        log_handler.log.push('a', 100);
+        log_handler.log.push('b', 100);
+        let mut runtime =
+            ServerRuntime::new(|command, last_event_offset, subscriptions| match command {
+                Some(Command::Control(ControlCommand::Heartbeat)) => Reply::EventsPending(
+                    log_handler.events_pending(last_event_offset, subscriptions),
+                ),
+                command => log_handler.on_command(command, last_event_offset, subscriptions),
+            });
+
+        let mut request = |last_event_offset, command| {
+            runtime.handle::<Command, char>(CommandRequest {
+                command,
+                ..poll(last_event_offset)
+            })
+        };
+        let heartbeat = || Some(Command::Control(ControlCommand::Heartbeat));
+
+        // The heartbeat reports an event pending without conveying it...
+        assert_eq!(request(0, heartbeat()), Reply::EventsPending(1));
+
+        // ...so a full poll is made for it...
+        assert!(matches!(
+            request(0, None),
+            Reply::Event(CommandReply {
+                event: Some('b'),
+                offset: 1,
+                ..
+            })
+        ));
+
+        // ...after which there are none pending.
+        assert_eq!(request(1, heartbeat()), Reply::EventsPending(0));
+    }
+}
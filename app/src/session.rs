@@ -0,0 +1,149 @@
+use heapless::Vec;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::CommandRequest;
+
+/// Commands that control a client's session with a server rather than
+/// the application itself. An application conveys these by including them
+/// within its own command type.
+/// As control commands affect what a server replies with, they must only be
+/// acted upon once authenticated e.g. having been received within a data frame
+/// with a valid MAC.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ControlCommand {
+    /// Have the server treat the client's next request as though it were the
+    /// first one of the session, so that its events are replayed from the
+    /// oldest one retained. This is useful for forcing a client to resync
+    /// e.g. after a configuration change.
+    ResetSession,
+    /// Have the server reply with the number of events that the client has
+    /// yet to receive, rather than with the next event. A sleeping client can
+    /// then cheaply decide whether to wake and poll for them.
+    Heartbeat,
+}
+
+/// Tracks the client sessions that have been reset by a [ControlCommand] and
+/// have yet to make their next request. Sessions are identified by a key
+/// that is meaningful to the transport e.g. a client's socket address. At
+/// most `N` resets may be pending at any one time.
+pub struct SessionResets<K, const N: usize> {
+    pending: Vec<K, N>,
+}
+
+impl<K: PartialEq, const N: usize> SessionResets<K, N> {
+    /// Create a tracker with no resets pending.
+    pub const fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+
+    /// Apply a control command received by a session. Returns the
+    /// session's key as an error if there is no room to record the reset.
+    pub fn apply(&mut self, session: K, command: ControlCommand) -> Result<(), K> {
+        match command {
+            ControlCommand::ResetSession => self.reset(session),
+            ControlCommand::Heartbeat => Ok(()),
+        }
+    }
+
+    /// Have the next request of a session treated as its first. Returns the
+    /// session's key as an error if there is no room to record the reset.
+    pub fn reset(&mut self, session: K) -> Result<(), K> {
+        if self.pending.contains(&session) {
+            Ok(())
+        } else {
+            self.pending.push(session)
+        }
+    }
+
+    /// The last event offset that a request should be replied to in relation
+    /// to, or None if the session has been reset and so the request should be
+    /// treated as the first one of the session. Any pending reset is consumed.
+    pub fn last_event_offset<C>(&mut self, session: &K, request: &CommandRequest<C>) -> Option<u32>
+    where
+        C: DeserializeOwned + Serialize,
+    {
+        match self.pending.iter().position(|s| s == session) {
+            Some(i) => {
+                let _ = self.pending.swap_remove(i);
+                None
+            }
+            None => Some(request.last_event_offset),
+        }
+    }
+}
+
+impl<K: PartialEq, const N: usize> Default for SessionResets<K, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::poll;
+    use crate::{event_reply, EventLog};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Command {
+        Control(ControlCommand),
+    }
+
+    #[test]
+    fn test_reset_replies_with_oldest_event() {
+        let mut log = EventLog::<char, u64, 4>::new();
+        for (t, e) in ('a'..='f').enumerate() {
+            log.push(e, t as u64);
+        }
+
+        let mut resets = SessionResets::<&str, 2>::new();
+
+        let reply_to =
+            |resets: &mut SessionResets<&str, 2>, session, request: CommandRequest<Command>| {
+                let maybe_event = match resets.last_event_offset(&session, &request) {
+                    Some(offset) => log.events_after(offset, 1).next(),
+                    None => log.iter().next(),
+                };
+                let reply = event_reply(maybe_event, |t| 10 - t);
+                (reply.age.get(), reply.into_event())
+            };
+
+        assert_eq!(
+            reply_to(&mut resets, "client", poll(4)),
+            (5, Some(('f', 5)))
+        );
+
+        let request = CommandRequest {
+            command: Some(Command::Control(ControlCommand::ResetSession)),
+            ..poll(4)
+        };
+        if let Some(Command::Control(command)) = request.command {
+            assert_eq!(resets.apply("client", command), Ok(()));
+        }
+
+        // Another session is unaffected.
+        assert_eq!(reply_to(&mut resets, "other", poll(4)), (5, Some(('f', 5))));
+
+        // The reset session is replied to as though it had just started...
+        assert_eq!(
+            reply_to(&mut resets, "client", poll(4)),
+            (8, Some(('c', 2)))
+        );
+
+        // ...and only its next request.
+        assert_eq!(
+            reply_to(&mut resets, "client", poll(4)),
+            (5, Some(('f', 5)))
+        );
+    }
+
+    #[test]
+    fn test_resets_are_bounded() {
+        let mut resets = SessionResets::<u8, 1>::new();
+        assert_eq!(resets.reset(1), Ok(()));
+        assert_eq!(resets.reset(1), Ok(()));
+        assert_eq!(resets.reset(2), Err(2));
+    }
+}
@@ -0,0 +1,167 @@
+use flip_flop_data::{DataFrame, DataSource, Header, ServerAddress};
+use heapless::Vec;
+
+use crate::Rejection;
+
+/// Counters of the notable outcomes of a server's handling of the frames and
+/// requests it receives from its client, for operators to observe. Counters
+/// wrap to zero on overflow.
+///
+/// A [crate::ServerEndpoint] records each outcome as it processes a datagram.
+/// Hosts handling frames themselves record their outcomes with
+/// [Stats::record], as a [crate::Server] only sees the requests that reach it.
+/// Outcomes may also be counted for each of the peers that frames are received
+/// from, as per [PeerStats].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Frames received from the client, whatever their outcome.
+    pub received: u32,
+    /// Frames that failed to parse e.g. as they were truncated.
+    pub parse_failed: u32,
+    /// Frames dropped as their frame counter was not newer than the last one
+    /// accepted.
+    pub replayed: u32,
+    /// Frames dropped for another reason given the receiver's configuration
+    /// e.g. being for another server address.
+    pub rejected: u32,
+    /// Frames whose payload failed to authenticate.
+    pub unauthenticated: u32,
+    /// Payloads that are not a command request.
+    pub decode_failed: u32,
+    /// Requests conveying a last event offset beyond any that the server has
+    /// assigned, as from a client whose record of events predates a reset.
+    /// These are counted by a [crate::ServerRuntime] whose handler has its
+    /// events to compare with, as per [crate::CommandHandler::is_stale].
+    pub stale: u32,
+    /// Replies that would have exceeded the maximum datagram size and so
+    /// were reduced to fit.
+    pub reply_truncated: u32,
+}
+
+/// A notable outcome of handling a frame or request, as counted by [Stats].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// Counted by [Stats::received].
+    Received,
+    /// Counted by [Stats::parse_failed].
+    ParseFailed,
+    /// Counted by [Stats::replayed].
+    Replayed,
+    /// Counted by [Stats::rejected].
+    Rejected,
+    /// Counted by [Stats::unauthenticated].
+    Unauthenticated,
+    /// Counted by [Stats::decode_failed].
+    DecodeFailed,
+    /// Counted by [Stats::stale].
+    Stale,
+    /// Counted by [Stats::reply_truncated].
+    ReplyTruncated,
+}
+
+impl From<&Rejection> for Outcome {
+    fn from(rejection: &Rejection) -> Self {
+        match rejection {
+            Rejection::Parse(_) => Outcome::ParseFailed,
+            Rejection::Replayed => Outcome::Replayed,
+            _ => Outcome::Rejected,
+        }
+    }
+}
+
+impl Stats {
+    /// Count an outcome.
+    pub fn record(&mut self, outcome: impl Into<Outcome>) {
+        let counter = match outcome.into() {
+            Outcome::Received => &mut self.received,
+            Outcome::ParseFailed => &mut self.parse_failed,
+            Outcome::Replayed => &mut self.replayed,
+            Outcome::Rejected => &mut self.rejected,
+            Outcome::Unauthenticated => &mut self.unauthenticated,
+            Outcome::DecodeFailed => &mut self.decode_failed,
+            Outcome::Stale => &mut self.stale,
+            Outcome::ReplyTruncated => &mut self.reply_truncated,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+}
+
+/// The peer that a frame is received from, as identified by the source and
+/// server address of its header, for counting outcomes by peer as per
+/// [PeerStats].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Peer {
+    pub source: DataSource,
+    pub server_address: ServerAddress,
+}
+
+impl From<&Header> for Peer {
+    fn from(header: &Header) -> Self {
+        Self {
+            source: header.source,
+            server_address: header.server_address,
+        }
+    }
+}
+
+impl Peer {
+    /// The peer that a datagram is from, as per [DataFrame::inspect], so that
+    /// even a frame failing to parse is attributed to its peer. There is none
+    /// for a datagram too short to hold a header.
+    pub fn of_datagram(datagram: &[u8]) -> Option<Self> {
+        let info = DataFrame::inspect(datagram);
+        match (info.source, info.server_address) {
+            (Some(source), Some(server_address)) => Some(Self {
+                source,
+                server_address: ServerAddress::new_unchecked(server_address),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// [Stats] for each of up to `N` peers, in the order that they were first
+/// recorded. Outcomes for further peers, or for frames that cannot be
+/// attributed to a peer, are counted as others.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerStats<const N: usize> {
+    peers: Vec<(Peer, Stats), N>,
+    others: Stats,
+}
+
+impl<const N: usize> PeerStats<N> {
+    /// Count an outcome for a peer, if any.
+    pub fn record(&mut self, peer: Option<Peer>, outcome: impl Into<Outcome>) {
+        let index = peer.and_then(|peer| {
+            self.peers.iter().position(|(p, _)| *p == peer).or_else(|| {
+                self.peers.push((peer, Stats::default())).ok()?;
+                Some(self.peers.len() - 1)
+            })
+        });
+        let stats = match index {
+            Some(i) => &mut self.peers[i].1,
+            None => &mut self.others,
+        };
+        stats.record(outcome);
+    }
+
+    /// The counters of a peer, if any of its outcomes have been recorded apart
+    /// from others.
+    pub fn get(&self, peer: &Peer) -> Option<&Stats> {
+        self.peers
+            .iter()
+            .find(|(p, _)| p == peer)
+            .map(|(_, stats)| stats)
+    }
+
+    /// The peers whose outcomes have been recorded, along with their
+    /// counters.
+    pub fn iter(&self) -> impl Iterator<Item = &(Peer, Stats)> {
+        self.peers.iter()
+    }
+
+    /// The counters of outcomes not attributed to a peer.
+    pub fn others(&self) -> &Stats {
+        &self.others
+    }
+}
@@ -0,0 +1,602 @@
+use core::ops::{Deref, DerefMut};
+
+use flip_flop_data::{
+    encrypted_len, Header, ProtocolVersion, HEADER_SIZE, MAC_SIZE, MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    deserialise_last_field, serialise_last_field, CommandReply, CommandRequest, DiscriminantSet,
+    EventReply, Reply, SATURATED_DELTA_TICKS,
+};
+
+/// A message conveyed within the payload of a data frame, declaring the greatest
+/// number of bytes that it may be encoded as. Buffers can then be sized exactly
+/// with [max_frame_size].
+///
+/// For application-defined commands and events, this is the greatest encoded
+/// length of any of their variants e.g. 1 for an enum of fewer than 128 variants
+/// that carry no data.
+pub trait WireMessage {
+    /// The greatest number of bytes that the message may be encoded as.
+    const MAX_ENCODED_LEN: usize;
+
+    /// The number of bytes that this message is encoded as. By default, this is
+    /// the greatest number, and should be overridden by messages where their
+    /// lengths vary considerably.
+    fn encoded_len(&self) -> usize {
+        Self::MAX_ENCODED_LEN
+    }
+}
+
+macro_rules! fixed_len_messages {
+    ($($t:ty => $len:expr),*) => {
+        $(
+            impl WireMessage for $t {
+                const MAX_ENCODED_LEN: usize = $len;
+            }
+        )*
+    };
+}
+
+fixed_len_messages!(
+    () => 0, bool => 1,
+    u8 => 1, u16 => 2, u32 => 4, u64 => 8,
+    i8 => 1, i16 => 2, i32 => 4, i64 => 8
+);
+
+impl WireMessage for char {
+    // A length followed by up to 4 bytes of UTF-8.
+    const MAX_ENCODED_LEN: usize = 1 + 4;
+
+    fn encoded_len(&self) -> usize {
+        1 + self.len_utf8()
+    }
+}
+
+impl<C> WireMessage for CommandRequest<C>
+where
+    C: DeserializeOwned + Serialize + WireMessage,
+{
+    const MAX_ENCODED_LEN: usize = 4 + 4 + 4 + 2 + C::MAX_ENCODED_LEN;
+
+    fn encoded_len(&self) -> usize {
+        4 + 4 + 4 + 2 + self.command.as_ref().map_or(0, C::encoded_len)
+    }
+}
+
+// The bytes of a command reply preceding its event, being up to and including
+// its offset, where the server's time is not conveyed. Conveying it requires
+// SERVER_TIME_LEN more.
+pub(crate) const COMMAND_REPLY_FIXED_LEN: usize = 2 + 2 + 1 + 2 + 4;
+pub(crate) const SERVER_TIME_LEN: usize = 8;
+
+impl<E> WireMessage for CommandReply<E>
+where
+    E: DeserializeOwned + Serialize + WireMessage,
+{
+    const MAX_ENCODED_LEN: usize = COMMAND_REPLY_FIXED_LEN + SERVER_TIME_LEN + E::MAX_ENCODED_LEN;
+
+    fn encoded_len(&self) -> usize {
+        COMMAND_REPLY_FIXED_LEN
+            + self.server_time.map_or(0, |_| SERVER_TIME_LEN)
+            + self.event.as_ref().map_or(0, E::encoded_len)
+    }
+}
+
+impl<E> WireMessage for EventReply<E>
+where
+    E: DeserializeOwned + Serialize + WireMessage,
+{
+    const MAX_ENCODED_LEN: usize = 8 + E::MAX_ENCODED_LEN + 4;
+
+    fn encoded_len(&self) -> usize {
+        8 + self.event.as_ref().map_or(0, |(e, _)| e.encoded_len() + 4)
+    }
+}
+
+impl<E> WireMessage for Reply<E>
+where
+    E: DeserializeOwned + Serialize + WireMessage,
+{
+    const MAX_ENCODED_LEN: usize = 1 + CommandReply::<E>::MAX_ENCODED_LEN;
+
+    fn encoded_len(&self) -> usize {
+        1 + match self {
+            Reply::Event(reply) => reply.encoded_len(),
+            Reply::Nack => 0,
+            Reply::EventsPending(_) => 4,
+            Reply::Alive { .. } => 2,
+            Reply::UnsupportedVersion { .. } => 1,
+            Reply::Truncated { .. } => 2 + 4,
+        }
+    }
+}
+
+// A command request as laid out by version 0 of the protocol, conveying only
+// the last event offset and the command.
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "C: Deserialize<'de>"))]
+struct RequestV0<C> {
+    last_event_offset: u32,
+    #[serde(deserialize_with = "deserialise_last_field")]
+    command: Option<C>,
+}
+
+// As per RequestV0, borrowing the command to encode it.
+#[derive(Serialize)]
+#[serde(bound(serialize = "C: Serialize"))]
+struct RequestV0Ref<'a, C> {
+    last_event_offset: u32,
+    #[serde(serialize_with = "serialise_borrowed_last_field")]
+    command: &'a Option<C>,
+}
+
+// As per EventReply, borrowing the event of a command reply to encode it.
+#[derive(Serialize)]
+#[serde(bound(serialize = "E: Serialize"))]
+struct EventReplyRef<'a, E> {
+    delta_ticks: u64,
+    #[serde(serialize_with = "serialise_last_field")]
+    event: Option<(&'a E, u32)>,
+}
+
+fn serialise_borrowed_last_field<S, T>(o: &&Option<T>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: Serialize,
+{
+    serialise_last_field(o, s)
+}
+
+// The least number of bytes of a version 0 reply, being its delta ticks.
+pub(crate) const REPLY_V0_MIN_LEN: usize = 8;
+
+impl<C: DeserializeOwned + Serialize> CommandRequest<C> {
+    /// Encode this request into `buf` as laid out by a protocol version,
+    /// returning the bytes written. Version 0 conveys only the last event
+    /// offset and the command, as per [CommandRequest].
+    pub fn encode<'a>(
+        &self,
+        version: ProtocolVersion,
+        buf: &'a mut [u8],
+    ) -> Result<&'a mut [u8], postcard::Error> {
+        match version {
+            ProtocolVersion::V0 => postcard::to_slice(
+                &RequestV0Ref {
+                    last_event_offset: self.last_event_offset,
+                    command: &self.command,
+                },
+                buf,
+            ),
+            ProtocolVersion::V1 => postcard::to_slice(self, buf),
+        }
+    }
+
+    /// Decode a request as laid out by a protocol version. A version 0 request
+    /// subscribes to all events and acknowledges none, as its client predates
+    /// both.
+    pub fn decode(version: ProtocolVersion, bytes: &[u8]) -> Result<Self, postcard::Error> {
+        match version {
+            ProtocolVersion::V0 => {
+                let RequestV0 {
+                    last_event_offset,
+                    command,
+                } = postcard::from_bytes(bytes)?;
+                Ok(CommandRequest {
+                    last_event_offset,
+                    subscriptions: DiscriminantSet::ALL,
+                    acked_offset: 0,
+                    acked_epoch: 0,
+                    command,
+                })
+            }
+            ProtocolVersion::V1 => postcard::from_bytes(bytes),
+        }
+    }
+}
+
+impl<E: DeserializeOwned + Serialize> Reply<E> {
+    /// Encode this reply into `buf` as laid out by a protocol version,
+    /// returning the bytes written.
+    ///
+    /// Version 0 conveys only command replies, as an [EventReply] and so
+    /// without the server's time. Other replies, including [Reply::Truncated],
+    /// are conveyed to it as there being no more events, save for
+    /// [Reply::UnsupportedVersion], which is laid out as in version 1 so that a
+    /// client of a later version can tell it apart: being of 2 bytes, it is
+    /// shorter than any version 0 reply.
+    pub fn encode<'a>(
+        &self,
+        version: ProtocolVersion,
+        buf: &'a mut [u8],
+    ) -> Result<&'a mut [u8], postcard::Error> {
+        match (version, self) {
+            (ProtocolVersion::V0, Reply::Event(reply)) => postcard::to_slice(
+                &EventReplyRef {
+                    delta_ticks: if reply.age.is_saturated() {
+                        SATURATED_DELTA_TICKS
+                    } else {
+                        reply.age.get().into()
+                    },
+                    event: reply.event.as_ref().map(|e| (e, reply.offset)),
+                },
+                buf,
+            ),
+            (ProtocolVersion::V0, Reply::UnsupportedVersion { .. }) | (ProtocolVersion::V1, _) => {
+                postcard::to_slice(self, buf)
+            }
+            (ProtocolVersion::V0, _) => postcard::to_slice(
+                &EventReplyRef::<E> {
+                    delta_ticks: 0,
+                    event: None,
+                },
+                buf,
+            ),
+        }
+    }
+
+    /// Decode a reply as laid out by a protocol version, as per
+    /// [Reply::encode]. A version 0 event reply is of epoch 0, and echoes no
+    /// frame counter.
+    pub fn decode(version: ProtocolVersion, bytes: &[u8]) -> Result<Self, postcard::Error> {
+        match version {
+            ProtocolVersion::V0 if bytes.len() >= REPLY_V0_MIN_LEN => {
+                let reply = postcard::from_bytes::<EventReply<E>>(bytes)?;
+                Ok(Reply::Event(reply.into()))
+            }
+            ProtocolVersion::V0 => match postcard::from_bytes(bytes)? {
+                reply @ Reply::UnsupportedVersion { .. } => Ok(reply),
+                _ => Err(postcard::Error::DeserializeUnexpectedEnd),
+            },
+            ProtocolVersion::V1 => postcard::from_bytes(bytes),
+        }
+    }
+}
+
+/// The size of the buffer required to hold a data frame conveying a message,
+/// being its header, inclusive of the payload's length, and its encrypted
+/// payload. As this is a const fn, a buffer's size can be checked at compile
+/// time:
+///
+/// ```
+/// use flip_flop_app::{max_frame_size, CommandRequest, WireMessage};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, Serialize)]
+/// enum Command {
+///     SomeCommand,
+/// }
+///
+/// impl WireMessage for Command {
+///     const MAX_ENCODED_LEN: usize = 1;
+/// }
+///
+/// const SEND_BUF_SIZE: usize = 32;
+/// const _: () = assert!(max_frame_size::<CommandRequest<Command>>() <= SEND_BUF_SIZE);
+/// ```
+pub const fn max_frame_size<M: WireMessage>() -> usize {
+    HEADER_SIZE + encrypted_len(M::MAX_ENCODED_LEN)
+}
+
+/// The greatest length of an encrypted payload that a datagram of a given size
+/// can convey, being what remains once the header and the payload's length are
+/// conveyed, to at most the 127 bytes permitted.
+pub const fn max_payload_len(datagram_size: usize) -> usize {
+    let len = datagram_size.saturating_sub(HEADER_SIZE);
+    if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+        MAX_ENCRYPTED_PAYLOAD_LEN
+    } else {
+        len
+    }
+}
+
+/// A buffer for a datagram of `N` bytes, conveying one data frame. The size is
+/// then the only one to choose, with the budget for the frame's plaintext being
+/// derived from it, and messages being checked against it at compile time:
+///
+/// ```
+/// use flip_flop_app::{Datagram, Reply, WireMessage};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, Serialize)]
+/// enum Event {
+///     SomeEvent,
+/// }
+///
+/// impl WireMessage for Event {
+///     const MAX_ENCODED_LEN: usize = 1;
+/// }
+///
+/// type SendBuf = Datagram<64>;
+/// const _: () = assert!(SendBuf::fits::<Reply<Event>>());
+/// assert_eq!(SendBuf::MAX_PLAINTEXT_LEN, 64 - 5 - 4);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Datagram<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> Datagram<N> {
+    /// The greatest length of an encrypted payload that the datagram can
+    /// convey, as per [max_payload_len].
+    pub const MAX_PAYLOAD_LEN: usize = max_payload_len(N);
+
+    /// The greatest length of a plaintext that the datagram can convey once
+    /// sealed with the default MAC of `MAC_SIZE` bytes from `flip-flop-data`,
+    /// as per [Header::payload_capacity].
+    pub const MAX_PLAINTEXT_LEN: usize = Header::payload_capacity(N, MAC_SIZE);
+
+    /// Create a buffer of zeros.
+    pub const fn new() -> Self {
+        Self { bytes: [0; N] }
+    }
+
+    /// True if any message of a given type fits within the datagram once sealed
+    /// within a data frame, as per [max_frame_size].
+    pub const fn fits<M: WireMessage>() -> bool {
+        max_frame_size::<M>() <= N && M::MAX_ENCODED_LEN <= Self::MAX_PLAINTEXT_LEN
+    }
+}
+
+impl<const N: usize> Default for Datagram<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for Datagram<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<const N: usize> DerefMut for Datagram<N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Event {
+        Temperature(i16),
+        Reset,
+    }
+
+    impl WireMessage for Event {
+        const MAX_ENCODED_LEN: usize = 1 + 2;
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        assert_eq!(
+            max_frame_size::<CommandRequest<Event>>(),
+            HEADER_SIZE + 4 + 4 + 4 + 2 + 3 + 4
+        );
+        assert_eq!(
+            max_frame_size::<Reply<Event>>(),
+            HEADER_SIZE + 1 + COMMAND_REPLY_FIXED_LEN + SERVER_TIME_LEN + 3 + 4
+        );
+    }
+
+    #[test]
+    fn test_max_encoded_len_is_sufficient() {
+        let reply = Reply::Event(CommandReply {
+            server_time: Some(u64::MAX),
+            ..crate::event_reply(Some(&(Event::Temperature(-1), u32::MAX, u64::MAX)), |t| t)
+        });
+        let mut buf = [0; Reply::<Event>::MAX_ENCODED_LEN];
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), Reply::<Event>::MAX_ENCODED_LEN);
+        assert_eq!(encoded.len(), reply.encoded_len());
+    }
+
+    #[test]
+    fn test_reply_round_trips_within_datagram() {
+        const MAX_DATAGRAM_SIZE: usize = 64;
+        assert!(Datagram::<MAX_DATAGRAM_SIZE>::fits::<Reply<Event>>());
+
+        let reply = Reply::Event(crate::event_reply(
+            Some(&(Event::Temperature(-1), 7, 3)),
+            |t| 10 - t,
+        ));
+        let mut buf = [0; Datagram::<MAX_DATAGRAM_SIZE>::MAX_PLAINTEXT_LEN];
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<Reply<Event>>(encoded).unwrap(),
+            reply
+        );
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        let mut buf = [0; 32];
+
+        let reply = Reply::Event(crate::event_reply(Some(&('é', 1, 0)), |t| t));
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = Reply::<char>::Event(crate::event_reply(None, |t: u64| t));
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = Reply::<char>::EventsPending(3);
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = Reply::<char>::Alive { frame_counter: 300 };
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded, [3, 44, 1]);
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = Reply::<char>::UnsupportedVersion {
+            highest: flip_flop_data::ProtocolVersion::V0,
+        };
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded, [4, 0]);
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let reply = Reply::<char>::Truncated {
+            epoch: 2,
+            offset: 9,
+        };
+        let encoded = postcard::to_slice(&reply, &mut buf).unwrap();
+        assert_eq!(encoded, [5, 2, 0, 9, 0, 0, 0]);
+        assert_eq!(encoded.len(), reply.encoded_len());
+
+        let request = CommandRequest::<u16> {
+            last_event_offset: 1,
+            subscriptions: crate::DiscriminantSet::ALL,
+            acked_offset: 0,
+            acked_epoch: 0,
+            command: Some(2),
+        };
+        let encoded = postcard::to_slice(&request, &mut buf).unwrap();
+        assert_eq!(encoded.len(), request.encoded_len());
+    }
+
+    #[test]
+    fn test_baseline_request_is_decoded_as_version_0() {
+        // As encoded before the introduction of version 1.
+        let request = CommandRequest::<u8>::decode(ProtocolVersion::V0, &[9, 0, 0, 0, 2]).unwrap();
+        assert_eq!(
+            request,
+            CommandRequest {
+                last_event_offset: 9,
+                subscriptions: DiscriminantSet::ALL,
+                acked_offset: 0,
+                acked_epoch: 0,
+                command: Some(2),
+            }
+        );
+        let request = CommandRequest::<u8>::decode(ProtocolVersion::V0, &[9, 0, 0, 0]).unwrap();
+        assert_eq!(request.command, None);
+
+        // The extended fields are not conveyed in version 0, and are conveyed
+        // in version 1.
+        let request = CommandRequest {
+            last_event_offset: 9,
+            subscriptions: DiscriminantSet::ALL,
+            acked_offset: 3,
+            acked_epoch: 1,
+            command: Some(2u8),
+        };
+        let mut buf = [0; 32];
+        assert_eq!(
+            request.encode(ProtocolVersion::V0, &mut buf).unwrap(),
+            [9, 0, 0, 0, 2]
+        );
+        let encoded = request.encode(ProtocolVersion::V1, &mut buf).unwrap();
+        assert_eq!(encoded.len(), request.encoded_len());
+        assert_eq!(
+            CommandRequest::<u8>::decode(ProtocolVersion::V1, encoded).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn test_replies_are_laid_out_by_version() {
+        let mut buf = [0; 32];
+
+        // Version 0 conveys event replies without a variant or epoch.
+        let reply = Reply::Event(crate::event_reply(Some(&(1u8, 9, 0)), |_| 10));
+        assert_eq!(
+            reply.encode(ProtocolVersion::V0, &mut buf).unwrap(),
+            [10, 0, 0, 0, 0, 0, 0, 0, 1, 9, 0, 0, 0]
+        );
+        let decoded = Reply::<u8>::decode(
+            ProtocolVersion::V0,
+            &[10, 0, 0, 0, 0, 0, 0, 0, 1, 9, 0, 0, 0],
+        )
+        .unwrap();
+        assert!(matches!(
+            decoded,
+            Reply::Event(CommandReply {
+                age,
+                frame_counter: 0,
+                epoch: 0,
+                offset: 9,
+                event: Some(1),
+                ..
+            }) if age.get() == 10
+        ));
+
+        // Other replies are conveyed in version 0 as there being no event,
+        // except for an unsupported version.
+        assert_eq!(
+            Reply::<u8>::Nack
+                .encode(ProtocolVersion::V0, &mut buf)
+                .unwrap(),
+            [0; 8]
+        );
+        let reply = Reply::<u8>::UnsupportedVersion {
+            highest: ProtocolVersion::V0,
+        };
+        let encoded = reply.encode(ProtocolVersion::V0, &mut buf).unwrap();
+        assert_eq!(encoded, [4, 0]);
+        assert_eq!(
+            Reply::<u8>::decode(ProtocolVersion::V0, &[4, 0]).unwrap(),
+            reply
+        );
+        assert!(Reply::<u8>::decode(ProtocolVersion::V0, &[3, 44, 1]).is_err());
+
+        // Version 1 conveys every reply.
+        let reply = Reply::<u8>::Alive { frame_counter: 300 };
+        let encoded = reply.encode(ProtocolVersion::V1, &mut buf).unwrap();
+        assert_eq!(encoded, [3, 44, 1]);
+        assert_eq!(
+            Reply::<u8>::decode(ProtocolVersion::V1, encoded).unwrap(),
+            reply
+        );
+    }
+
+    #[test]
+    fn test_datagram_budget() {
+        // A header of 4 bytes and a length, followed by the encrypted payload.
+        assert_eq!(max_payload_len(0), 0);
+        assert_eq!(max_payload_len(HEADER_SIZE), 0);
+        assert_eq!(max_payload_len(32), 27);
+        assert_eq!(max_payload_len(HEADER_SIZE + 127), 127);
+        assert_eq!(max_payload_len(1500), 127);
+
+        // MACs of 4, 8 and 16 bytes, and none for payloads that are not sealed.
+        assert_eq!(Header::payload_capacity(32, 4), 23);
+        assert_eq!(Header::payload_capacity(32, 8), 19);
+        assert_eq!(Header::payload_capacity(32, 16), 11);
+        assert_eq!(Header::payload_capacity(32, 0), 27);
+        assert_eq!(Header::payload_capacity(1500, 4), 123);
+        assert_eq!(Header::payload_capacity(1500, 16), 111);
+        assert_eq!(Header::payload_capacity(8, 4), 0);
+
+        assert_eq!(Datagram::<32>::MAX_PAYLOAD_LEN, 27);
+        assert_eq!(Datagram::<32>::MAX_PLAINTEXT_LEN, 23);
+        assert_eq!(Datagram::<64>::MAX_PLAINTEXT_LEN, 55);
+        assert_eq!(Datagram::<255>::MAX_PLAINTEXT_LEN, 123);
+        assert_eq!(Datagram::<32>::new().len(), 32);
+
+        // A reply of an event fits exactly...
+        const REPLY_LEN: usize = 1 + COMMAND_REPLY_FIXED_LEN + SERVER_TIME_LEN + 3;
+        assert_eq!(Reply::<Event>::MAX_ENCODED_LEN, REPLY_LEN);
+        assert!(Datagram::<{ HEADER_SIZE + REPLY_LEN + MAC_SIZE }>::fits::<
+            Reply<Event>,
+        >());
+        assert!(!Datagram::<{ HEADER_SIZE + REPLY_LEN + MAC_SIZE - 1 }>::fits::<Reply<Event>>());
+
+        // ...yet no message can exceed the 123 bytes of plaintext permitted.
+        struct Large<const L: usize>;
+
+        impl<const L: usize> WireMessage for Large<L> {
+            const MAX_ENCODED_LEN: usize = L;
+        }
+
+        assert!(Datagram::<1500>::fits::<Large<123>>());
+        assert!(!Datagram::<1500>::fits::<Large<124>>());
+    }
+}
@@ -0,0 +1,72 @@
+//! Compares sealing frames with a cipher set up for each frame against a
+//! [Sealer] that is set up once and reused.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use flip_flop_data::{
+    crypto::{Opener, Sealer},
+    DataSource, Header, ServerAddress, ServerPort,
+};
+
+const KEY: &[u8; 16] = b"0123456789ABCDEF";
+
+const HEADER: Header = Header {
+    version: 0,
+    source: DataSource::Server,
+    server_address: ServerAddress::new_unchecked(31),
+    server_port: ServerPort::new_unchecked(2),
+    frame_counter: 1,
+};
+
+const NONCE: [u8; 8] = [0; 8];
+
+const PLAINTEXT: &[u8] = b"some data";
+
+fn seal(c: &mut Criterion) {
+    let mut out = [0; 32];
+
+    c.bench_function("seal with setup per call", |b| {
+        b.iter(|| {
+            Sealer::new(black_box(KEY))
+                .seal_next(&HEADER, &NONCE, black_box(PLAINTEXT), &mut out)
+                .unwrap()
+        })
+    });
+
+    let sealer = Sealer::new(KEY);
+    c.bench_function("seal with reused setup", |b| {
+        b.iter(|| {
+            sealer
+                .seal_next(&HEADER, &NONCE, black_box(PLAINTEXT), &mut out)
+                .unwrap()
+        })
+    });
+}
+
+fn open(c: &mut Criterion) {
+    let mut encrypted_payload = [0; 32];
+    let len = Sealer::new(KEY)
+        .seal_next(&HEADER, &NONCE, PLAINTEXT, &mut encrypted_payload)
+        .unwrap();
+    let encrypted_payload = &encrypted_payload[..len];
+    let mut out = [0; 32];
+
+    c.bench_function("open with setup per call", |b| {
+        b.iter(|| {
+            Opener::new(black_box(KEY))
+                .open(&HEADER, &NONCE, black_box(encrypted_payload), &mut out)
+                .unwrap()
+        })
+    });
+
+    let opener = Opener::new(KEY);
+    c.bench_function("open with reused setup", |b| {
+        b.iter(|| {
+            opener
+                .open(&HEADER, &NONCE, black_box(encrypted_payload), &mut out)
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, seal, open);
+criterion_main!(benches);
@@ -0,0 +1,259 @@
+//! Data frames conveying their payload as plaintext with a CRC-16 appended, for
+//! links that are physically secure yet subject to corruption e.g. a wired bus.
+//! The CRC detects accidental corruption cheaply, without the cost of AES-CCM,
+//! but provides neither confidentiality nor authenticity: anyone able to write
+//! to the link can forge a frame. This module is therefore only present with
+//! the `checksum` feature.
+//!
+//! The CRC takes the place of the MAC at the end of the payload, and is the
+//! CRC-16/CCITT-FALSE of the frame as written by [DataFrame::to_bytes] up to
+//! it i.e. the header, the payload's length and the plaintext. It is appended
+//! in big endian order.
+//!
+//! A frame with a CRC sets bit 14 of the header, being the second of its
+//! reserved bits, so that it is distinguishable from a frame sealed with a MAC.
+//! Receivers that do not expect a CRC therefore refuse it with
+//! [ParseError::ReservedBitsSet], as do [DataFrame::parse] and
+//! `DataFrame::open`.
+
+use core::fmt;
+
+use crate::{DataFrame, Header, ParseError, HEADER_SIZE, MAX_ENCRYPTED_PAYLOAD_LEN};
+
+/// Bit 14 of the header, being set for frames having a CRC appended.
+const CRC16_FLAG: u32 = 0x01 << 14;
+
+/// The number of bytes that a CRC appended to a payload occupies.
+pub const CRC16_SIZE: usize = 2;
+
+/// How the integrity of a data frame's payload is checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Checksum {
+    /// The payload is encrypted and has a MAC appended, as per AES-CCM.
+    CcmMac,
+    /// The payload is plaintext and has a CRC-16 appended.
+    Crc16,
+}
+
+/// There was an error appending or verifying a CRC.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumError {
+    /// The plaintext and its CRC would exceed the 127 bytes permitted.
+    PayloadTooLong,
+    /// The buffer provided is too small for the plaintext and its CRC.
+    BufferTooSmall,
+    /// The CRC received differs from that of the frame, which has therefore
+    /// been corrupted.
+    Mismatch,
+    /// The data frame failed to parse.
+    Parse(ParseError),
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::PayloadTooLong => write!(f, "payload too long for a CRC"),
+            ChecksumError::BufferTooSmall => write!(f, "buffer too small for the payload"),
+            ChecksumError::Mismatch => write!(f, "CRC mismatch"),
+            ChecksumError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for ChecksumError {}
+
+/// The CRC-16/CCITT-FALSE of some bytes, having a polynomial of 0x1021 and an
+/// initial value of 0xFFFF. It is computed bitwise to avoid a table, as suits
+/// small targets.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    crc16_update(0xFFFF, bytes)
+}
+
+fn crc16_update(crc: u16, bytes: &[u8]) -> u16 {
+    bytes.iter().fold(crc, |crc, b| {
+        (0..8).fold(crc ^ ((*b as u16) << 8), |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            }
+        })
+    })
+}
+
+impl<'a> DataFrame<'a> {
+    /// Create a data frame conveying a plaintext payload with its CRC appended,
+    /// writing the payload to `out`.
+    pub fn new_crc16(
+        header: &'a Header,
+        plaintext: &[u8],
+        out: &'a mut [u8],
+    ) -> Result<Self, ChecksumError> {
+        let len = plaintext.len() + CRC16_SIZE;
+        if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(ChecksumError::PayloadTooLong);
+        }
+        let out = out.get_mut(..len).ok_or(ChecksumError::BufferTooSmall)?;
+        let (payload, crc) = out.split_at_mut(plaintext.len());
+        payload.copy_from_slice(plaintext);
+        let frame_header = Self::new(header, &[]).header | CRC16_FLAG;
+        crc.copy_from_slice(&frame_crc16(frame_header, len, payload).to_be_bytes());
+        Ok(Self {
+            header: frame_header,
+            encrypted_payload: out,
+        })
+    }
+
+    /// How the integrity of this frame's payload is checked.
+    pub fn checksum(&self) -> Checksum {
+        if self.header & CRC16_FLAG == 0 {
+            Checksum::CcmMac
+        } else {
+            Checksum::Crc16
+        }
+    }
+
+    /// Parse a frame created by [DataFrame::new_crc16] and verify its CRC,
+    /// returning its header and plaintext payload. Errors are returned as per
+    /// [DataFrame::parse], including [ParseError::ReservedBitsSet] for a frame
+    /// that has a MAC.
+    pub fn parse_crc16(&self) -> Result<(Header, &'a [u8]), ChecksumError> {
+        if self.checksum() != Checksum::Crc16 {
+            return Err(ChecksumError::Parse(ParseError::ReservedBitsSet));
+        }
+        let (header, payload) = Self {
+            header: self.header & !CRC16_FLAG,
+            encrypted_payload: self.encrypted_payload,
+        }
+        .parse()
+        .map_err(ChecksumError::Parse)?;
+        let len = payload
+            .len()
+            .checked_sub(CRC16_SIZE)
+            .ok_or(ChecksumError::Mismatch)?;
+        let (plaintext, crc) = payload.split_at(len);
+        if frame_crc16(self.header, payload.len(), plaintext).to_be_bytes() != crc {
+            return Err(ChecksumError::Mismatch);
+        }
+        Ok((header, plaintext))
+    }
+}
+
+// The CRC of a frame up to its CRC, as written by to_bytes.
+fn frame_crc16(header: u32, payload_len: usize, plaintext: &[u8]) -> u16 {
+    let mut prefix = [0; HEADER_SIZE];
+    prefix[..4].copy_from_slice(&header.to_be_bytes());
+    prefix[4] = payload_len as u8;
+    crc16_update(crc16(&prefix), plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataSource, ServerAddress, ServerPort};
+
+    fn header() -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(3),
+            server_port: ServerPort::new_unchecked(4),
+            frame_counter: 5,
+        }
+    }
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn test_crc16_round_trip() {
+        let header = header();
+        let mut payload_buf = [0; 16];
+        let frame = DataFrame::new_crc16(&header, b"some data", &mut payload_buf).unwrap();
+        assert_eq!(frame.checksum(), Checksum::Crc16);
+        assert_eq!(frame.encrypted_payload.len(), 9 + CRC16_SIZE);
+
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+        assert_eq!(&buf[HEADER_SIZE..len - CRC16_SIZE], b"some data");
+        assert_eq!(
+            crc16(&buf[..len - CRC16_SIZE]).to_be_bytes(),
+            buf[len - 2..len]
+        );
+
+        let frame = DataFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(frame.parse_crc16(), Ok((header, b"some data".as_slice())));
+    }
+
+    #[test]
+    fn test_crc16_detects_corruption() {
+        let header = header();
+        let mut payload_buf = [0; 16];
+        let frame = DataFrame::new_crc16(&header, b"some data", &mut payload_buf).unwrap();
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+
+        // Every single bit flipped throughout the frame is detected, be it in
+        // the header, the payload or the CRC itself.
+        for i in 0..len {
+            for bit in 0..8 {
+                let mut corrupted = buf;
+                corrupted[i] ^= 1 << bit;
+                let result = DataFrame::from_bytes(&corrupted[..len]).map(|f| f.parse_crc16());
+                assert!(
+                    !matches!(result, Ok(Ok(_))),
+                    "byte {} bit {} undetected",
+                    i,
+                    bit
+                );
+            }
+        }
+
+        let mut corrupted = buf;
+        corrupted[HEADER_SIZE] ^= 0x01;
+        let frame = DataFrame::from_bytes(&corrupted[..len]).unwrap();
+        assert_eq!(frame.parse_crc16(), Err(ChecksumError::Mismatch));
+    }
+
+    #[test]
+    fn test_crc16_is_distinguishable() {
+        let header = header();
+        let mut payload_buf = [0; 16];
+        let crc = DataFrame::new_crc16(&header, b"some data", &mut payload_buf).unwrap();
+        let mac = DataFrame::new(&header, b"some data");
+        assert_eq!(mac.checksum(), Checksum::CcmMac);
+
+        // Each is refused when parsed as the other.
+        assert_eq!(crc.parse(), Err(ParseError::ReservedBitsSet));
+        assert_eq!(
+            mac.parse_crc16(),
+            Err(ChecksumError::Parse(ParseError::ReservedBitsSet))
+        );
+
+        // A payload too short to have a CRC.
+        let frame = DataFrame {
+            header: mac.header | CRC16_FLAG,
+            encrypted_payload: &[0],
+        };
+        assert_eq!(frame.parse_crc16(), Err(ChecksumError::Mismatch));
+    }
+
+    #[test]
+    fn test_new_crc16_checks_lengths() {
+        let header = header();
+        let plaintext = [0; MAX_ENCRYPTED_PAYLOAD_LEN - CRC16_SIZE + 1];
+        assert_eq!(
+            DataFrame::new_crc16(&header, &plaintext, &mut [0; 128]),
+            Err(ChecksumError::PayloadTooLong)
+        );
+        assert_eq!(
+            DataFrame::new_crc16(&header, b"some data", &mut [0; 10]),
+            Err(ChecksumError::BufferTooSmall)
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Compression of plaintext payloads before they are sealed, and their
+//! decompression once opened, for links where every byte of a datagram counts
+//! e.g. LoRa. Structured payloads such as events often have runs of repeated
+//! bytes, being zeroed fields and padding, and so compress well with run-length
+//! encoding. This module is present with the `compression` feature.
+//!
+//! Compression is selected per frame. A compressed frame sets bit 15 of the
+//! header, being the last of its reserved bits, so that receivers that do not
+//! expect compression refuse it with
+//! [crate::ParseError::ReservedBitsSet], as do
+//! [DataFrame::parse] and [DataFrame::open]. As the header's reserved bits are
+//! not part of its associated data, the associated data of a compressed frame
+//! is qualified by a byte following the header identifying the compression.
+//! Flipping the bit in transit therefore causes the frame to fail to
+//! authenticate, rather than its plaintext to be misinterpreted.
+//!
+//! # Run-length encoding
+//!
+//! A compressed payload is a sequence of runs, each introduced by a control
+//! byte. A control byte `n` of 0..=127 is followed by `n + 1` bytes conveyed
+//! literally, and one of 128..=255 by a single byte to be repeated `n - 125`
+//! times i.e. 3..=130 times. Payloads without repetition grow by a byte for
+//! every 128, so compression should only be selected for those that benefit.
+//!
+//! A payload decompressing to more than the 127 bytes that a frame may convey
+//! is refused, so that a small frame cannot expand without bound.
+
+use crate::{
+    crypto::{CryptoError, Opener, Sealer},
+    DataFrame, Header, HEADER_POSTCARD_MAX, MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+
+/// Bit 15 of the header, being set for frames having a compressed payload.
+const COMPRESSED_FLAG: u32 = 0x01 << 15;
+
+/// The greatest number of bytes conveyed by a literal run.
+const MAX_LITERAL_RUN: usize = 128;
+
+/// The least and greatest number of times that a repeat run repeats its byte.
+const MIN_REPEAT_RUN: usize = 3;
+const MAX_REPEAT_RUN: usize = 130;
+
+/// How a plaintext payload is compressed before being sealed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Compression {
+    /// The plaintext is sealed as is.
+    None,
+    /// The plaintext is run-length encoded.
+    Rle,
+}
+
+/// The reasons that a payload may fail to be compressed or decompressed.
+#[derive(Debug, PartialEq)]
+pub enum CompressionError {
+    /// The buffer to write to is too small.
+    BufferTooSmall,
+    /// The payload, be it compressed or decompressed, would exceed the 127
+    /// bytes permitted.
+    PayloadTooLong,
+    /// The compressed payload ends within a run.
+    Malformed,
+    /// The payload failed to seal or open.
+    Crypto(CryptoError),
+}
+
+impl Compression {
+    /// Compress a plaintext, writing the result to `out` and returning its
+    /// length.
+    pub fn compress(&self, plaintext: &[u8], out: &mut [u8]) -> Result<usize, CompressionError> {
+        if plaintext.len() > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(CompressionError::PayloadTooLong);
+        }
+        let mut writer = Writer::new(out);
+        match self {
+            Compression::None => writer.write(plaintext)?,
+            Compression::Rle => {
+                let mut literal_start = 0;
+                let mut i = 0;
+                while i < plaintext.len() {
+                    let byte = plaintext[i];
+                    let run = plaintext[i..]
+                        .iter()
+                        .take(MAX_REPEAT_RUN)
+                        .take_while(|b| **b == byte)
+                        .count();
+                    if run >= MIN_REPEAT_RUN {
+                        writer.write_literals(&plaintext[literal_start..i])?;
+                        writer.write(&[(run - MIN_REPEAT_RUN + 128) as u8, byte])?;
+                        i += run;
+                        literal_start = i;
+                    } else {
+                        i += 1;
+                    }
+                }
+                writer.write_literals(&plaintext[literal_start..])?;
+            }
+        }
+        Ok(writer.len)
+    }
+
+    /// Decompress a payload compressed by [Compression::compress], writing the
+    /// plaintext to `out` and returning its length.
+    pub fn decompress(&self, payload: &[u8], out: &mut [u8]) -> Result<usize, CompressionError> {
+        let mut writer = Writer::new(out);
+        match self {
+            Compression::None => writer.write(payload)?,
+            Compression::Rle => {
+                let mut runs = payload;
+                while let Some((control, rest)) = runs.split_first() {
+                    let control = *control as usize;
+                    runs = if control < 128 {
+                        let (literals, rest) = split_at_checked(rest, control + 1)?;
+                        writer.write(literals)?;
+                        rest
+                    } else {
+                        let (byte, rest) = rest.split_first().ok_or(CompressionError::Malformed)?;
+                        writer.repeat(*byte, control - 128 + MIN_REPEAT_RUN)?;
+                        rest
+                    };
+                }
+            }
+        }
+        Ok(writer.len)
+    }
+
+    // The associated data of a frame sealed with this compression, being the
+    // header's qualified by a byte identifying the compression, if any.
+    fn associated_data(&self, header: &Header) -> ([u8; HEADER_POSTCARD_MAX + 1], usize) {
+        let mut associated_data = [0; HEADER_POSTCARD_MAX + 1];
+        associated_data[..HEADER_POSTCARD_MAX].copy_from_slice(&header.associated_data());
+        match self {
+            Compression::None => (associated_data, HEADER_POSTCARD_MAX),
+            Compression::Rle => {
+                associated_data[HEADER_POSTCARD_MAX] = 1;
+                (associated_data, HEADER_POSTCARD_MAX + 1)
+            }
+        }
+    }
+}
+
+fn split_at_checked(bytes: &[u8], mid: usize) -> Result<(&[u8], &[u8]), CompressionError> {
+    if mid <= bytes.len() {
+        Ok(bytes.split_at(mid))
+    } else {
+        Err(CompressionError::Malformed)
+    }
+}
+
+// Writes to a buffer, refusing to exceed either it or the greatest length of
+// a payload.
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(out: &'a mut [u8]) -> Self {
+        Self { out, len: 0 }
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<&mut [u8], CompressionError> {
+        let end = self.len + len;
+        if end > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(CompressionError::PayloadTooLong);
+        }
+        let reserved = self
+            .out
+            .get_mut(self.len..end)
+            .ok_or(CompressionError::BufferTooSmall)?;
+        self.len = end;
+        Ok(reserved)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CompressionError> {
+        self.reserve(bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn repeat(&mut self, byte: u8, times: usize) -> Result<(), CompressionError> {
+        self.reserve(times)?.fill(byte);
+        Ok(())
+    }
+
+    fn write_literals(&mut self, literals: &[u8]) -> Result<(), CompressionError> {
+        for run in literals.chunks(MAX_LITERAL_RUN) {
+            self.write(&[(run.len() - 1) as u8])?;
+            self.write(run)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> DataFrame<'a> {
+    /// Create a data frame with a plaintext compressed and then sealed as per
+    /// [DataFrame::seal]. A frame sealed without compression is identical to
+    /// one created by [DataFrame::seal].
+    pub fn seal_compressed(
+        header: &'a Header,
+        key: &[u8; 16],
+        salt: &[u8; 6],
+        plaintext: &[u8],
+        compression: Compression,
+        out: &'a mut [u8],
+    ) -> Result<Self, CompressionError> {
+        let mut compressed = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = compression.compress(plaintext, &mut compressed)?;
+        let (associated_data, associated_data_len) = compression.associated_data(header);
+        let len = Sealer::new(key)
+            .seal_with(
+                &associated_data[..associated_data_len],
+                &header.nonce(salt),
+                &compressed[..len],
+                out,
+            )
+            .map_err(CompressionError::Crypto)?;
+        let frame = Self::new(header, &out[..len]);
+        Ok(match compression {
+            Compression::None => frame,
+            Compression::Rle => Self {
+                header: frame.header | COMPRESSED_FLAG,
+                ..frame
+            },
+        })
+    }
+
+    /// How this frame's payload was compressed before being sealed.
+    pub fn compression(&self) -> Compression {
+        if self.header & COMPRESSED_FLAG == 0 {
+            Compression::None
+        } else {
+            Compression::Rle
+        }
+    }
+
+    /// Parse and open this frame as per [DataFrame::open], decompressing its
+    /// plaintext should it have been compressed. An error is returned if the
+    /// payload fails to authenticate or decompress, in which case `out` is
+    /// cleared.
+    pub fn open_compressed<'b>(
+        &self,
+        key: &[u8; 16],
+        salt: &[u8; 6],
+        out: &'b mut [u8],
+    ) -> Result<(Header, &'b [u8]), CompressionError> {
+        let compression = self.compression();
+        let (header, encrypted_payload) = Self {
+            header: self.header & !COMPRESSED_FLAG,
+            encrypted_payload: self.encrypted_payload,
+        }
+        .parse()
+        .map_err(|e| CompressionError::Crypto(CryptoError::Parse(e)))?;
+        let (associated_data, associated_data_len) = compression.associated_data(&header);
+        let mut compressed = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+        let len = Opener::new(key)
+            .open_with(
+                &associated_data[..associated_data_len],
+                &header.nonce(salt),
+                encrypted_payload,
+                &mut compressed,
+            )
+            .map_err(|e| {
+                out.fill(0);
+                CompressionError::Crypto(e)
+            })?;
+        let len = compression
+            .decompress(&compressed[..len], out)
+            .inspect_err(|_| out.fill(0))?;
+        Ok((header, &out[..len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataSource, ParseError, ServerAddress, ServerPort, MAC_SIZE};
+
+    const KEY: &[u8; 16] = b"0123456789ABCDEF";
+    const SALT: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    fn header() -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(3),
+            server_port: ServerPort::new_unchecked(4),
+            frame_counter: 5,
+        }
+    }
+
+    #[test]
+    fn test_rle_runs() {
+        let mut out = [0; 16];
+        let len = Compression::Rle.compress(b"ab\0\0\0\0c", &mut out).unwrap();
+        assert_eq!(&out[..len], &[1, b'a', b'b', 129, 0, 0, b'c']);
+
+        let mut plaintext = [0; 16];
+        let len = Compression::Rle
+            .decompress(&out[..len], &mut plaintext)
+            .unwrap();
+        assert_eq!(&plaintext[..len], b"ab\0\0\0\0c");
+
+        // Plaintexts are limited in length, and payloads must not end within a
+        // run.
+        let len = Compression::Rle.compress(&[7; 127], &mut out).unwrap();
+        assert_eq!(&out[..len], &[252, 7]);
+        let len = Compression::Rle.compress(&[7; 131], &mut out);
+        assert_eq!(len, Err(CompressionError::PayloadTooLong));
+        assert_eq!(
+            Compression::Rle.decompress(&[2, b'a'], &mut plaintext),
+            Err(CompressionError::Malformed)
+        );
+        assert_eq!(
+            Compression::Rle.decompress(&[128], &mut plaintext),
+            Err(CompressionError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let header = header();
+        let mut plaintext = [0; 100];
+        plaintext[..5].copy_from_slice(b"event");
+
+        let mut encrypted_payload = [0; 128];
+        let frame = DataFrame::seal_compressed(
+            &header,
+            KEY,
+            &SALT,
+            &plaintext,
+            Compression::Rle,
+            &mut encrypted_payload,
+        )
+        .unwrap();
+        assert_eq!(frame.compression(), Compression::Rle);
+        assert_eq!(frame.encrypted_payload.len(), 6 + 2 + MAC_SIZE);
+        assert_eq!(frame.parse(), Err(ParseError::ReservedBitsSet));
+
+        let mut out = [0; 128];
+        let (opened_header, opened) = frame.open_compressed(KEY, &SALT, &mut out).unwrap();
+        assert_eq!(opened_header, header);
+        assert_eq!(opened, plaintext);
+
+        // Clearing the flag causes the frame to fail to authenticate.
+        let frame = DataFrame {
+            header: frame.header & !COMPRESSED_FLAG,
+            ..frame
+        };
+        assert_eq!(
+            frame.open_compressed(KEY, &SALT, &mut out),
+            Err(CompressionError::Crypto(CryptoError::Unauthenticated))
+        );
+        assert_eq!(out, [0; 128]);
+    }
+
+    #[test]
+    fn test_uncompressed_is_sealed() {
+        let header = header();
+        let mut compressed_payload = [0; 64];
+        let uncompressed = DataFrame::seal_compressed(
+            &header,
+            KEY,
+            &SALT,
+            b"some data",
+            Compression::None,
+            &mut compressed_payload,
+        )
+        .unwrap();
+        let mut encrypted_payload = [0; 64];
+        let sealed =
+            DataFrame::seal(&header, KEY, &SALT, b"some data", &mut encrypted_payload).unwrap();
+        assert_eq!(uncompressed, sealed);
+
+        let mut out = [0; 64];
+        let (_, opened) = sealed.open_compressed(KEY, &SALT, &mut out).unwrap();
+        assert_eq!(opened, b"some data");
+    }
+
+    #[test]
+    fn test_over_long_decompression_is_rejected() {
+        // A payload that would decompress to 260 bytes, sealed as compressed.
+        let header = header();
+        let (associated_data, len) = Compression::Rle.associated_data(&header);
+        let mut encrypted_payload = [0; 16];
+        let len = Sealer::new(KEY)
+            .seal_with(
+                &associated_data[..len],
+                &header.nonce(&SALT),
+                &[255, 0, 255, 0],
+                &mut encrypted_payload,
+            )
+            .unwrap();
+        let frame = DataFrame::new(&header, &encrypted_payload[..len]);
+        let frame = DataFrame {
+            header: frame.header | COMPRESSED_FLAG,
+            ..frame
+        };
+
+        let mut out = [0; 512];
+        assert_eq!(
+            frame.open_compressed(KEY, &SALT, &mut out),
+            Err(CompressionError::PayloadTooLong)
+        );
+        assert_eq!(out, [0; 512]);
+    }
+}
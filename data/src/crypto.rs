@@ -0,0 +1,837 @@
+//! Cryptographic operations on data frames, available with the `crypto`
+//! feature.
+//!
+//! # Sealing and opening
+//!
+//! Payloads are sealed using AES-128 CCM with a 4 byte MAC, an 8 byte nonce,
+//! and the header, as serialised by postcard, as associated data. Setting up
+//! the cipher involves expanding its key, so a [Sealer] or [Opener] should be
+//! created once per key and then used for each frame. The nonce is expected to
+//! vary with the header's frame counter, as [crate::Header::nonce] provides.
+//! [DataFrame::seal] and [DataFrame::open] bring these together for the
+//! occasional frame.
+//!
+//! # MAC size
+//!
+//! A 4 byte MAC keeps frames small, but may be forged by chance once in
+//! every 2^32 attempts. Deployments wanting stronger authentication may seal
+//! with a longer MAC of any even size from 4 to 16 bytes by creating a
+//! [Sealer] and [Opener] with [Sealer::with_mac_size] and
+//! [Opener::with_mac_size] e.g. `Sealer::<U8>::with_mac_size(key)`. Both ends
+//! must agree on the size. The MAC is appended to the ciphertext, so an encrypted
+//! payload is [Sealer::MAC_SIZE] bytes longer than its plaintext, which must be
+//! budgeted for when sizing datagrams. The encrypted payload remains limited to
+//! 127 bytes.
+//!
+//! # Key rotation
+//!
+//! The nonce varies only with the 16 bit frame counter, so a key may seal at
+//! most 65536 frames before a nonce would be reused with it, which breaks the
+//! confidentiality of CCM. Long-lived sessions must therefore rotate keys
+//! before the frame counter cycles, as a [KeySchedule] tracks, refusing to
+//! seal further with a key once its rotation is due. Once rotated,
+//! frames are sealed with the new key, while those still in flight under the
+//! previous key continue to open until that key is retired. The new key is to
+//! be exchanged out of band, as with the first.
+//!
+//! # Header protection
+//!
+//! A data frame's header is sent in the clear, which reveals the server
+//! address and port being communicated with to any eavesdropper. Deployments
+//! where this is a concern may additionally protect the header, similar to
+//! QUIC's header protection, where the server address and port are masked
+//! using a key shared by the client and its servers.
+//!
+//! The version, source, reserved bits and frame counter remain in the clear.
+//! Receivers can therefore still filter frames by their direction before
+//! performing any cryptographic work, and the frame counter remains available
+//! for deriving the nonce used to open the payload.
+//!
+//! The mask is derived by encrypting a sample of the frame with AES-128, being
+//! the frame counter in big endian order followed by up to the first 14 bytes
+//! of the encrypted payload, padded with zeros. The low 10 bits of the first
+//! two bytes of the result, taken in big endian order, are exclusive-ored with
+//! bits 3..=12 of the header. As the sample is unaffected by the mask, the
+//! same operation both protects and unprotects a header.
+//!
+//! Header protection is applied after a payload has been sealed, and removed
+//! before it is opened, so that the payload's associated data is always the
+//! unprotected header. The header protection key must be distinct from the key
+//! used to seal payloads.
+
+use aes::{cipher::generic_array::GenericArray, Aes128, Block, BlockEncrypt, NewBlockCipher};
+use ccm::{
+    aead::{generic_array::ArrayLength, AeadInPlace, NewAead},
+    Ccm, TagSize,
+};
+
+pub use ccm::consts::{U10, U12, U14, U16, U4, U6, U8};
+
+use crate::{timing, timing::Phase, DataFrame, Header, ParseError, MAX_ENCRYPTED_PAYLOAD_LEN};
+
+type AesCcm<M> = Ccm<Aes128, M, U8>;
+
+/// The sizes of MAC that payloads may be sealed with, being the even sizes from
+/// [U4] to [U16] bytes.
+pub trait MacSize: ArrayLength<u8> + TagSize {}
+
+impl<M: ArrayLength<u8> + TagSize> MacSize for M {}
+
+/// The reasons that a payload may fail to be sealed or opened.
+#[derive(Debug, PartialEq)]
+pub enum CryptoError {
+    /// The buffer to write to is too small.
+    BufferTooSmall,
+    /// The encrypted payload would exceed the greatest length permitted.
+    PayloadTooLong,
+    /// The encrypted payload failed to authenticate.
+    Unauthenticated,
+    /// The header of the frame to open could not be parsed, or its encrypted
+    /// payload is too short to contain a MAC, being [ParseError::Truncated].
+    Parse(ParseError),
+    /// The key of a [KeySchedule] is due to be rotated, and so it seals no
+    /// more frames lest a nonce be reused with it.
+    RotationDue,
+    /// The frame counter is not ahead of the last one that the current key of
+    /// a [KeySchedule] sealed with, and so its nonce may have been used.
+    CounterNotAhead,
+}
+
+/// Seals payloads using a key, with the cipher being set up once. Payloads are
+/// sealed with a MAC of 4 bytes, or of `M` bytes when created with
+/// [Sealer::with_mac_size].
+pub struct Sealer<M: MacSize = U4> {
+    cipher: AesCcm<M>,
+}
+
+impl Sealer {
+    /// Create a sealer from the bytes of a key.
+    pub fn new(key: &[u8; 16]) -> Self {
+        Self::with_mac_size(key)
+    }
+}
+
+impl<M: MacSize> Sealer<M> {
+    /// The number of bytes of the MAC appended to each encrypted payload.
+    pub const MAC_SIZE: usize = M::USIZE;
+
+    /// Create a sealer from the bytes of a key, sealing payloads with a MAC
+    /// of `M` bytes.
+    pub fn with_mac_size(key: &[u8; 16]) -> Self {
+        Self {
+            cipher: AesCcm::new(GenericArray::from_slice(key)),
+        }
+    }
+
+    /// Seal the plaintext of the next frame to be sent with a given header,
+    /// writing the encrypted payload inclusive of its MAC to `out` and
+    /// returning its length.
+    pub fn seal_next(
+        &self,
+        header: &Header,
+        nonce: &[u8; 8],
+        plaintext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        self.seal_with(&header.associated_data(), nonce, plaintext, out)
+    }
+
+    // Seal with the associated data given, being the header's unless it is
+    // qualified e.g. by the compression of the plaintext.
+    pub(crate) fn seal_with(
+        &self,
+        associated_data: &[u8],
+        nonce: &[u8; 8],
+        plaintext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        let len = plaintext.len() + Self::MAC_SIZE;
+        if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(CryptoError::PayloadTooLong);
+        }
+        let out = out.get_mut(..len).ok_or(CryptoError::BufferTooSmall)?;
+        let (ciphertext, mac) = out.split_at_mut(plaintext.len());
+        ciphertext.copy_from_slice(plaintext);
+        timing::timed(Phase::Seal, || {
+            let tag = self
+                .cipher
+                .encrypt_in_place_detached(
+                    GenericArray::from_slice(nonce),
+                    associated_data,
+                    ciphertext,
+                )
+                .map_err(|_| CryptoError::PayloadTooLong)?;
+            mac.copy_from_slice(&tag);
+            Ok(len)
+        })
+    }
+}
+
+/// Opens payloads using a key, with the cipher being set up once. Payloads are
+/// expected to have been sealed with a MAC of 4 bytes, or of `M` bytes when
+/// created with [Opener::with_mac_size].
+pub struct Opener<M: MacSize = U4> {
+    cipher: AesCcm<M>,
+}
+
+impl Opener {
+    /// Create an opener from the bytes of a key.
+    pub fn new(key: &[u8; 16]) -> Self {
+        Self::with_mac_size(key)
+    }
+}
+
+impl<M: MacSize> Opener<M> {
+    /// The number of bytes of the MAC expected at the end of each encrypted
+    /// payload.
+    pub const MAC_SIZE: usize = M::USIZE;
+
+    /// Create an opener from the bytes of a key, opening payloads sealed with a
+    /// MAC of `M` bytes.
+    pub fn with_mac_size(key: &[u8; 16]) -> Self {
+        Self {
+            cipher: AesCcm::new(GenericArray::from_slice(key)),
+        }
+    }
+
+    /// Open the encrypted payload of a frame received with a given header,
+    /// writing its plaintext to `out` and returning its length. Should the
+    /// payload fail to authenticate then `out` is cleared. A payload too short
+    /// to contain a MAC of [Opener::MAC_SIZE] bytes is refused as truncated
+    /// without involving the cipher.
+    pub fn open(
+        &self,
+        header: &Header,
+        nonce: &[u8; 8],
+        encrypted_payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        self.open_with(&header.associated_data(), nonce, encrypted_payload, out)
+    }
+
+    // Open with the associated data given, as per Sealer::seal_with.
+    pub(crate) fn open_with(
+        &self,
+        associated_data: &[u8],
+        nonce: &[u8; 8],
+        encrypted_payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        let len = encrypted_payload
+            .len()
+            .checked_sub(Self::MAC_SIZE)
+            .ok_or(CryptoError::Parse(ParseError::Truncated {
+                expected: Self::MAC_SIZE,
+                got: encrypted_payload.len(),
+            }))?;
+        let (ciphertext, mac) = encrypted_payload.split_at(len);
+        let out = out.get_mut(..len).ok_or(CryptoError::BufferTooSmall)?;
+        out.copy_from_slice(ciphertext);
+        timing::timed(Phase::Open, || {
+            self.cipher
+                .decrypt_in_place_detached(
+                    GenericArray::from_slice(nonce),
+                    associated_data,
+                    out,
+                    GenericArray::from_slice(mac),
+                )
+                .map_err(|_| {
+                    out.fill(0);
+                    CryptoError::Unauthenticated
+                })
+        })?;
+        Ok(len)
+    }
+}
+
+/// The number of frames that a [KeySchedule] seals with a key before a rotation
+/// is due by default, leaving a margin of 256 frames in which to complete the
+/// rotation before the frame counter cycles.
+pub const DEFAULT_ROTATION_INTERVAL: u16 = 0xFF00;
+
+/// The keys of a session in one direction, rotating them with the frame counter
+/// so that no nonce is reused with a key. Frames are sealed with the current
+/// key, and opened with either the current key or, during a transition, the
+/// previous one. The frame counter at which the current key became active is
+/// recorded, with a rotation being due once the counter has advanced by the
+/// rotation interval since, or once the interval's number of frames have been
+/// sealed with the key. The key then seals no more frames until it is rotated,
+/// even should the counter cycle back to within the interval. Nor does the key
+/// seal a frame whose counter is not ahead of the last one it sealed.
+///
+/// Both ends of a session hold a schedule for each direction, and are expected
+/// to rotate to the same keys.
+pub struct KeySchedule<M: MacSize = U4> {
+    sealer: Sealer<M>,
+    opener: Opener<M>,
+    previous: Option<Opener<M>>,
+    activated_at: u16,
+    rotation_interval: u16,
+    sealed: u16,
+    last_sealed: Option<u16>,
+    due: bool,
+}
+
+impl KeySchedule {
+    /// Create a schedule whose first key becomes active at a given frame
+    /// counter.
+    pub fn new(key: &[u8; 16], frame_counter: u16) -> Self {
+        Self::with_mac_size(key, frame_counter)
+    }
+}
+
+impl<M: MacSize> KeySchedule<M> {
+    /// Create a schedule as per [KeySchedule::new], sealing and opening
+    /// payloads with a MAC of `M` bytes.
+    pub fn with_mac_size(key: &[u8; 16], frame_counter: u16) -> Self {
+        Self {
+            sealer: Sealer::with_mac_size(key),
+            opener: Opener::with_mac_size(key),
+            previous: None,
+            activated_at: frame_counter,
+            rotation_interval: DEFAULT_ROTATION_INTERVAL,
+            sealed: 0,
+            last_sealed: None,
+            due: false,
+        }
+    }
+
+    /// Rotate after sealing a given number of frames with a key. By default,
+    /// this is [DEFAULT_ROTATION_INTERVAL].
+    pub fn rotating_after(mut self, rotation_interval: u16) -> Self {
+        self.rotation_interval = rotation_interval;
+        self
+    }
+
+    /// True if the frame counter has advanced by the rotation interval since
+    /// the current key became active, or the interval's number of frames have
+    /// been sealed with it or refused, and so the key must be rotated before
+    /// sealing with it further.
+    pub fn should_rotate(&self, frame_counter: u16) -> bool {
+        self.due
+            || self.sealed >= self.rotation_interval
+            || frame_counter.wrapping_sub(self.activated_at) >= self.rotation_interval
+    }
+
+    /// Make a new key current as of a given frame counter, retaining the
+    /// current one as previous so that frames in flight continue to open. Any
+    /// key previously retained is retired.
+    pub fn rotate(&mut self, key: &[u8; 16], frame_counter: u16) {
+        let opener = core::mem::replace(&mut self.opener, Opener::with_mac_size(key));
+        self.sealer = Sealer::with_mac_size(key);
+        self.previous = Some(opener);
+        self.activated_at = frame_counter;
+        self.sealed = 0;
+        self.last_sealed = None;
+        self.due = false;
+    }
+
+    /// Retire the previous key, once no more frames sealed with it are
+    /// expected, so that they no longer open.
+    pub fn retire_previous(&mut self) {
+        self.previous = None;
+    }
+
+    /// Seal the plaintext of the next frame with the current key, as per
+    /// [Sealer::seal_next]. [CryptoError::RotationDue] is returned should a
+    /// rotation be due as of the header's frame counter, as per
+    /// [KeySchedule::should_rotate], with no more frames being sealed until
+    /// the key is rotated. [CryptoError::CounterNotAhead] is returned should
+    /// the header's frame counter not be ahead of the last one sealed with the
+    /// key, as counted from the frame counter at which it became active.
+    pub fn seal_next(
+        &mut self,
+        header: &Header,
+        nonce: &[u8; 8],
+        plaintext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        if self.should_rotate(header.frame_counter) {
+            self.due = true;
+            return Err(CryptoError::RotationDue);
+        }
+        let since_activated = |frame_counter: u16| frame_counter.wrapping_sub(self.activated_at);
+        if self
+            .last_sealed
+            .is_some_and(|last| since_activated(header.frame_counter) <= since_activated(last))
+        {
+            return Err(CryptoError::CounterNotAhead);
+        }
+        let len = self.sealer.seal_next(header, nonce, plaintext, out)?;
+        self.sealed += 1;
+        self.last_sealed = Some(header.frame_counter);
+        Ok(len)
+    }
+
+    /// Open the encrypted payload of a frame with the current key, or else the
+    /// previous one, as per [Opener::open].
+    pub fn open(
+        &self,
+        header: &Header,
+        nonce: &[u8; 8],
+        encrypted_payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        match self.opener.open(header, nonce, encrypted_payload, out) {
+            Err(CryptoError::Unauthenticated) => match &self.previous {
+                Some(previous) => previous.open(header, nonce, encrypted_payload, out),
+                None => Err(CryptoError::Unauthenticated),
+            },
+            result => result,
+        }
+    }
+}
+
+/// Bits 3..=12 of the header, holding the server address and port.
+const PROTECTED_BITS: u32 = 0x3FF << 3;
+
+/// A key for protecting the server address and port of data frame headers.
+pub struct HeaderProtectionKey {
+    cipher: Aes128,
+}
+
+impl HeaderProtectionKey {
+    /// Create a header protection key from its bytes.
+    pub fn new(key: &[u8; 16]) -> Self {
+        Self {
+            cipher: Aes128::new(GenericArray::from_slice(key)),
+        }
+    }
+
+    fn mask(&self, frame: &DataFrame) -> u32 {
+        let mut sample = Block::default();
+        let frame_counter = (frame.header >> 16) as u16;
+        sample[..2].copy_from_slice(&frame_counter.to_be_bytes());
+        let len = frame.encrypted_payload.len().min(sample.len() - 2);
+        sample[2..2 + len].copy_from_slice(&frame.encrypted_payload[..len]);
+        self.cipher.encrypt_block(&mut sample);
+        (u32::from(u16::from_be_bytes([sample[0], sample[1]])) << 3) & PROTECTED_BITS
+    }
+}
+
+impl<'a> DataFrame<'a> {
+    /// Create a data frame with a plaintext sealed using a key and the nonce
+    /// for the header with a salt, as per [Header::nonce]. The encrypted payload
+    /// is written to `out`, which the frame then borrows.
+    ///
+    /// The cipher is set up on each call, so a [Sealer] should be preferred
+    /// when sealing many frames with the same key.
+    pub fn seal(
+        header: &'a Header,
+        key: &[u8; 16],
+        salt: &[u8; 6],
+        plaintext: &[u8],
+        out: &'a mut [u8],
+    ) -> Result<Self, CryptoError> {
+        let len = Sealer::new(key).seal_next(header, &header.nonce(salt), plaintext, out)?;
+        Ok(Self::new(header, &out[..len]))
+    }
+
+    /// Parse this frame and open its encrypted payload using a key and the
+    /// nonce for its header with a salt, as per [Header::nonce]. The plaintext
+    /// is written to `out`, and returned along with the header. An error is
+    /// returned if the payload fails to authenticate, in which case `out` is
+    /// cleared.
+    ///
+    /// The cipher is set up on each call, so an [Opener] should be preferred
+    /// when opening many frames with the same key.
+    pub fn open<'b>(
+        &self,
+        key: &[u8; 16],
+        salt: &[u8; 6],
+        out: &'b mut [u8],
+    ) -> Result<(Header, &'b [u8]), CryptoError> {
+        let (header, encrypted_payload) = self.parse().map_err(CryptoError::Parse)?;
+        let len = Opener::new(key).open(&header, &header.nonce(salt), encrypted_payload, out)?;
+        Ok((header, &out[..len]))
+    }
+
+    /// Return this frame with its server address and port masked.
+    pub fn protect_header(&self, key: &HeaderProtectionKey) -> Self {
+        Self {
+            header: self.header ^ key.mask(self),
+            encrypted_payload: self.encrypted_payload,
+        }
+    }
+
+    /// Return this frame with the mask of its server address and port removed,
+    /// ready to be parsed.
+    pub fn unprotect_header(&self, key: &HeaderProtectionKey) -> Self {
+        self.protect_header(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encrypted_len, DataSource, ServerAddress, ServerPort, MAC_SIZE};
+
+    #[test]
+    fn test_header_protection_round_trip() {
+        let key = HeaderProtectionKey::new(b"FEDCBA9876543210");
+
+        let header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 1,
+        };
+        let encrypted_payload = [112, 28, 128, 64, 171, 5, 37, 219, 171, 39, 144, 217, 94];
+        let frame = DataFrame::new(&header, &encrypted_payload);
+
+        let protected = frame.protect_header(&key);
+        assert_ne!(protected.header, frame.header);
+        assert_eq!(
+            protected.header & !PROTECTED_BITS,
+            frame.header & !PROTECTED_BITS
+        );
+        assert_eq!(protected.encrypted_payload, frame.encrypted_payload);
+
+        let unprotected = protected.unprotect_header(&key);
+        assert_eq!(unprotected, frame);
+        assert_eq!(unprotected.parse(), Ok((header, &encrypted_payload[..])));
+    }
+
+    #[test]
+    fn test_header_protection_varies_with_counter() {
+        let key = HeaderProtectionKey::new(b"FEDCBA9876543210");
+
+        let masks = (0..4)
+            .map(|frame_counter| {
+                let header = Header {
+                    version: 0,
+                    source: DataSource::Client,
+                    server_address: ServerAddress::new_unchecked(0),
+                    server_port: ServerPort::new_unchecked(0),
+                    frame_counter,
+                };
+                let frame = DataFrame::new(&header, &[1, 2, 3, 4]);
+                frame.protect_header(&key).header & PROTECTED_BITS
+            })
+            .collect::<std::vec::Vec<_>>();
+        assert!(masks.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    fn server_header(frame_counter: u16) -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter,
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealer = Sealer::new(b"0123456789ABCDEF");
+        let opener = Opener::new(b"0123456789ABCDEF");
+        let nonce = [0; 8];
+        let header = server_header(1);
+
+        let mut encrypted_payload = [0; 32];
+        let len = sealer
+            .seal_next(&header, &nonce, b"some data", &mut encrypted_payload)
+            .unwrap();
+        // The same as the data frame tests, which set up a cipher for each call.
+        assert_eq!(
+            &encrypted_payload[..len],
+            [112, 28, 128, 64, 171, 5, 37, 219, 171, 39, 144, 217, 94]
+        );
+
+        let mut plaintext = [0; 32];
+        let len = opener
+            .open(&header, &nonce, &encrypted_payload[..len], &mut plaintext)
+            .unwrap();
+        assert_eq!(&plaintext[..len], b"some data");
+
+        // The sealer is reused for subsequent frames.
+        let header = server_header(2);
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 2];
+        let len = sealer
+            .seal_next(&header, &nonce, b"more data", &mut encrypted_payload)
+            .unwrap();
+        let encrypted_payload = &encrypted_payload[..len];
+        assert_eq!(
+            opener.open(&header, &nonce, encrypted_payload, &mut plaintext),
+            Ok(9)
+        );
+        assert_eq!(&plaintext[..9], b"more data");
+
+        // The header is authenticated.
+        assert_eq!(
+            opener.open(&server_header(3), &nonce, encrypted_payload, &mut plaintext),
+            Err(CryptoError::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn test_seal_and_open_errors() {
+        let sealer = Sealer::new(b"0123456789ABCDEF");
+        let opener = Opener::new(b"0123456789ABCDEF");
+        let nonce = [0; 8];
+        let header = server_header(1);
+
+        let mut out = [0; 128];
+        assert_eq!(
+            sealer.seal_next(&header, &nonce, b"some data", &mut out[..12]),
+            Err(CryptoError::BufferTooSmall)
+        );
+        assert_eq!(
+            sealer.seal_next(&header, &nonce, &[0; 124], &mut out),
+            Err(CryptoError::PayloadTooLong)
+        );
+        assert_eq!(
+            sealer.seal_next(&header, &nonce, &[0; 123], &mut out),
+            Ok(127)
+        );
+
+        let mut plaintext = [0; 8];
+        assert_eq!(
+            opener.open(&header, &nonce, &[0; 3], &mut plaintext),
+            Err(CryptoError::Parse(ParseError::Truncated {
+                expected: 4,
+                got: 3
+            }))
+        );
+        assert_eq!(
+            opener.open(&header, &nonce, &[0; 13], &mut plaintext),
+            Err(CryptoError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn test_seal_and_open_with_mac_sizes() {
+        let key = b"0123456789ABCDEF";
+        let nonce = [0; 8];
+        let header = server_header(1);
+        let mut encrypted_payload = [0; 32];
+        let mut plaintext = [0; 32];
+
+        assert_eq!(<Sealer>::MAC_SIZE, MAC_SIZE);
+        let len = Sealer::new(key)
+            .seal_next(&header, &nonce, b"some data", &mut encrypted_payload)
+            .unwrap();
+        assert_eq!(len, encrypted_len(9));
+        let short = encrypted_payload;
+        assert_eq!(
+            Opener::new(key).open(&header, &nonce, &short[..len], &mut plaintext),
+            Ok(9)
+        );
+
+        assert_eq!(Sealer::<U8>::MAC_SIZE, 8);
+        let len = Sealer::<U8>::with_mac_size(key)
+            .seal_next(&header, &nonce, b"some data", &mut encrypted_payload)
+            .unwrap();
+        assert_eq!(len, 9 + 8);
+        let opener = Opener::<U8>::with_mac_size(key);
+        assert_eq!(
+            opener.open(&header, &nonce, &encrypted_payload[..len], &mut plaintext),
+            Ok(9)
+        );
+        assert_eq!(&plaintext[..9], b"some data");
+
+        // The ciphertext is the same, with only the MACs differing.
+        assert_eq!(short[..9], encrypted_payload[..9]);
+        assert_ne!(short[9..13], encrypted_payload[9..13]);
+
+        // Payloads must be opened with the MAC size they were sealed with.
+        assert_eq!(
+            Opener::new(key).open(&header, &nonce, &encrypted_payload[..len], &mut plaintext),
+            Err(CryptoError::Unauthenticated)
+        );
+        assert_eq!(
+            opener.open(&header, &nonce, &short[..13], &mut plaintext),
+            Err(CryptoError::Unauthenticated)
+        );
+        assert_eq!(
+            Sealer::<U16>::with_mac_size(key).seal_next(&header, &nonce, &[0; 112], &mut [0; 128]),
+            Err(CryptoError::PayloadTooLong)
+        );
+    }
+
+    #[test]
+    fn test_open_payload_shorter_than_mac() {
+        let key = b"0123456789ABCDEF";
+        let header = server_header(1);
+        let mut plaintext = [0; 8];
+
+        // A payload long enough for a 4 byte MAC and not for an 8 byte one is
+        // refused as truncated before reaching the cipher.
+        let opener = Opener::<U8>::with_mac_size(key);
+        assert_eq!(
+            opener.open(&header, &[0; 8], &[0; 7], &mut plaintext),
+            Err(CryptoError::Parse(ParseError::Truncated {
+                expected: 8,
+                got: 7
+            }))
+        );
+        assert_eq!(
+            opener.open(&header, &[0; 8], &[0; 8], &mut plaintext),
+            Err(CryptoError::Unauthenticated)
+        );
+
+        let frame = DataFrame::new(&header, &[0; 2]);
+        assert_eq!(
+            frame.open(key, &[0; 6], &mut plaintext),
+            Err(CryptoError::Parse(ParseError::Truncated {
+                expected: 4,
+                got: 2
+            }))
+        );
+    }
+
+    #[test]
+    fn test_data_frame_seal_and_open() {
+        let key = b"0123456789ABCDEF";
+        let salt = [1, 2, 3, 4, 5, 6];
+        let header = server_header(1);
+
+        let mut encrypted_payload = [0; 32];
+        let frame =
+            DataFrame::seal(&header, key, &salt, b"some data", &mut encrypted_payload).unwrap();
+        assert_eq!(frame.parse().unwrap().0, header);
+
+        let mut plaintext = [0; 32];
+        assert_eq!(
+            frame.open(key, &salt, &mut plaintext),
+            Ok((header, &b"some data"[..]))
+        );
+
+        // A different salt yields a different nonce, so the MAC mismatches.
+        assert_eq!(
+            frame.open(key, &[0; 6], &mut plaintext),
+            Err(CryptoError::Unauthenticated)
+        );
+        assert_eq!(&plaintext[..9], [0; 9]);
+
+        let frame = DataFrame {
+            header: frame.header | 2,
+            encrypted_payload: frame.encrypted_payload,
+        };
+        assert_eq!(
+            frame.open(key, &salt, &mut plaintext),
+            Err(CryptoError::Parse(ParseError::UnsupportedVersion(2)))
+        );
+    }
+
+    #[test]
+    fn test_key_schedule_rotates_across_wraparound() {
+        let key_1 = b"0123456789ABCDEF";
+        let key_2 = b"FEDCBA9876543210";
+        let key_3 = b"00112233445566AA";
+        let salt = [1, 2, 3, 4, 5, 6];
+        let mut sender = KeySchedule::new(key_1, 0x0100);
+        let mut receiver = KeySchedule::new(key_1, 0x0100);
+
+        let seal = |schedule: &mut KeySchedule, frame_counter| {
+            let header = server_header(frame_counter);
+            let mut out = [0; 16];
+            let len = schedule
+                .seal_next(&header, &header.nonce(&salt), b"some data", &mut out)
+                .unwrap();
+            (header, out, len)
+        };
+        let open = |schedule: &KeySchedule, (header, sealed, len): (Header, [u8; 16], usize)| {
+            let mut out = [0; 16];
+            schedule
+                .open(&header, &header.nonce(&salt), &sealed[..len], &mut out)
+                .map(|len| out[..len] == *b"some data")
+        };
+
+        // A rotation is due once the counter nears the end of its cycle.
+        assert!(!sender.should_rotate(0x0100));
+        assert!(!sender.should_rotate(0xFFFF));
+        assert!(sender.should_rotate(0x0000));
+        assert!(sender.should_rotate(0x00FF));
+
+        // The last frames sealed with the first key, one of which remains in
+        // flight while both ends rotate.
+        let old = seal(&mut sender, 0xFFFE);
+        assert_eq!(open(&receiver, old), Ok(true));
+        let in_flight = seal(&mut sender, 0xFFFF);
+        sender.rotate(key_2, 0x0000);
+        receiver.rotate(key_2, 0x0000);
+        assert!(!sender.should_rotate(0x0000));
+        assert!(!sender.should_rotate(0x0001));
+
+        // Frames from both keys open, with the counter having wrapped.
+        let new = seal(&mut sender, 0x0000);
+        assert_eq!(open(&receiver, new), Ok(true));
+        assert_eq!(open(&receiver, in_flight), Ok(true));
+        assert_eq!(open(&receiver, seal(&mut sender, 0x0001)), Ok(true));
+
+        // The same counter sealed with each key yields differing payloads, so
+        // no nonce is reused with a key.
+        assert_ne!(seal(&mut KeySchedule::new(key_1, 0), 0x0000).1, new.1);
+
+        // Once retired, or rotated past, the first key no longer opens.
+        let mut retired = KeySchedule::new(key_1, 0);
+        retired.rotate(key_2, 0);
+        retired.retire_previous();
+        assert_eq!(open(&retired, in_flight), Err(CryptoError::Unauthenticated));
+        assert_eq!(open(&retired, new), Ok(true));
+        receiver.rotate(key_3, 0x8000);
+        assert_eq!(
+            open(&receiver, in_flight),
+            Err(CryptoError::Unauthenticated)
+        );
+        assert_eq!(open(&receiver, new), Ok(true));
+    }
+
+    #[test]
+    fn test_key_schedule_rotation_interval() {
+        let mut schedule = KeySchedule::new(b"0123456789ABCDEF", 0xFFF0).rotating_after(0x20);
+        assert!(!schedule.should_rotate(0xFFF0));
+        assert!(!schedule.should_rotate(0x000F));
+        assert!(schedule.should_rotate(0x0010));
+
+        schedule.rotate(b"FEDCBA9876543210", 0x0010);
+        assert!(!schedule.should_rotate(0x0010));
+        assert!(schedule.should_rotate(0x0030));
+    }
+
+    #[test]
+    fn test_key_schedule_refuses_to_seal_a_full_cycle() {
+        let salt = [1, 2, 3, 4, 5, 6];
+        let mut schedule = KeySchedule::new(b"0123456789ABCDEF", 0);
+        let seal = |schedule: &mut KeySchedule, frame_counter| {
+            let header = server_header(frame_counter);
+            let mut out = [0; 16];
+            schedule.seal_next(&header, &header.nonce(&salt), b"some data", &mut out)
+        };
+
+        // Every frame up to the rotation interval is sealed, and then sealing
+        // is refused for the rest of the cycle...
+        for frame_counter in 0..DEFAULT_ROTATION_INTERVAL {
+            assert!(seal(&mut schedule, frame_counter).is_ok());
+        }
+        for frame_counter in DEFAULT_ROTATION_INTERVAL..=0xFFFF {
+            assert_eq!(
+                seal(&mut schedule, frame_counter),
+                Err(CryptoError::RotationDue)
+            );
+        }
+
+        // ...and once the counter has cycled, as its nonces would be reused.
+        assert!(schedule.should_rotate(0x0000));
+        assert_eq!(seal(&mut schedule, 0x0000), Err(CryptoError::RotationDue));
+
+        // A counter skipping back is refused, as its nonce has been used...
+        let mut schedule = KeySchedule::new(b"0123456789ABCDEF", 0).rotating_after(4);
+        assert!(seal(&mut schedule, 0).is_ok());
+        assert!(seal(&mut schedule, 1).is_ok());
+        assert_eq!(seal(&mut schedule, 0), Err(CryptoError::CounterNotAhead));
+        assert_eq!(seal(&mut schedule, 1), Err(CryptoError::CounterNotAhead));
+
+        // ...whereas one skipping ahead is sealed, with frames sealed being
+        // counted towards the rotation.
+        assert!(seal(&mut schedule, 3).is_ok());
+        assert_eq!(seal(&mut schedule, 4), Err(CryptoError::RotationDue));
+
+        // Once rotated, sealing resumes with the new key.
+        schedule.rotate(b"FEDCBA9876543210", 0);
+        assert!(seal(&mut schedule, 0).is_ok());
+    }
+}
@@ -0,0 +1,217 @@
+use core::fmt;
+
+use crate::{DataFrame, DataSource, ParseError, HEADER_SIZE, RESERVED_MASK};
+
+/// What could be decoded of the bytes of a data frame, valid or not, as
+/// returned by [DataFrame::inspect] e.g. for a dissector of captured
+/// datagrams. The fields of the header are present given at least its 4
+/// bytes, and the payload's length given the byte following them. They are
+/// decoded as they are packed, and so may be out of range for a [crate::Header].
+///
+/// Displaying the info yields a one line breakdown of the frame.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameInfo {
+    /// The number of bytes inspected.
+    pub len: usize,
+    /// The bits of the header as packed, as per [DataFrame::raw_header].
+    pub raw_header: Option<u32>,
+    /// The protocol version, being any of 0..=3.
+    pub version: Option<u8>,
+    /// The source of the frame.
+    pub source: Option<DataSource>,
+    /// The server address.
+    pub server_address: Option<u8>,
+    /// The server port.
+    pub server_port: Option<u8>,
+    /// The reserved bits 13..=15, shifted to 0..=2.
+    pub reserved: Option<u8>,
+    /// The frame counter.
+    pub frame_counter: Option<u16>,
+    /// The length of the encrypted payload declared, which may exceed the
+    /// bytes present.
+    pub payload_len: Option<usize>,
+    /// Why the bytes fail to be read and parsed by [DataFrame::from_bytes]
+    /// and [DataFrame::parse], if they do.
+    pub error: Option<ParseError>,
+}
+
+impl FrameInfo {
+    /// True if the bytes would be read and parsed cleanly.
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// True if any of the reserved bits are set.
+    pub fn reserved_bits_set(&self) -> bool {
+        self.reserved.is_some_and(|r| r != 0)
+    }
+}
+
+impl fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (
+            self.raw_header,
+            self.version,
+            self.source,
+            self.server_address,
+            self.server_port,
+            self.reserved,
+            self.frame_counter,
+        ) {
+            (
+                Some(raw_header),
+                Some(version),
+                Some(source),
+                Some(server_address),
+                Some(server_port),
+                Some(reserved),
+                Some(frame_counter),
+            ) => write!(
+                f,
+                "header {:#010x}: version {}, {:?}, address {}, port {}, reserved {:#05b}, counter {}",
+                raw_header, version, source, server_address, server_port, reserved, frame_counter
+            )?,
+            _ => write!(f, "no header")?,
+        }
+        if let Some(payload_len) = self.payload_len {
+            write!(f, ", payload of {} bytes", payload_len)?;
+        }
+        write!(f, " in {} bytes", self.len)?;
+        match &self.error {
+            Some(e) => write!(f, ": {}", e),
+            None => write!(f, ": valid"),
+        }
+    }
+}
+
+impl DataFrame<'_> {
+    /// Decode whatever can be decoded of the bytes of a data frame, as written
+    /// by [DataFrame::to_bytes], without rejecting them as [DataFrame::parse]
+    /// would. Any bytes are accepted, including those too few or too many for
+    /// a frame.
+    pub fn inspect(bytes: &[u8]) -> FrameInfo {
+        let raw_header = bytes
+            .get(..4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let field = |shift: u32, mask: u32| raw_header.map(|h| ((h >> shift) & mask) as u8);
+        FrameInfo {
+            len: bytes.len(),
+            raw_header,
+            version: field(0, 0x03),
+            source: raw_header.map(|h| {
+                if h & 0x04 == 0 {
+                    DataSource::Client
+                } else {
+                    DataSource::Server
+                }
+            }),
+            server_address: field(3, 0x1F),
+            server_port: field(8, 0x1F),
+            reserved: raw_header.map(|h| ((h & RESERVED_MASK) >> 13) as u8),
+            frame_counter: raw_header.map(|h| (h >> 16) as u16),
+            payload_len: bytes.get(HEADER_SIZE - 1).map(|len| *len as usize),
+            error: DataFrame::from_bytes(bytes)
+                .and_then(|frame| frame.parse())
+                .err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Header, ServerAddress, ServerPort};
+
+    fn frame_bytes(out: &mut [u8]) -> usize {
+        let header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(3),
+            server_port: ServerPort::new_unchecked(4),
+            frame_counter: 5,
+        };
+        DataFrame::new(&header, b"some data").to_bytes(out).unwrap()
+    }
+
+    #[test]
+    fn test_inspect_valid_frame() {
+        let mut bytes = [0; 16];
+        let len = frame_bytes(&mut bytes);
+        let info = DataFrame::inspect(&bytes[..len]);
+        assert_eq!(
+            info,
+            FrameInfo {
+                len: 14,
+                raw_header: Some(0x0005_041C),
+                version: Some(0),
+                source: Some(DataSource::Server),
+                server_address: Some(3),
+                server_port: Some(4),
+                reserved: Some(0),
+                frame_counter: Some(5),
+                payload_len: Some(9),
+                error: None,
+            }
+        );
+        assert!(info.is_valid());
+        assert!(!info.reserved_bits_set());
+        assert_eq!(
+            info.to_string(),
+            "header 0x0005041c: version 0, Server, address 3, port 4, reserved 0b000, counter 5, \
+             payload of 9 bytes in 14 bytes: valid"
+        );
+    }
+
+    #[test]
+    fn test_inspect_reserved_bits_set() {
+        let mut bytes = [0; 16];
+        let len = frame_bytes(&mut bytes);
+        bytes[2] |= 0xA0;
+        let info = DataFrame::inspect(&bytes[..len]);
+        assert_eq!(info.reserved, Some(0b101));
+        assert_eq!(info.server_port, Some(4));
+        assert!(info.reserved_bits_set());
+        assert!(!info.is_valid());
+        assert_eq!(info.error, Some(ParseError::ReservedBitsSet));
+        assert!(info.to_string().ends_with(": reserved header bits are set"));
+    }
+
+    #[test]
+    fn test_inspect_malformed_bytes() {
+        let info = DataFrame::inspect(&[]);
+        assert_eq!(info.raw_header, None);
+        assert_eq!(info.payload_len, None);
+        assert_eq!(
+            info.error,
+            Some(ParseError::Truncated {
+                expected: HEADER_SIZE,
+                got: 0
+            })
+        );
+        assert_eq!(
+            info.to_string(),
+            "no header in 0 bytes: truncated: expected 5 bytes, got 0"
+        );
+
+        // A header without its payload's length.
+        let info = DataFrame::inspect(&[0, 5, 4, 0x1C]);
+        assert_eq!(info.frame_counter, Some(5));
+        assert_eq!(info.payload_len, None);
+
+        // A length exceeding the bytes present, and the greatest permitted.
+        let mut bytes = [0xFF; 300];
+        let info = DataFrame::inspect(&bytes);
+        assert_eq!(info.payload_len, Some(255));
+        assert_eq!(info.error, Some(ParseError::PayloadTooLong(255)));
+        bytes[4] = 10;
+        let info = DataFrame::inspect(&bytes[..8]);
+        assert_eq!(
+            info.error,
+            Some(ParseError::Truncated {
+                expected: 15,
+                got: 8
+            })
+        );
+    }
+}
@@ -1,32 +1,259 @@
 #![cfg_attr(not(test), no_std)]
 #![doc = include_str!("../README.md")]
 
+use core::fmt;
+
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "checksum")]
+pub mod checksum;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+mod inspect;
+#[cfg(feature = "insecure-plaintext")]
+pub mod plaintext;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "timing")]
+pub mod timing;
+#[cfg(not(feature = "timing"))]
+mod timing;
+
+pub use inspect::FrameInfo;
+use timing::Phase;
+
 /// Indicates where data is sourced from i.e. its direction.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum DataSource {
     Client,
     Server,
 }
 
-/// There was an error parsing the data frame. Possibly due
-/// to an incompatible data frame version.
-/// was invalid.
+impl DataSource {
+    /// The source of the frames replying to those of this source i.e. the
+    /// server for the client, and the client for the server.
+    pub fn opposite(&self) -> Self {
+        match self {
+            DataSource::Client => DataSource::Server,
+            DataSource::Server => DataSource::Client,
+        }
+    }
+}
+
+/// There was an error parsing the data frame.
 #[derive(Debug, PartialEq)]
-pub struct ParseError {}
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    /// The data frame has a protocol version that this implementation does
+    /// not support, or a header is being created with a version greater
+    /// than 3.
+    UnsupportedVersion(u8),
+    /// The data frame has some of its reserved header bits set, as may be for
+    /// a future revision of the protocol.
+    ReservedBitsSet,
+    /// Fewer bytes were received than the data frame declares, as can happen
+    /// when a datagram is truncated by a receive buffer that is too small.
+    /// Also, a buffer to write a data frame to is too small to hold it.
+    Truncated { expected: usize, got: usize },
+    /// The encrypted payload is longer than the 127 bytes permitted.
+    PayloadTooLong(usize),
+    /// A server address or port is greater than the 31 that can be conveyed.
+    AddressOutOfRange,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            ParseError::ReservedBitsSet => write!(f, "reserved header bits are set"),
+            ParseError::Truncated { expected, got } => {
+                write!(f, "truncated: expected {} bytes, got {}", expected, got)
+            }
+            ParseError::PayloadTooLong(len) => {
+                write!(f, "encrypted payload of {} bytes exceeds 127", len)
+            }
+            ParseError::AddressOutOfRange => write!(f, "server address or port exceeds 31"),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// There was an error encoding a data frame with [DataFrame::encode_into].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncodeError<E> {
+    /// The encrypted payload is longer than the 127 bytes permitted.
+    PayloadTooLong(usize),
+    /// The writer refused a byte, as may be when it is full.
+    Write(E),
+}
+
+impl<E: fmt::Display> fmt::Display for EncodeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::PayloadTooLong(len) => {
+                write!(f, "encrypted payload of {} bytes exceeds 127", len)
+            }
+            EncodeError::Write(e) => write!(f, "write failed: {}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for EncodeError<E> {}
+
+macro_rules! five_bit_fields {
+    ($($(#[$meta:meta])* $name:ident => $what:literal),*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+            #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+            #[serde(try_from = "u8", into = "u8")]
+            pub struct $name(u8);
+
+            impl $name {
+                /// The greatest value that can be conveyed.
+                pub const MAX: u8 = 31;
+
+                /// Create a value, returning an error if it is greater than 31.
+                pub const fn new(value: u8) -> Result<Self, ParseError> {
+                    if value > Self::MAX {
+                        Err(ParseError::AddressOutOfRange)
+                    } else {
+                        Ok(Self(value))
+                    }
+                }
+
+                /// Create a value that is known to be no greater than 31. This
+                /// is only checked in debug builds, with the value otherwise
+                /// being truncated to its range.
+                pub const fn new_unchecked(value: u8) -> Self {
+                    debug_assert!(value <= Self::MAX, concat!($what, " out of range"));
+                    Self(value & Self::MAX)
+                }
+
+                /// The value, being 0..=31.
+                pub const fn get(self) -> u8 {
+                    self.0
+                }
+            }
+
+            impl TryFrom<u8> for $name {
+                type Error = ParseError;
+
+                fn try_from(value: u8) -> Result<Self, Self::Error> {
+                    Self::new(value)
+                }
+            }
+
+            impl From<$name> for u8 {
+                fn from(value: $name) -> Self {
+                    value.0
+                }
+            }
+        )*
+    };
+}
+
+five_bit_fields!(
+    /// The address of a server, being 0..=31 so that it can be conveyed in a
+    /// data frame header.
+    ServerAddress => "server address",
+    /// The port of a server, being 0..=31 so that it can be conveyed in a data
+    /// frame header.
+    ServerPort => "server port"
+);
+
+/// A version of the protocol that this implementation supports, as conveyed by
+/// bits 0..=1 of a data frame's header.
+///
+/// Frames of every version are laid out identically, so that a receiver can
+/// always parse the header of a frame and reply in a version that its sender
+/// understands. Versions instead differ in the layouts of the payloads that
+/// they convey, as the application layer defines:
+///
+/// - Version 0 is the original layout, where a request conveys only an offset
+///   and a command, and a reply conveys only an event and its age.
+/// - Version 1 is the extended layout, where a request also conveys
+///   subscriptions and an acknowledgement, and a reply is one of several kinds
+///   conveying an epoch as well as an event.
+///
+/// A receiver supporting only version 0 is to reply that the version is
+/// unsupported rather than interpret the payload of a version 1 frame, and a
+/// receiver supporting version 1 is to interpret a version 0 payload in the
+/// original layout.
+///
+/// Should a future version change the layout of the header, it must retain the
+/// version at bits 0..=1 so that a receiver can still tell it apart.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[serde(try_from = "u8", into = "u8")]
+pub enum ProtocolVersion {
+    V0 = 0,
+    V1 = 1,
+}
+
+impl ProtocolVersion {
+    /// The latest version that this implementation supports.
+    pub const LATEST: Self = ProtocolVersion::V1;
+
+    /// The version conveyed as a given value, returning an error if it is not
+    /// supported.
+    pub const fn new(version: u8) -> Result<Self, ParseError> {
+        match version {
+            0 => Ok(ProtocolVersion::V0),
+            1 => Ok(ProtocolVersion::V1),
+            _ => Err(ParseError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// The value conveying this version.
+    pub const fn get(self) -> u8 {
+        self as u8
+    }
+}
+
+impl TryFrom<u8> for ProtocolVersion {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<ProtocolVersion> for u8 {
+    fn from(value: ProtocolVersion) -> Self {
+        value.get()
+    }
+}
+
+/// The server address that frames are sent to when addressed to every server
+/// sharing a medium, as is common for multi-drop buses. Being the greatest
+/// address, it is not to be assigned to any one server. Servers act upon the
+/// commands of broadcast frames but do not reply to them, so that their replies
+/// do not collide.
+pub const BROADCAST_ADDRESS: ServerAddress = ServerAddress::new_unchecked(ServerAddress::MAX);
 
 /// The haader fields of the data frame.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Header {
-    /// The protocol version. Should be 0.
+    /// The protocol version, being one of [ProtocolVersion] for frames that
+    /// parse.
     pub version: u8,
     /// The direction of data flow.
     pub source: DataSource,
     /// The address of the server 0..31.
-    pub server_address: u8,
+    pub server_address: ServerAddress,
     /// The port of the server 0..31.
-    pub server_port: u8,
+    pub server_port: ServerPort,
     /// A frame counter for ensuring message authenticity by
     /// being able to vary a nonce. Should be incremented by
     /// the message source and is expected to overflow to zero
@@ -34,6 +261,169 @@ pub struct Header {
     pub frame_counter: u16,
 }
 
+impl Header {
+    /// The protocol version of this header, returning an error if it is not
+    /// supported.
+    pub fn protocol_version(&self) -> Result<ProtocolVersion, ParseError> {
+        ProtocolVersion::new(self.version)
+    }
+
+    /// Begin building a header, which is an alternative to a struct literal
+    /// where the server address and port are named rather than positional.
+    /// The version defaults to 0, the source to the client, and the server
+    /// address, port and frame counter to 0.
+    pub fn builder() -> HeaderBuilder {
+        HeaderBuilder {
+            version: 0,
+            source: DataSource::Client,
+            server_address: 0,
+            server_port: 0,
+            frame_counter: 0,
+        }
+    }
+
+    /// The greatest length of a plaintext that a frame can convey within a
+    /// datagram of a given length once sealed with a tag of a given length,
+    /// e.g. [MAC_SIZE]. This is what remains once the header, the payload's
+    /// length and the tag are conveyed, with the encrypted payload being at
+    /// most 127 bytes. A datagram too small for the header and tag has a
+    /// capacity of 0. Being a const fn, payloads can be asserted to fit at
+    /// compile time:
+    ///
+    /// ```
+    /// use flip_flop_data::{Header, MAC_SIZE};
+    ///
+    /// const EVENT_LEN: usize = 16;
+    /// const _: () = assert!(EVENT_LEN <= Header::payload_capacity(32, MAC_SIZE));
+    /// ```
+    pub const fn payload_capacity(datagram_len: usize, tag_len: usize) -> usize {
+        let len = datagram_len.saturating_sub(HEADER_SIZE);
+        let len = if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            MAX_ENCRYPTED_PAYLOAD_LEN
+        } else {
+            len
+        };
+        len.saturating_sub(tag_len)
+    }
+
+    /// True if this header is addressed to every server i.e. to
+    /// [BROADCAST_ADDRESS].
+    pub fn is_broadcast(&self) -> bool {
+        self.server_address == BROADCAST_ADDRESS
+    }
+
+    /// Increment the frame counter for the next frame from this source,
+    /// returning the new value. The counter overflows to zero after 0xFFFF.
+    pub fn next_counter(&mut self) -> u16 {
+        self.frame_counter = self.frame_counter.wrapping_add(1);
+        self.frame_counter
+    }
+
+    /// This header with a given frame counter.
+    pub fn with_counter(self, frame_counter: u16) -> Self {
+        Self {
+            frame_counter,
+            ..self
+        }
+    }
+
+    /// The nonce for sealing and opening the payload of the frame with this
+    /// header, being a salt followed by the frame counter in big endian order.
+    ///
+    /// The salt is to be exchanged between a client and server out of band,
+    /// with each session having its own. Reusing a salt and frame counter pair
+    /// with a key breaks the confidentiality of the payloads sealed with it, so
+    /// the salt or key must change before the frame counter is repeated.
+    pub fn nonce(&self, salt: &[u8; 6]) -> [u8; 8] {
+        let [counter_hi, counter_lo] = self.frame_counter.to_be_bytes();
+        let [s0, s1, s2, s3, s4, s5] = *salt;
+        [s0, s1, s2, s3, s4, s5, counter_hi, counter_lo]
+    }
+
+    /// The associated data for sealing and opening the payload of the frame
+    /// with this header, being the header as postcard serialises it: each field
+    /// in order, with the source as its variant index and the frame counter in
+    /// little endian order.
+    ///
+    /// The associated data must be the header and nothing else, and be
+    /// byte-identical at both ends, else the payload fails to authenticate.
+    /// Sealers and openers should therefore call this rather than serialise the
+    /// header themselves.
+    pub fn associated_data(&self) -> [u8; HEADER_POSTCARD_MAX] {
+        let source = match self.source {
+            DataSource::Client => 0,
+            DataSource::Server => 1,
+        };
+        let [counter_lo, counter_hi] = self.frame_counter.to_le_bytes();
+        [
+            self.version,
+            source,
+            self.server_address.get(),
+            self.server_port.get(),
+            counter_lo,
+            counter_hi,
+        ]
+    }
+}
+
+/// Builds a [Header], with the server address and port being validated by
+/// [HeaderBuilder::build].
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderBuilder {
+    version: u8,
+    source: DataSource,
+    server_address: u8,
+    server_port: u8,
+    frame_counter: u16,
+}
+
+impl HeaderBuilder {
+    /// The protocol version, being 0 by default.
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The direction of data flow.
+    pub fn source(mut self, source: DataSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// The address of the server 0..31.
+    pub fn address(mut self, server_address: u8) -> Self {
+        self.server_address = server_address;
+        self
+    }
+
+    /// The port of the server 0..31.
+    pub fn port(mut self, server_port: u8) -> Self {
+        self.server_port = server_port;
+        self
+    }
+
+    /// The frame counter.
+    pub fn counter(mut self, frame_counter: u16) -> Self {
+        self.frame_counter = frame_counter;
+        self
+    }
+
+    /// Produce the header, returning an error if the version is greater than 3,
+    /// or the server address or port is greater than 31.
+    pub fn build(self) -> Result<Header, ParseError> {
+        if self.version > 3 {
+            return Err(ParseError::UnsupportedVersion(self.version));
+        }
+        Ok(Header {
+            version: self.version,
+            source: self.source,
+            server_address: ServerAddress::new(self.server_address)?,
+            server_port: ServerPort::new(self.server_port)?,
+            frame_counter: self.frame_counter,
+        })
+    }
+}
+
 /// A data frame encapsulates client and server packets
 /// and provides for error checking.
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
@@ -47,7 +437,7 @@ pub struct DataFrame<'a> {
     // 16..=31 frame counter
     header: u32,
     // Payload data appended with a Message Authentication Code (MAC) using AES-128 CCM.
-    // This will be required to have a one byte length as the first byte.
+    // Its length is conveyed as the byte following the header, and so is at most 127.
     encrypted_payload: &'a [u8],
 }
 
@@ -55,31 +445,276 @@ pub struct DataFrame<'a> {
 /// The byte length value is not to exceed 127.
 pub const HEADER_SIZE: usize = 5;
 
+/// The greatest size of a [Header] when serialised by postcard, as it is when
+/// being the associated data of an encrypted payload. Buffers of this size can
+/// hold any header.
+pub const HEADER_POSTCARD_MAX: usize = {
+    let version = 1;
+    let source = 1; // A varint variant index, being one byte for fewer than 128.
+    let server_address = 1;
+    let server_port = 1;
+    let frame_counter = 2;
+    version + source + server_address + server_port + frame_counter
+};
+
+/// The size of the Message Authentication Code (MAC) appended to an
+/// encrypted payload.
+pub const MAC_SIZE: usize = 4;
+
+/// The length of an encrypted payload given the length of its plaintext,
+/// being inclusive of its MAC.
+pub const fn encrypted_len(plaintext_len: usize) -> usize {
+    plaintext_len + MAC_SIZE
+}
+
+/// The number of bytes that a data frame is written as given the length of
+/// its encrypted payload, as per [DataFrame::encoded_len], so that buffers can
+/// be sized before the frame exists.
+pub const fn frame_len(encrypted_payload_len: usize) -> usize {
+    HEADER_SIZE + encrypted_payload_len
+}
+
+/// Bits 0..=1 of the header, holding the protocol version.
+const VERSION_MASK: u32 = 0x03;
+
+/// The version mask previously applied, which omitted bit 0.
+const LEGACY_VERSION_MASK: u32 = 0x02;
+
+/// Bits 13..=15 of the header, reserved for future use and so must be zero.
+const RESERVED_MASK: u32 = 0x07 << 13;
+
+/// The greatest length of an encrypted payload that may be declared, being
+/// inclusive of its MAC.
+pub const MAX_ENCRYPTED_PAYLOAD_LEN: usize = 127;
+
 impl<'a> DataFrame<'a> {
+    /// Write this data frame to a buffer as it is conveyed, returning the number
+    /// of bytes written. The wire format is:
+    ///
+    /// | 0 | 1 | 2 | 3 |   4    |        ..         |
+    /// +---+---+---+---+--------+-------------------+
+    /// |     header    | length | encrypted payload |
+    ///
+    /// The header is written most significant byte first. Its bits are, from
+    /// the least significant: the version at 0..=1, the source at 2 (0 being
+    /// the client), the server address at 3..=7, the server port at 8..=12,
+    /// reserved bits at 13..=15, and the frame counter at 16..=31. The length
+    /// is that of the encrypted payload, being at most 127 bytes. This format is pinned so
+    /// that implementations on other platforms can interoperate, and is
+    /// independent of any serialisation of the data frame with serde.
+    ///
+    /// The header is therefore in network byte order, i.e. big endian, being
+    /// that of [DataFrame::to_bytes_be]. [DataFrame::to_bytes_le] is available
+    /// for interoperating with peers that write it least significant byte
+    /// first.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, ParseError> {
+        self.to_bytes_be(buf)
+    }
+
+    /// Write this data frame as per [DataFrame::to_bytes], with the header
+    /// most significant byte first.
+    pub fn to_bytes_be(&self, buf: &mut [u8]) -> Result<usize, ParseError> {
+        self.write_bytes(buf, u32::to_be_bytes)
+    }
+
+    /// Write this data frame as per [DataFrame::to_bytes], except with the
+    /// header least significant byte first. Receivers must then read it with
+    /// [DataFrame::from_bytes_le], and functions of the wire format such as
+    /// [peek_source] do not apply.
+    pub fn to_bytes_le(&self, buf: &mut [u8]) -> Result<usize, ParseError> {
+        self.write_bytes(buf, u32::to_le_bytes)
+    }
+
+    fn write_bytes(
+        &self,
+        buf: &mut [u8],
+        header_bytes: fn(u32) -> [u8; 4],
+    ) -> Result<usize, ParseError> {
+        let len = self.encrypted_payload.len();
+        if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(ParseError::PayloadTooLong(len));
+        }
+        let frame_len = HEADER_SIZE + len;
+        let got = buf.len();
+        let buf = buf.get_mut(..frame_len).ok_or(ParseError::Truncated {
+            expected: frame_len,
+            got,
+        })?;
+        buf[..4].copy_from_slice(&header_bytes(self.header));
+        buf[4] = len as u8;
+        buf[HEADER_SIZE..].copy_from_slice(self.encrypted_payload);
+        Ok(frame_len)
+    }
+
+    /// Write this data frame as per [DataFrame::to_bytes] a byte at a time,
+    /// e.g. directly to a UART or a DMA buffer, returning the number of bytes
+    /// written. Exactly [DataFrame::encoded_len] bytes are written, the header
+    /// first, and no serialisation of the frame is involved.
+    ///
+    /// An error is returned without anything being written should the payload
+    /// be too long, and as soon as the writer refuses a byte, in which case
+    /// the bytes already written are a partial frame.
+    pub fn encode_into<E>(
+        &self,
+        out: &mut impl FnMut(u8) -> Result<(), E>,
+    ) -> Result<usize, EncodeError<E>> {
+        let len = self.encrypted_payload.len();
+        if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(EncodeError::PayloadTooLong(len));
+        }
+        let header = self.header.to_be_bytes();
+        for byte in header
+            .iter()
+            .chain(&[len as u8])
+            .chain(self.encrypted_payload)
+        {
+            out(*byte).map_err(EncodeError::Write)?;
+        }
+        Ok(self.encoded_len())
+    }
+
+    /// The number of bytes that this data frame is written as by
+    /// [DataFrame::to_bytes], being its header, the payload's length and the
+    /// encrypted payload.
+    pub const fn encoded_len(&self) -> usize {
+        frame_len(self.encrypted_payload.len())
+    }
+
+    /// Read a data frame from the bytes received for it, as written by
+    /// [DataFrame::to_bytes]. The encrypted payload is sliced according to the
+    /// length that precedes it, and any bytes beyond are ignored. Where datagrams
+    /// have been coalesced, the next frame therefore begins at
+    /// [DataFrame::encoded_len].
+    ///
+    /// An error is returned if the length declared exceeds 127, or if fewer
+    /// bytes are present than it declares.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        Self::from_bytes_be(bytes)
+    }
+
+    /// Read a data frame as per [DataFrame::from_bytes], with the header most
+    /// significant byte first.
+    pub fn from_bytes_be(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        Self::read_bytes(bytes, u32::from_be_bytes)
+    }
+
+    /// Read a data frame as written by [DataFrame::to_bytes_le], with the
+    /// header least significant byte first.
+    pub fn from_bytes_le(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        Self::read_bytes(bytes, u32::from_le_bytes)
+    }
+
+    fn read_bytes(bytes: &'a [u8], header: fn([u8; 4]) -> u32) -> Result<Self, ParseError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(ParseError::Truncated {
+                expected: HEADER_SIZE,
+                got: bytes.len(),
+            });
+        }
+        let len = bytes[HEADER_SIZE - 1] as usize;
+        if len > MAX_ENCRYPTED_PAYLOAD_LEN {
+            return Err(ParseError::PayloadTooLong(len));
+        }
+        let encrypted_payload =
+            bytes
+                .get(HEADER_SIZE..HEADER_SIZE + len)
+                .ok_or(ParseError::Truncated {
+                    expected: HEADER_SIZE + len,
+                    got: bytes.len(),
+                })?;
+        Ok(Self {
+            header: header([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            encrypted_payload,
+        })
+    }
+
     /// Create a new dataframe with an encrypted payload inclusive of its MAC which
-    /// is expected to be appended at the end.
+    /// is expected to be appended at the end. The version is expected to be 0..=3,
+    /// and the encrypted payload at most 127 bytes, with these ranges only being
+    /// checked in debug builds. Otherwise, the version is truncated to its range
+    /// and the payload is refused by [DataFrame::to_bytes], so
+    /// [DataFrame::try_new] should be used where they are unchecked.
     pub fn new(header: &'a Header, encrypted_payload: &'a [u8]) -> Self {
+        debug_assert!(header.version <= 3, "version out of range");
+        debug_assert!(
+            encrypted_payload.len() <= MAX_ENCRYPTED_PAYLOAD_LEN,
+            "encrypted payload too long"
+        );
         let source = if header.source == DataSource::Client {
             0
         } else {
             1
         };
         Self {
-            header: (source << 2)
-                | (((header.server_address as u32) & 0x1F) << 3)
-                | (((header.server_port as u32) & 0x1F) << 8)
+            header: ((header.version as u32) & VERSION_MASK)
+                | (source << 2)
+                | (((header.server_address.get() as u32) & 0x1F) << 3)
+                | (((header.server_port.get() as u32) & 0x1F) << 8)
                 | (((header.frame_counter as u32) & 0xFFFF) << 16),
             encrypted_payload,
         }
     }
 
+    /// Create a new dataframe as per [DataFrame::new], returning an error if the
+    /// version is greater than 3, or the encrypted payload is longer than 127
+    /// bytes.
+    pub fn try_new(header: &'a Header, encrypted_payload: &'a [u8]) -> Result<Self, ParseError> {
+        if header.version > 3 {
+            Err(ParseError::UnsupportedVersion(header.version))
+        } else if encrypted_payload.len() > MAX_ENCRYPTED_PAYLOAD_LEN {
+            Err(ParseError::PayloadTooLong(encrypted_payload.len()))
+        } else {
+            Ok(Self::new(header, encrypted_payload))
+        }
+    }
+
+    /// Create a dataframe from the bits of its header exactly as they are
+    /// packed, being those returned by [DataFrame::raw_header]. No bits are
+    /// checked, so that frames with an unsupported version or reserved bits set
+    /// may be reconstructed e.g. for testing how they are refused. The encrypted
+    /// payload is expected to be at most 127 bytes, as per [DataFrame::new].
+    pub fn from_raw(header: u32, encrypted_payload: &'a [u8]) -> Self {
+        debug_assert!(
+            encrypted_payload.len() <= MAX_ENCRYPTED_PAYLOAD_LEN,
+            "encrypted payload too long"
+        );
+        Self {
+            header,
+            encrypted_payload,
+        }
+    }
+
+    /// The bits of the header exactly as they are packed, including any version
+    /// or reserved bits that [DataFrame::parse] would refuse, e.g. for
+    /// dissecting frames on the wire.
+    pub fn raw_header(&self) -> u32 {
+        self.header
+    }
+
     /// Parse the contents of the data frame.
-    /// If the data frame version is an incompatible value
-    /// then an error is returned. Otherwise, the header
+    /// If the data frame version is unsupported i.e. is not one of
+    /// [ProtocolVersion],
+    /// or any of the reserved bits are set, then an error
+    /// is returned. Otherwise, the header
     /// and encrypted payload (including a MAC at the end)
     /// are returned.
     pub fn parse(&self) -> Result<(Header, &'a [u8]), ParseError> {
-        let version = self.header & 0x02;
+        timing::timed(Phase::Parse, || self.parse_header(VERSION_MASK))
+    }
+
+    /// Parse the contents of the data frame as `parse` previously did, where
+    /// only bit 1 of the version was checked. Frames with a version of 1 are
+    /// therefore accepted as version 0. This permits frames generated under that
+    /// assumption to be accepted while deployments migrate.
+    #[deprecated(
+        note = "accepts frames with a version of 1, use `parse` instead. To be removed in 0.2.0."
+    )]
+    pub fn parse_legacy(&self) -> Result<(Header, &'a [u8]), ParseError> {
+        timing::timed(Phase::Parse, || self.parse_header(LEGACY_VERSION_MASK))
+    }
+
+    fn parse_header(&self, version_mask: u32) -> Result<(Header, &'a [u8]), ParseError> {
+        let version = self.header & version_mask;
         let source = match (self.header >> 2) & 0x01 {
             0 => Some(DataSource::Client),
             1 => Some(DataSource::Server),
@@ -88,23 +723,140 @@ impl<'a> DataFrame<'a> {
         let server_address = (self.header >> 3) & 0x1F;
         let server_port = (self.header >> 8) & 0x1F;
         let frame_counter = (self.header >> 16) & 0xFFFF;
+        let reserved = self.header & RESERVED_MASK;
 
-        match (version, source) {
-            (0, Some(source)) => Ok((
+        match (ProtocolVersion::new(version as _), source, reserved) {
+            (Ok(version), Some(source), 0) => Ok((
                 Header {
-                    version: 0,
+                    version: version.get(),
                     source,
-                    server_address: server_address as _,
-                    server_port: server_port as _,
+                    server_address: ServerAddress::new_unchecked(server_address as _),
+                    server_port: ServerPort::new_unchecked(server_port as _),
                     frame_counter: frame_counter as _,
                 },
                 self.encrypted_payload,
             )),
-            _ => Err(ParseError {}),
+            (Ok(_), Some(_), _) => Err(ParseError::ReservedBitsSet),
+            _ => Err(ParseError::UnsupportedVersion(version as _)),
         }
     }
 }
 
+/// Frames are logged with their header decoded into its fields, and the
+/// length of their encrypted payload.
+#[cfg(feature = "defmt")]
+impl defmt::Format for DataFrame<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        let source = if (self.header >> 2) & 0x01 == 0 {
+            DataSource::Client
+        } else {
+            DataSource::Server
+        };
+        defmt::write!(
+            f,
+            "DataFrame {{ version: {=u32}, source: {}, server_address: {=u32}, server_port: {=u32}, reserved: {=u32}, frame_counter: {=u32}, encrypted_payload_len: {=usize} }}",
+            self.header & VERSION_MASK,
+            source,
+            (self.header >> 3) & 0x1F,
+            (self.header >> 8) & 0x1F,
+            (self.header & RESERVED_MASK) >> 13,
+            (self.header >> 16) & 0xFFFF,
+            self.encrypted_payload.len(),
+        )
+    }
+}
+
+/// Read a data frame from a datagram as per [DataFrame::from_bytes], with its
+/// header also being checked as per [DataFrame::parse]. An error is returned if
+/// the datagram is too short for the frame that it declares, or if the header
+/// has an unsupported version or reserved bits set.
+///
+/// A datagram can be turned into a frame and back again:
+///
+/// ```
+/// use flip_flop_data::{DataFrame, DataFrameBytes};
+///
+/// let datagram = [0x00, 0x01, 0x1F, 0x0C, 2, 0xAB, 0xCD];
+/// let frame = DataFrame::try_from(&datagram[..]).unwrap();
+/// let (header, encrypted_payload) = frame.parse().unwrap();
+/// assert_eq!(header.server_address.get(), 1);
+/// assert_eq!(encrypted_payload, [0xAB, 0xCD]);
+///
+/// let bytes = DataFrameBytes::from(frame).collect::<Vec<u8>>();
+/// assert_eq!(bytes, datagram);
+/// ```
+impl<'a> TryFrom<&'a [u8]> for DataFrame<'a> {
+    type Error = ParseError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        let frame = Self::from_bytes(bytes)?;
+        frame.parse()?;
+        Ok(frame)
+    }
+}
+
+/// An iterator over the bytes of a data frame as it is conveyed, being those
+/// written by [DataFrame::to_bytes]. The encrypted payload is expected to be at
+/// most 127 bytes, as it is for frames read from bytes or created by
+/// [DataFrame::try_new].
+#[derive(Clone, Debug)]
+pub struct DataFrameBytes<'a> {
+    header: core::array::IntoIter<u8, HEADER_SIZE>,
+    encrypted_payload: core::slice::Iter<'a, u8>,
+}
+
+impl<'a> From<DataFrame<'a>> for DataFrameBytes<'a> {
+    fn from(frame: DataFrame<'a>) -> Self {
+        let [h0, h1, h2, h3] = frame.header.to_be_bytes();
+        let len = frame.encrypted_payload.len() as u8;
+        Self {
+            header: [h0, h1, h2, h3, len].into_iter(),
+            encrypted_payload: frame.encrypted_payload.iter(),
+        }
+    }
+}
+
+impl Iterator for DataFrameBytes<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.header
+            .next()
+            .or_else(|| self.encrypted_payload.next().copied())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.header.len() + self.encrypted_payload.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for DataFrameBytes<'_> {}
+
+/// Read the source of a data frame's bytes, as written by [DataFrame::to_bytes],
+/// without parsing or opening it. The header's least significant byte is its
+/// fourth, which holds the source at bit 2. Returns `None` if there are too few
+/// bytes to be a data frame.
+pub fn peek_source(bytes: &[u8]) -> Option<DataSource> {
+    if bytes.len() < HEADER_SIZE {
+        return None;
+    }
+    if bytes[3] & 0x04 == 0 {
+        Some(DataSource::Client)
+    } else {
+        Some(DataSource::Server)
+    }
+}
+
+/// Decide whether a data frame's bytes are worth parsing and opening by a
+/// receiver having a given role. Frames sourced by the same role, as may be
+/// received on a shared medium, and frames too short to be valid are not.
+/// This is the cheapest check that can be made of a frame, and should be made
+/// before any cryptographic work.
+pub fn should_process(bytes: &[u8], my_role: DataSource) -> bool {
+    matches!(peek_source(bytes), Some(source) if source != my_role)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,6 +870,7 @@ mod tests {
     use heapless::Vec;
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn test_command_serialisation() {
         type AesCcm = Ccm<Aes128, U4, U8>;
 
@@ -127,20 +880,19 @@ mod tests {
         let header = Header {
             version: 0,
             source: DataSource::Server,
-            server_address: 31,
-            server_port: 2,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
             frame_counter: 1,
         };
 
         let nonce = GenericArray::from_slice(&[0; 8]); // Should be some random value exchanged and concatenated with the frame counter, not zero!
 
-        let mut associated_data = [0; 6];
-        let _ = postcard::to_slice(&header, &mut associated_data).unwrap();
+        let associated_data = header.associated_data();
 
         let payload = b"some data";
         let mut encrypted_payload: Vec<u8, 128> = Vec::new();
-        let _ = encrypted_payload.extend_from_slice(payload).unwrap();
-        let _ = cipher
+        encrypted_payload.extend_from_slice(payload).unwrap();
+        cipher
             .encrypt_in_place(nonce, &associated_data, &mut encrypted_payload)
             .unwrap();
 
@@ -156,6 +908,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn test_command_deserialisation() {
         type AesCcm = Ccm<Aes128, U4, U8>;
 
@@ -173,8 +926,8 @@ mod tests {
         let expected_header = Header {
             version: 0,
             source: DataSource::Server,
-            server_address: 31,
-            server_port: 2,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
             frame_counter: 1,
         };
 
@@ -182,14 +935,13 @@ mod tests {
 
         let nonce = GenericArray::from_slice(&[0; 8]); // Should be some random value exchanged and concatenated with the frame counter, not zero!
 
-        let mut associated_data = [0; 6];
-        let _ = postcard::to_slice(&header, &mut associated_data).unwrap();
+        let associated_data = header.associated_data();
 
         let mut decrypted_payload: Vec<u8, 128> = Vec::new();
-        let _ = decrypted_payload
+        decrypted_payload
             .extend_from_slice(encrypted_payload)
             .unwrap();
-        let _ = cipher
+        cipher
             .decrypt_in_place(nonce, &associated_data, &mut decrypted_payload)
             .unwrap();
 
@@ -197,4 +949,676 @@ mod tests {
 
         assert_eq!(decrypted_payload, expected_payload);
     }
+
+    #[test]
+    fn test_associated_data() {
+        for header in [
+            Header {
+                version: 0,
+                source: DataSource::Server,
+                server_address: ServerAddress::new_unchecked(31),
+                server_port: ServerPort::new_unchecked(2),
+                frame_counter: 0xABCD,
+            },
+            Header {
+                version: 0,
+                source: DataSource::Client,
+                server_address: ServerAddress::new_unchecked(5),
+                server_port: ServerPort::new_unchecked(17),
+                frame_counter: 1,
+            },
+        ] {
+            let mut buf = [0; HEADER_POSTCARD_MAX];
+            assert_eq!(
+                &header.associated_data()[..],
+                postcard::to_slice(&header, &mut buf).unwrap()
+            );
+
+            // The receiver's header, as parsed from the frame conveyed, has the
+            // same associated data as the sender's.
+            let mut frame_buf = [0; HEADER_SIZE];
+            let len = DataFrame::new(&header, &[])
+                .to_bytes(&mut frame_buf)
+                .unwrap();
+            let (parsed, _) = DataFrame::from_bytes(&frame_buf[..len])
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(parsed.associated_data(), header.associated_data());
+        }
+    }
+
+    #[test]
+    fn test_nonce() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 0x0102,
+        };
+        let salt = [9, 8, 7, 6, 5, 4];
+        assert_eq!(header.nonce(&salt), [9, 8, 7, 6, 5, 4, 1, 2]);
+
+        let next = Header {
+            frame_counter: header.frame_counter + 1,
+            ..header
+        };
+        assert_ne!(next.nonce(&salt), header.nonce(&salt));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let frame = |version| DataFrame {
+            header: version,
+            encrypted_payload: &[],
+        };
+
+        assert_eq!(frame(0b00).parse().unwrap().0.version, 0);
+        assert_eq!(frame(0b01).parse().unwrap().0.version, 1);
+        assert_eq!(frame(0b10).parse(), Err(ParseError::UnsupportedVersion(2)));
+        assert_eq!(frame(0b11).parse(), Err(ParseError::UnsupportedVersion(3)));
+    }
+
+    #[test]
+    fn test_byte_order() {
+        // Version 1, server sourced, address 31, port 2 and a frame counter of
+        // 0xABCD, being a header of 0xABCD_02FD.
+        let header = Header {
+            version: 1,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 0xABCD,
+        };
+        let frame = DataFrame::new(&header, &[0x11, 0x22]);
+        assert_eq!(frame.raw_header(), 0xABCD_02FD);
+
+        let mut be = [0; 7];
+        assert_eq!(frame.to_bytes_be(&mut be), Ok(7));
+        assert_eq!(be, [0xAB, 0xCD, 0x02, 0xFD, 2, 0x11, 0x22]);
+        let mut default = [0; 7];
+        assert_eq!(frame.to_bytes(&mut default), Ok(7));
+        assert_eq!(default, be);
+
+        let mut le = [0; 7];
+        assert_eq!(frame.to_bytes_le(&mut le), Ok(7));
+        assert_eq!(le, [0xFD, 0x02, 0xCD, 0xAB, 2, 0x11, 0x22]);
+
+        assert_eq!(DataFrame::from_bytes(&be).as_ref(), Ok(&frame));
+        assert_eq!(DataFrame::from_bytes_be(&be).as_ref(), Ok(&frame));
+        assert_eq!(DataFrame::from_bytes_le(&le).as_ref(), Ok(&frame));
+        assert_ne!(DataFrame::from_bytes_le(&be).as_ref(), Ok(&frame));
+    }
+
+    #[test]
+    fn test_payload_capacity() {
+        assert_eq!(Header::payload_capacity(32, MAC_SIZE), 23);
+        assert_eq!(Header::payload_capacity(32, 8), 19);
+        assert_eq!(Header::payload_capacity(32, 0), 27);
+        assert_eq!(
+            Header::payload_capacity(frame_len(encrypted_len(10)), MAC_SIZE),
+            10
+        );
+
+        // The encrypted payload is limited to 127 bytes, however large the
+        // datagram.
+        assert_eq!(Header::payload_capacity(frame_len(127), MAC_SIZE), 123);
+        assert_eq!(Header::payload_capacity(1500, MAC_SIZE), 123);
+        assert_eq!(Header::payload_capacity(1500, 16), 111);
+
+        // Nothing fits where the header, or the header and tag, do not.
+        assert_eq!(Header::payload_capacity(0, MAC_SIZE), 0);
+        assert_eq!(Header::payload_capacity(HEADER_SIZE - 1, 0), 0);
+        assert_eq!(Header::payload_capacity(HEADER_SIZE, 0), 0);
+        assert_eq!(
+            Header::payload_capacity(HEADER_SIZE + MAC_SIZE, MAC_SIZE),
+            0
+        );
+        assert_eq!(Header::payload_capacity(HEADER_SIZE + 2, MAC_SIZE), 0);
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let header = Header {
+            version: 1,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 0xABCD,
+        };
+        for payload in [&[][..], &[0x11, 0x22], &[0xFF; MAX_ENCRYPTED_PAYLOAD_LEN]] {
+            let frame = DataFrame::new(&header, payload);
+            assert_eq!(frame.encoded_len(), frame_len(payload.len()));
+
+            // The bytes written are exactly those of to_bytes.
+            let mut written = Vec::<u8, { frame_len(MAX_ENCRYPTED_PAYLOAD_LEN) }>::new();
+            let len = frame.encode_into(&mut |b| written.push(b)).unwrap();
+            assert_eq!(len, frame.encoded_len());
+            assert_eq!(written.len(), len);
+            let mut buf = [0; frame_len(MAX_ENCRYPTED_PAYLOAD_LEN)];
+            assert_eq!(frame.to_bytes(&mut buf), Ok(len));
+            assert_eq!(&written[..], &buf[..len]);
+        }
+
+        // A writer refusing a byte ends the encoding with its error.
+        let frame = DataFrame::new(&header, &[0x11, 0x22]);
+        let mut written = Vec::<u8, 6>::new();
+        assert_eq!(
+            frame.encode_into(&mut |b| written.push(b)),
+            Err(EncodeError::Write(0x22))
+        );
+        assert_eq!(&written[..], &[0xAB, 0xCD, 0x02, 0xFD, 2, 0x11]);
+
+        let payload = [0; MAX_ENCRYPTED_PAYLOAD_LEN + 1];
+        let frame = DataFrame {
+            header: 0,
+            encrypted_payload: &payload,
+        };
+        let mut count = 0;
+        assert_eq!(
+            frame.encode_into(&mut |_| -> Result<(), ()> {
+                count += 1;
+                Ok(())
+            }),
+            Err(EncodeError::PayloadTooLong(MAX_ENCRYPTED_PAYLOAD_LEN + 1))
+        );
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_raw_header_round_trip() {
+        // Version 1, server sourced, address 31, port 2, reserved bits 0b101 and
+        // a frame counter of 0xABCD.
+        let raw = 0xABCD_A2FD;
+        let frame = DataFrame::from_raw(raw, &[1, 2, 3]);
+        assert_eq!(frame.raw_header(), raw);
+        assert_eq!(frame.parse(), Err(ParseError::ReservedBitsSet));
+
+        let mut buf = [0; HEADER_SIZE + 3];
+        let len = frame.to_bytes(&mut buf).unwrap();
+        let read = DataFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(read.raw_header(), raw);
+        assert_eq!(read, frame);
+
+        // Clearing the reserved bits yields the frame that the header packs to.
+        let frame = DataFrame::from_raw(raw & !(0x07 << 13), &[1, 2, 3]);
+        let (header, payload) = frame.parse().unwrap();
+        assert_eq!(
+            (
+                header.version,
+                header.source,
+                header.server_address.get(),
+                header.server_port.get(),
+                header.frame_counter,
+            ),
+            (1, DataSource::Server, 31, 2, 0xABCD)
+        );
+        assert_eq!(payload, &[1, 2, 3]);
+        assert_eq!(DataFrame::new(&header, payload), frame);
+    }
+
+    #[test]
+    fn test_parse_reserved_bits() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(31),
+            frame_counter: 0xFFFF,
+        };
+        let frame = DataFrame::new(&header, &[]);
+        assert!(frame.parse().is_ok());
+
+        for bit in 13..=15 {
+            let frame = DataFrame {
+                header: frame.header | (1 << bit),
+                encrypted_payload: &[],
+            };
+            assert_eq!(
+                frame.parse(),
+                Err(ParseError::ReservedBitsSet),
+                "bit {}",
+                bit
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_new() {
+        let header = Header {
+            version: 3,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(31),
+            frame_counter: 0xFFFF,
+        };
+        assert_eq!(
+            DataFrame::try_new(&header, &[]),
+            Ok(DataFrame::new(&header, &[]))
+        );
+
+        assert_eq!(
+            DataFrame::try_new(
+                &Header {
+                    version: 4,
+                    ..header
+                },
+                &[]
+            ),
+            Err(ParseError::UnsupportedVersion(4))
+        );
+        assert_eq!(
+            DataFrame::try_new(&header, &[0; 128]),
+            Err(ParseError::PayloadTooLong(128))
+        );
+    }
+
+    #[test]
+    fn test_server_address_and_port() {
+        assert_eq!(ServerAddress::new(31).map(ServerAddress::get), Ok(31));
+        assert_eq!(ServerAddress::new(32), Err(ParseError::AddressOutOfRange));
+        assert_eq!(ServerPort::try_from(0).map(u8::from), Ok(0));
+        assert_eq!(
+            ServerPort::try_from(255),
+            Err(ParseError::AddressOutOfRange)
+        );
+
+        // Conveyed by serde as a u8, with out of range values being rejected.
+        let mut buf = [0; 1];
+        let serialised = postcard::to_slice(&ServerPort::new_unchecked(17), &mut buf).unwrap();
+        assert_eq!(serialised, [17]);
+        assert_eq!(
+            postcard::from_bytes::<ServerPort>(serialised),
+            Ok(ServerPort::new_unchecked(17))
+        );
+        assert!(postcard::from_bytes::<ServerAddress>(&[32]).is_err());
+    }
+
+    #[test]
+    fn test_is_broadcast() {
+        let header = |server_address| Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(server_address),
+            server_port: ServerPort::new_unchecked(0),
+            frame_counter: 0,
+        };
+        assert_eq!(BROADCAST_ADDRESS.get(), 31);
+        assert!(header(31).is_broadcast());
+        assert!(!header(30).is_broadcast());
+        assert!(!header(0).is_broadcast());
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        assert_eq!(ProtocolVersion::new(0), Ok(ProtocolVersion::V0));
+        assert_eq!(ProtocolVersion::new(1), Ok(ProtocolVersion::V1));
+        assert_eq!(
+            ProtocolVersion::new(2),
+            Err(ParseError::UnsupportedVersion(2))
+        );
+        assert_eq!(ProtocolVersion::LATEST.get(), 1);
+        assert!(ProtocolVersion::V0 < ProtocolVersion::V1);
+
+        let mut buf = [0; 1];
+        assert_eq!(
+            postcard::to_slice(&ProtocolVersion::V1, &mut buf).unwrap(),
+            [1]
+        );
+        assert_eq!(
+            postcard::from_bytes::<ProtocolVersion>(&[0]),
+            Ok(ProtocolVersion::V0)
+        );
+        assert!(postcard::from_bytes::<ProtocolVersion>(&[2]).is_err());
+    }
+
+    #[test]
+    fn test_parse_version_1() {
+        let header = Header {
+            version: 1,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(5),
+            server_port: ServerPort::new_unchecked(17),
+            frame_counter: 0xABCD,
+        };
+        assert_eq!(header.protocol_version(), Ok(ProtocolVersion::V1));
+
+        // A version 1 frame differs from a version 0 one in its version only.
+        let v1 = DataFrame::new(&header, b"some data");
+        let v0_header = Header {
+            version: 0,
+            ..header
+        };
+        let v0 = DataFrame::new(&v0_header, b"some data");
+        assert_eq!(v1.header ^ v0.header, 0b01);
+        assert_eq!(v1.parse(), Ok((header, b"some data".as_slice())));
+    }
+
+    #[test]
+    fn test_opposite_source() {
+        assert_eq!(DataSource::Client.opposite(), DataSource::Server);
+        assert_eq!(DataSource::Server.opposite(), DataSource::Client);
+        assert_eq!(DataSource::Client.opposite().opposite(), DataSource::Client);
+    }
+
+    #[test]
+    fn test_header_builder() {
+        let header = Header::builder()
+            .source(DataSource::Server)
+            .address(31)
+            .port(2)
+            .counter(1)
+            .build();
+        assert_eq!(
+            header,
+            Ok(Header {
+                version: 0,
+                source: DataSource::Server,
+                server_address: ServerAddress::new_unchecked(31),
+                server_port: ServerPort::new_unchecked(2),
+                frame_counter: 1,
+            })
+        );
+
+        let header = Header::builder().build().unwrap();
+        assert_eq!(header.version, 0);
+        assert_eq!(header.source, DataSource::Client);
+        assert_eq!(Header::builder().version(3).build().unwrap().version, 3);
+
+        assert_eq!(
+            Header::builder().address(32).build(),
+            Err(ParseError::AddressOutOfRange)
+        );
+        assert_eq!(
+            Header::builder().port(32).build(),
+            Err(ParseError::AddressOutOfRange)
+        );
+        assert_eq!(
+            Header::builder().version(4).build(),
+            Err(ParseError::UnsupportedVersion(4))
+        );
+    }
+
+    #[test]
+    fn test_next_counter_wraps() {
+        let mut header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 0,
+        }
+        .with_counter(0xFFFE);
+        assert_eq!(header.frame_counter, 0xFFFE);
+        assert_eq!(header.next_counter(), 0xFFFF);
+        assert_eq!(header.next_counter(), 0x0000);
+        assert_eq!(header.next_counter(), 0x0001);
+        assert_eq!(header.frame_counter, 0x0001);
+        assert_eq!(header.server_port, ServerPort::new_unchecked(2));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "server address out of range")]
+    fn test_new_unchecked_checks_range() {
+        let _ = ServerAddress::new_unchecked(32);
+    }
+
+    #[test]
+    fn test_new_writes_version() {
+        for version in 0..=3 {
+            let header = Header {
+                version,
+                source: DataSource::Client,
+                server_address: ServerAddress::new_unchecked(1),
+                server_port: ServerPort::new_unchecked(2),
+                frame_counter: 3,
+            };
+            let frame = DataFrame::new(&header, &[]);
+            assert_eq!(frame.header & VERSION_MASK, version as u32);
+            assert_eq!(frame.parse().is_ok(), version <= 1, "version {}", version);
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_parse_legacy_version() {
+        let frame = |version| DataFrame {
+            header: version,
+            encrypted_payload: &[],
+        };
+
+        assert!(frame(0b00).parse_legacy().is_ok());
+        assert_eq!(frame(0b01).parse_legacy().unwrap().0.version, 0);
+        assert_eq!(
+            frame(0b10).parse_legacy(),
+            Err(ParseError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            frame(0b11).parse_legacy(),
+            Err(ParseError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn test_should_process() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 1,
+        };
+        let encrypted_payload = [112, 28, 128, 64, 171, 5, 37, 219, 171, 39, 144, 217, 94];
+        let mut buf = [0; 32];
+        let len = DataFrame::new(&header, &encrypted_payload)
+            .to_bytes(&mut buf)
+            .unwrap();
+        let bytes = &buf[..len];
+
+        assert_eq!(peek_source(bytes), Some(DataSource::Server));
+
+        // Peer sourced
+        assert!(should_process(bytes, DataSource::Client));
+
+        // Self sourced
+        assert!(!should_process(bytes, DataSource::Server));
+
+        // Truncated
+        assert_eq!(peek_source(&bytes[..HEADER_SIZE - 1]), None);
+        assert!(!should_process(
+            &bytes[..HEADER_SIZE - 1],
+            DataSource::Client
+        ));
+        assert!(!should_process(&[], DataSource::Client));
+
+        let header = Header {
+            source: DataSource::Client,
+            ..header
+        };
+        let len = DataFrame::new(&header, &encrypted_payload)
+            .to_bytes(&mut buf)
+            .unwrap();
+        let bytes = &buf[..len];
+        assert_eq!(peek_source(bytes), Some(DataSource::Client));
+        assert!(should_process(bytes, DataSource::Server));
+    }
+
+    #[test]
+    #[allow(clippy::unusual_byte_groupings)]
+    fn test_to_and_from_bytes() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 1,
+        };
+        let encrypted_payload = [112, 28, 128, 64, 171, 5, 37, 219, 171, 39, 144, 217, 94];
+        let frame = DataFrame::new(&header, &encrypted_payload);
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(
+            &buf[..len],
+            [
+                // Header, most significant byte first
+                0x00, 0x01, 0b000_000_10, 0b11111_1_00,
+                // Length
+                13,
+                // Encrypted payload
+                112, 28, 128, 64, 171, 5, 37, 219, 171, 39, 144, 217, 94,
+            ]
+        );
+        assert_eq!(DataFrame::from_bytes(&buf[..len]), Ok(frame));
+
+        assert_eq!(
+            DataFrame::new(&header, &encrypted_payload).to_bytes(&mut buf[..17]),
+            Err(ParseError::Truncated {
+                expected: 18,
+                got: 17
+            })
+        );
+        let frame = DataFrame {
+            header: 0,
+            encrypted_payload: &[0; 128],
+        };
+        assert_eq!(
+            frame.to_bytes(&mut [0; 256]),
+            Err(ParseError::PayloadTooLong(128))
+        );
+    }
+
+    #[test]
+    fn test_from_coalesced_bytes() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 3,
+        };
+        let first = DataFrame::new(&header, &[1, 2, 3, 4, 5]);
+        let second = DataFrame::new(&header, &[6, 7, 8, 9]);
+        let mut buf = [0; 32];
+        let len = first.to_bytes(&mut buf).unwrap();
+        let len = len + second.to_bytes(&mut buf[len..]).unwrap();
+        let bytes = &buf[..len];
+
+        let frame = DataFrame::from_bytes(bytes).unwrap();
+        assert_eq!(frame, first);
+        assert_eq!(frame.encoded_len(), HEADER_SIZE + 5);
+        let frame = DataFrame::from_bytes(&bytes[frame.encoded_len()..]).unwrap();
+        assert_eq!(frame, second);
+        assert_eq!(frame.encoded_len(), HEADER_SIZE + 4);
+    }
+
+    #[test]
+    fn test_try_from_and_into_bytes() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(31),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 1,
+        };
+        let frame = DataFrame::new(&header, &[1, 2, 3]);
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+
+        let bytes = DataFrameBytes::from(DataFrame::try_from(&buf[..len]).unwrap());
+        assert_eq!(bytes.len(), len);
+        assert!(bytes.eq(buf[..len].iter().copied()));
+
+        assert_eq!(
+            DataFrame::try_from(&buf[..len - 1]),
+            Err(ParseError::Truncated {
+                expected: len,
+                got: len - 1
+            })
+        );
+        buf[3] |= 0b11;
+        assert_eq!(
+            DataFrame::try_from(&buf[..len]),
+            Err(ParseError::UnsupportedVersion(3))
+        );
+        buf[3] &= !0b11;
+        buf[2] |= 0x80;
+        assert_eq!(
+            DataFrame::try_from(&buf[..len]),
+            Err(ParseError::ReservedBitsSet)
+        );
+    }
+
+    #[test]
+    fn test_from_truncated_bytes() {
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 3,
+        };
+        let encrypted_payload = [0; 13];
+        let frame = DataFrame::new(&header, &encrypted_payload);
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+        let bytes = &buf[..len];
+
+        // The length byte promises more bytes than are present.
+        assert_eq!(
+            DataFrame::from_bytes(&bytes[..10]),
+            Err(ParseError::Truncated {
+                expected: 18,
+                got: 10
+            })
+        );
+        assert_eq!(
+            DataFrame::from_bytes(&bytes[..3]),
+            Err(ParseError::Truncated {
+                expected: HEADER_SIZE,
+                got: 3
+            })
+        );
+        assert_eq!(
+            DataFrame::from_bytes(&[0, 0, 0, 0, 128]),
+            Err(ParseError::PayloadTooLong(128))
+        );
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        assert_eq!(
+            ParseError::UnsupportedVersion(2).to_string(),
+            "unsupported protocol version 2"
+        );
+        assert_eq!(
+            ParseError::Truncated {
+                expected: 18,
+                got: 10
+            }
+            .to_string(),
+            "truncated: expected 18 bytes, got 10"
+        );
+        let error: Box<dyn std::error::Error> = Box::new(ParseError::ReservedBitsSet);
+        assert_eq!(error.to_string(), "reserved header bits are set");
+    }
+
+    #[test]
+    fn test_header_postcard_max() {
+        let header = Header {
+            version: u8::MAX,
+            source: DataSource::Server,
+            server_address: ServerAddress::new_unchecked(ServerAddress::MAX),
+            server_port: ServerPort::new_unchecked(ServerPort::MAX),
+            frame_counter: u16::MAX,
+        };
+        let mut buf = [0; HEADER_POSTCARD_MAX];
+        assert_eq!(
+            postcard::to_slice(&header, &mut buf).unwrap().len(),
+            HEADER_POSTCARD_MAX
+        );
+    }
 }
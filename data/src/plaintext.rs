@@ -0,0 +1,123 @@
+//! Data frames conveying their payload as plaintext, for bringing up and
+//! debugging a node before encryption is wired in. Such payloads are neither
+//! encrypted nor authenticated, and so this module is only present with the
+//! `insecure-plaintext` feature, with its constructors deprecated so that
+//! their use warns.
+//!
+//! A plaintext frame sets the first of the header's reserved bits, bit 13, so
+//! that it is distinguishable from an encrypted one. Receivers that do not
+//! expect plaintext therefore refuse it with [ParseError::ReservedBitsSet],
+//! as do [DataFrame::parse] and `DataFrame::open`.
+
+use crate::{DataFrame, Header, ParseError, MAX_ENCRYPTED_PAYLOAD_LEN};
+
+/// Bit 13 of the header, being set for frames conveying plaintext.
+const PLAINTEXT_FLAG: u32 = 0x01 << 13;
+
+/// Whether a data frame conveys its payload encrypted or as plaintext.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameMode {
+    /// The payload is encrypted and has a MAC appended.
+    Encrypted,
+    /// The payload is plaintext, having no MAC.
+    Plaintext,
+}
+
+impl<'a> DataFrame<'a> {
+    /// Create a data frame conveying a plaintext payload of at most 127 bytes,
+    /// which is only checked in debug builds as per [DataFrame::new].
+    #[deprecated(note = "payloads are neither encrypted nor authenticated, for bring-up only")]
+    pub fn new_plaintext(header: &'a Header, plaintext: &'a [u8]) -> Self {
+        debug_assert!(
+            plaintext.len() <= MAX_ENCRYPTED_PAYLOAD_LEN,
+            "plaintext payload too long"
+        );
+        let frame = Self::new(header, plaintext);
+        Self {
+            header: frame.header | PLAINTEXT_FLAG,
+            ..frame
+        }
+    }
+
+    /// Whether this frame conveys its payload encrypted or as plaintext.
+    pub fn mode(&self) -> FrameMode {
+        if self.header & PLAINTEXT_FLAG == 0 {
+            FrameMode::Encrypted
+        } else {
+            FrameMode::Plaintext
+        }
+    }
+
+    /// Parse a frame created by [DataFrame::new_plaintext], returning its header
+    /// and plaintext payload. Errors are returned as per [DataFrame::parse],
+    /// including [ParseError::ReservedBitsSet] for a frame that is encrypted.
+    #[deprecated(note = "payloads are neither encrypted nor authenticated, for bring-up only")]
+    pub fn parse_plaintext(&self) -> Result<(Header, &'a [u8]), ParseError> {
+        if self.mode() != FrameMode::Plaintext {
+            return Err(ParseError::ReservedBitsSet);
+        }
+        Self {
+            header: self.header & !PLAINTEXT_FLAG,
+            encrypted_payload: self.encrypted_payload,
+        }
+        .parse()
+    }
+}
+
+#[cfg(test)]
+#[allow(deprecated)]
+mod tests {
+    use super::*;
+    use crate::{DataSource, ServerAddress, ServerPort};
+
+    fn header() -> Header {
+        Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(3),
+            server_port: ServerPort::new_unchecked(4),
+            frame_counter: 5,
+        }
+    }
+
+    #[test]
+    fn test_plaintext_round_trip() {
+        let header = header();
+        let frame = DataFrame::new_plaintext(&header, b"some data");
+        assert_eq!(frame.mode(), FrameMode::Plaintext);
+
+        let mut buf = [0; 32];
+        let len = frame.to_bytes(&mut buf).unwrap();
+        assert_eq!(&buf[crate::HEADER_SIZE..len], b"some data");
+
+        let frame = DataFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(
+            frame.parse_plaintext(),
+            Ok((header, b"some data".as_slice()))
+        );
+    }
+
+    #[test]
+    fn test_plaintext_is_distinguishable() {
+        let header = header();
+        let plaintext = DataFrame::new_plaintext(&header, b"some data");
+        let encrypted = DataFrame::new(&header, b"some data");
+        assert_eq!(encrypted.mode(), FrameMode::Encrypted);
+        assert_ne!(plaintext, encrypted);
+
+        // Each is refused when parsed as the other.
+        assert_eq!(plaintext.parse(), Err(ParseError::ReservedBitsSet));
+        assert_eq!(
+            encrypted.parse_plaintext(),
+            Err(ParseError::ReservedBitsSet)
+        );
+
+        // Other reserved bits remain refused.
+        let frame = DataFrame {
+            header: plaintext.header | (0x01 << 14),
+            encrypted_payload: &[],
+        };
+        assert_eq!(frame.parse_plaintext(), Err(ParseError::ReservedBitsSet));
+    }
+}
@@ -0,0 +1,115 @@
+//! Utilities for testing interoperability with other implementations of
+//! this protocol, available with the `test-util` feature.
+
+use crate::{
+    crypto::{Opener, Sealer},
+    DataFrame, Header, MAC_SIZE, MAX_ENCRYPTED_PAYLOAD_LEN,
+};
+
+/// Check a known test vector for a frame, panicking if it does not verify.
+///
+/// The plaintext is sealed as per [Sealer::seal_next], being AES-128 CCM with
+/// a MAC of [MAC_SIZE] bytes, the header as associated data, and a nonce of
+/// [Header::nonce] with the `salt`. The resulting encrypted payload, inclusive
+/// of its MAC, must equal `expected_bytes`. Separately, a frame carrying
+/// `expected_bytes` must parse to the header and open to the plaintext, as per
+/// [Opener::open].
+pub fn verify_known_frame(
+    key: &[u8; 16],
+    salt: &[u8; 6],
+    header: &Header,
+    plaintext: &[u8],
+    expected_bytes: &[u8],
+) {
+    let nonce = header.nonce(salt);
+
+    let mut buf = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+    assert!(
+        plaintext.len() + MAC_SIZE <= MAX_ENCRYPTED_PAYLOAD_LEN,
+        "the plaintext exceeds the maximum payload length"
+    );
+    let sealed_len = Sealer::new(key)
+        .seal_next(header, &nonce, plaintext, &mut buf)
+        .expect("the plaintext should seal");
+    assert_eq!(
+        &buf[..sealed_len],
+        expected_bytes,
+        "the sealed payload differs from the expected bytes"
+    );
+
+    let frame = DataFrame::new(header, expected_bytes);
+    let (parsed_header, encrypted_payload) = frame.parse().expect("the frame should parse");
+    assert_eq!(&parsed_header, header, "the parsed header differs");
+
+    assert!(
+        encrypted_payload.len() >= MAC_SIZE,
+        "the expected bytes are too short to hold a MAC"
+    );
+    let mut buf = [0; MAX_ENCRYPTED_PAYLOAD_LEN];
+    let opened_len = Opener::new(key)
+        .open(&parsed_header, &nonce, encrypted_payload, &mut buf)
+        .expect("the expected bytes should open");
+    assert_eq!(
+        &buf[..opened_len],
+        plaintext,
+        "the opened payload differs from the plaintext"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataSource, ServerAddress, ServerPort};
+
+    #[test]
+    fn test_server_vector() {
+        verify_known_frame(
+            b"0123456789ABCDEF",
+            &[0; 6],
+            &Header {
+                version: 0,
+                source: DataSource::Server,
+                server_address: ServerAddress::new_unchecked(31),
+                server_port: ServerPort::new_unchecked(2),
+                frame_counter: 1,
+            },
+            b"some data",
+            &[179, 90, 236, 66, 139, 68, 20, 184, 22, 12, 6, 138, 2],
+        );
+    }
+
+    #[test]
+    fn test_client_vector() {
+        verify_known_frame(
+            b"FEDCBA9876543210",
+            &[1, 2, 3, 4, 5, 6],
+            &Header {
+                version: 0,
+                source: DataSource::Client,
+                server_address: ServerAddress::new_unchecked(5),
+                server_port: ServerPort::new_unchecked(17),
+                frame_counter: 0xABCD,
+            },
+            &[0, 0, 0, 0, 2],
+            &[16, 13, 210, 99, 81, 40, 203, 0, 230],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the sealed payload differs from the expected bytes")]
+    fn test_mismatched_vector() {
+        verify_known_frame(
+            b"0123456789ABCDEF",
+            &[0; 6],
+            &Header {
+                version: 0,
+                source: DataSource::Server,
+                server_address: ServerAddress::new_unchecked(31),
+                server_port: ServerPort::new_unchecked(2),
+                frame_counter: 2,
+            },
+            b"some data",
+            &[179, 90, 236, 66, 139, 68, 20, 184, 22, 12, 6, 138, 2],
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Utilities for property testing the packing of headers into data frames,
+//! available with the `testing` feature. They are independent of any property
+//! testing framework, so that they can be driven by `proptest`, `quickcheck`
+//! or a plain loop alike e.g. with proptest:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn round_trips(bits: u64, payload in vec(any::<u8>(), 0..=127)) {
+//!         check_round_trip(&arbitrary_header(bits), &payload);
+//!     }
+//! }
+//! ```
+
+use crate::{
+    DataFrame, DataSource, Header, ParseError, ServerAddress, ServerPort, HEADER_SIZE,
+    RESERVED_MASK,
+};
+
+/// A header derived from arbitrary bits, with each field being within its
+/// range: the version 0..=3 from bits 0..=1, the source from bit 2, the server
+/// address 0..=31 from bits 3..=7, the server port 0..=31 from bits 8..=12, and
+/// the frame counter from bits 16..=31. Every valid header is derived from some
+/// bits, and uniformly so given uniformly random bits.
+pub fn arbitrary_header(bits: u64) -> Header {
+    Header {
+        version: (bits & 0x03) as u8,
+        source: if bits & 0x04 == 0 {
+            DataSource::Client
+        } else {
+            DataSource::Server
+        },
+        server_address: ServerAddress::new_unchecked(((bits >> 3) & 0x1F) as u8),
+        server_port: ServerPort::new_unchecked(((bits >> 8) & 0x1F) as u8),
+        frame_counter: (bits >> 16) as u16,
+    }
+}
+
+/// Check that a data frame conveying a header and payload of at most 127 bytes
+/// is read back as it was written, panicking otherwise. The frame is written
+/// with [DataFrame::to_bytes], read with [DataFrame::from_bytes], and must then
+/// parse to the same header and payload, with a version other than those of
+/// [crate::ProtocolVersion] being refused as unsupported. The fields packed
+/// into the header must also leave its reserved bits clear, so that no field
+/// bleeds into another's bits.
+pub fn check_round_trip(header: &Header, payload: &[u8]) {
+    let frame = DataFrame::new(header, payload);
+    assert_eq!(
+        frame.header & RESERVED_MASK,
+        0,
+        "the header {:?} sets reserved bits",
+        header
+    );
+
+    let mut buf = [0; HEADER_SIZE + 127];
+    let len = frame
+        .to_bytes(&mut buf)
+        .expect("the frame should be written");
+    let read = DataFrame::from_bytes(&buf[..len]).expect("the frame should be read");
+    assert_eq!(read, frame, "the frame read differs from that written");
+
+    match read.parse() {
+        Ok((parsed, parsed_payload)) => {
+            assert_eq!(&parsed, header, "the parsed header differs");
+            assert_eq!(parsed_payload, payload, "the parsed payload differs");
+        }
+        Err(ParseError::UnsupportedVersion(version)) => {
+            assert_eq!(version, header.version, "the parsed version differs");
+            let unversioned = Header {
+                version: 0,
+                ..*header
+            };
+            let (parsed, _) = DataFrame::new(&unversioned, payload)
+                .parse()
+                .expect("the frame should parse with version 0");
+            assert_eq!(parsed, unversioned, "the parsed header differs");
+        }
+        Err(e) => panic!("the header {:?} fails to parse: {}", header, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A xorshift generator, being enough for spreading inputs over the ranges
+    // of each field.
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_arbitrary_headers_round_trip() {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        let mut payload = [0; 127];
+        for _ in 0..10_000 {
+            let bits = xorshift(&mut state);
+            let len = (bits >> 32) as usize % (payload.len() + 1);
+            payload[..len].fill(bits as u8);
+            check_round_trip(&arbitrary_header(bits), &payload[..len]);
+        }
+    }
+
+    #[test]
+    fn test_every_header_field_round_trips() {
+        // Every combination of version, source, server address and port...
+        for bits in 0..1 << 13 {
+            let header = arbitrary_header(bits);
+            check_round_trip(&header, b"some data");
+            check_round_trip(
+                &Header {
+                    frame_counter: u16::MAX,
+                    ..header
+                },
+                &[],
+            );
+        }
+
+        // ...and every frame counter, with the other fields at their extremes.
+        for frame_counter in 0..=u16::MAX {
+            for bits in [0, 0x1FFF] {
+                let header = Header {
+                    frame_counter,
+                    ..arbitrary_header(bits)
+                };
+                check_round_trip(&header, &[]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_header_fields() {
+        let header = arbitrary_header(0xABCD_1FFE);
+        assert_eq!(header.version, 2);
+        assert_eq!(header.source, DataSource::Server);
+        assert_eq!(header.server_address.get(), 31);
+        assert_eq!(header.server_port.get(), 31);
+        assert_eq!(header.frame_counter, 0xABCD);
+    }
+
+    #[test]
+    #[should_panic(expected = "the parsed header differs")]
+    fn test_check_round_trip_detects_differences() {
+        // A header whose server address would be truncated by packing.
+        let header = Header {
+            server_address: ServerAddress(32),
+            ..arbitrary_header(0)
+        };
+        check_round_trip(&header, &[]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Lightweight profiling of the hot-path functions of this crate.
+//!
+//! When the `timing` feature is enabled, a user-provided hook is invoked at
+//! the start and end of each [Phase] of processing. The hook is a plain
+//! function so that it can be implemented with whatever is cheapest on the
+//! target e.g. reading a cycle counter. When the feature is disabled, no hook
+//! exists and the instrumentation compiles away entirely.
+
+#[cfg(feature = "timing")]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The phases of processing that are reported to a timing hook. The phases
+/// are the same whichever features are enabled, although sealing and opening
+/// are only reported with the `crypto` feature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// Parsing the header of a data frame.
+    Parse,
+    /// Sealing a payload.
+    Seal,
+    /// Opening a payload.
+    Open,
+}
+
+/// Whether a phase is starting or has ended.
+#[cfg(feature = "timing")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Start,
+    End,
+}
+
+/// A timing hook, called with the phase and whether it is starting or ending.
+#[cfg(feature = "timing")]
+pub type Hook = fn(Phase, Edge);
+
+#[cfg(feature = "timing")]
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Install a hook to be called around each phase of processing. Any
+/// previously installed hook is replaced.
+#[cfg(feature = "timing")]
+pub fn set_hook(hook: Hook) {
+    HOOK.store(hook as *mut (), Ordering::Relaxed);
+}
+
+/// Remove any installed hook.
+#[cfg(feature = "timing")]
+pub fn clear_hook() {
+    HOOK.store(core::ptr::null_mut(), Ordering::Relaxed);
+}
+
+#[cfg(feature = "timing")]
+fn mark(phase: Phase, edge: Edge) {
+    let hook = HOOK.load(Ordering::Relaxed);
+    if !hook.is_null() {
+        // Safety: the only non-null values ever stored are `Hook` function
+        // pointers, which are the same size as a data pointer.
+        let hook = unsafe { core::mem::transmute::<*mut (), Hook>(hook) };
+        hook(phase, edge);
+    }
+}
+
+/// Run a phase of processing, reporting its start and end to any hook.
+#[inline(always)]
+pub(crate) fn timed<R>(_phase: Phase, f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "timing")]
+    mark(_phase, Edge::Start);
+    let r = f();
+    #[cfg(feature = "timing")]
+    mark(_phase, Edge::End);
+    r
+}
+
+#[cfg(all(test, feature = "timing"))]
+mod tests {
+    use super::*;
+    use crate::{DataFrame, DataSource, Header, ServerAddress, ServerPort};
+    use std::{cell::RefCell, time::Instant, vec::Vec};
+
+    thread_local! {
+        static MARKS: RefCell<Vec<(Phase, Edge, Instant)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(phase: Phase, edge: Edge) {
+        MARKS.with(|m| m.borrow_mut().push((phase, edge, Instant::now())));
+    }
+
+    // Take the marks recorded, checking that each phase starts and then ends
+    // before the next one starts.
+    fn take_phases() -> Vec<Phase> {
+        let marks = MARKS.with(|m| m.take());
+        assert_eq!(marks.len() % 2, 0, "unpaired marks {:?}", marks);
+        marks
+            .chunks(2)
+            .map(|pair| match pair {
+                [(phase, Edge::Start, start), (end_phase, Edge::End, end)]
+                    if phase == end_phase && start <= end =>
+                {
+                    *phase
+                }
+                _ => panic!("unexpected marks {:?}", pair),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_phase_is_timed() {
+        set_hook(record);
+
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 3,
+        };
+        let payload = [0; 8];
+        let frame = DataFrame::new(&header, &payload);
+        for _ in 0..3 {
+            assert!(frame.parse().is_ok());
+        }
+
+        assert_eq!(take_phases(), [Phase::Parse; 3]);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_seal_and_open_phases_are_timed() {
+        use crate::crypto::{Opener, Sealer};
+
+        set_hook(record);
+
+        let key = b"0123456789ABCDEF";
+        let header = Header {
+            version: 0,
+            source: DataSource::Client,
+            server_address: ServerAddress::new_unchecked(1),
+            server_port: ServerPort::new_unchecked(2),
+            frame_counter: 3,
+        };
+        let nonce = header.nonce(&[0; 6]);
+        let mut encrypted_payload = [0; 16];
+        let len = Sealer::new(key)
+            .seal_next(&header, &nonce, b"hello", &mut encrypted_payload)
+            .unwrap();
+        let mut plaintext = [0; 16];
+        Opener::new(key)
+            .open(&header, &nonce, &encrypted_payload[..len], &mut plaintext)
+            .unwrap();
+        assert!(Opener::new(key)
+            .open(&header, &nonce, &plaintext[..len], &mut [0; 16])
+            .is_err());
+
+        assert_eq!(take_phases(), [Phase::Seal, Phase::Open, Phase::Open]);
+    }
+}